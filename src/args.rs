@@ -0,0 +1,141 @@
+use crate::scene::{Scene, Projection};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+// Settings from the command line. Those left as None keep the value from the
+// scene file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub scene: String,
+    pub output: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fov: Option<f64>,
+    pub samples: Option<u32>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            scene: "scenes/default.json".to_string(),
+            output: "test.png".to_string(),
+            width: None,
+            height: None,
+            fov: None,
+            samples: None,
+        }
+    }
+}
+
+impl Settings {
+    // Parses the arguments following the program name. A lone argument
+    // without a flag is taken as the scene file.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Settings, ArgsError> {
+        let mut settings = Settings::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| ArgsError::MissingValue(arg.clone()));
+            match arg.as_str() {
+                "--scene" => settings.scene = value()?,
+                "--output" => settings.output = value()?,
+                "--width" => settings.width = Some(parse_value(&arg, value()?)?),
+                "--height" => settings.height = Some(parse_value(&arg, value()?)?),
+                "--fov" => settings.fov = Some(parse_value(&arg, value()?)?),
+                "--samples" => settings.samples = Some(parse_value(&arg, value()?)?),
+                _ if arg.starts_with("--") => return Err(ArgsError::UnknownFlag(arg)),
+                _ => settings.scene = arg,
+            }
+        }
+        Ok(settings)
+    }
+
+    // Overrides the scene's own settings with any given on the command line.
+    pub fn apply(&self, scene: &mut Scene) {
+        if let Some(width) = self.width {
+            scene.width = width;
+        }
+        if let Some(height) = self.height {
+            scene.height = height;
+        }
+        if let Some(fov) = self.fov {
+            scene.camera.projection = Projection::Perspective { fov };
+        }
+        if let Some(samples) = self.samples {
+            scene.samples_per_pixel = samples;
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: String) -> Result<T, ArgsError> {
+    value.parse().map_err(|_| ArgsError::InvalidValue {
+        flag: flag.to_string(),
+        value,
+    })
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ArgsError {
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue { flag: String, value: String },
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArgsError::UnknownFlag(ref flag) => write!(f, "unknown option {}", flag),
+            ArgsError::MissingValue(ref flag) => write!(f, "{} needs a value", flag),
+            ArgsError::InvalidValue { ref flag, ref value } => {
+                write!(f, "invalid value for {}: {}", flag, value)
+            }
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+#[test]
+fn test_parse_args() {
+    let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+    let settings = Settings::parse(args(&["--scene", "room.json", "--width", "320",
+                                          "--height", "240", "--fov", "45.5",
+                                          "--samples", "16", "--output", "room.png"])).unwrap();
+    assert_eq!(settings, Settings {
+        scene: "room.json".to_string(),
+        output: "room.png".to_string(),
+        width: Some(320),
+        height: Some(240),
+        fov: Some(45.5),
+        samples: Some(16),
+    });
+
+    // No arguments keeps the defaults, and a bare argument names the scene.
+    assert_eq!(Settings::parse(args(&[])).unwrap(), Settings::default());
+    assert_eq!(Settings::parse(args(&["room.json"])).unwrap().scene, "room.json");
+
+    assert_eq!(Settings::parse(args(&["--depth", "3"])),
+               Err(ArgsError::UnknownFlag("--depth".to_string())));
+    assert_eq!(Settings::parse(args(&["--width"])),
+               Err(ArgsError::MissingValue("--width".to_string())));
+    assert_eq!(Settings::parse(args(&["--samples", "many"])),
+               Err(ArgsError::InvalidValue {
+                   flag: "--samples".to_string(),
+                   value: "many".to_string(),
+               }));
+}
+
+#[test]
+fn test_settings_override_scene() {
+    let settings = Settings {
+        width: Some(64),
+        fov: Some(30.0),
+        samples: Some(4),
+        ..Settings::default()
+    };
+    let mut scene = Scene::default();
+    settings.apply(&mut scene);
+    assert_eq!((scene.width, scene.height, scene.samples_per_pixel), (64, 600, 4));
+    assert!(matches!(scene.camera.projection, Projection::Perspective { fov } if fov == 30.0));
+}
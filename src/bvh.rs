@@ -0,0 +1,443 @@
+use crate::point::Point;
+use crate::vector::Vector3;
+use crate::math::Matrix4;
+use crate::render::{Ray, Intersectable};
+use crate::scene::{Element, Intersection, Sphere, Triangle, Disk, Quad, AxisAlignedBox, Cylinder, Cone, Torus,
+                   CsgOp};
+
+const MAX_LEAF_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl BoundingBox {
+    pub fn empty() -> BoundingBox {
+        BoundingBox {
+            min: Point::from_one(f64::INFINITY),
+            max: Point::from_one(f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: Point::new(self.min.x.min(other.min.x),
+                            self.min.y.min(other.min.y),
+                            self.min.z.min(other.min.z)),
+            max: Point::new(self.max.x.max(other.max.x),
+                            self.max.y.max(other.max.y),
+                            self.max.z.max(other.max.z)),
+        }
+    }
+
+    pub fn grow(&self, point: &Point) -> BoundingBox {
+        self.union(&BoundingBox {
+            min: *point,
+            max: *point,
+        })
+    }
+
+    pub fn centroid(&self) -> Point {
+        self.min.midpoint(&self.max)
+    }
+
+    // The box around all eight corners of this one after transforming them.
+    pub fn transformed(&self, matrix: &Matrix4) -> BoundingBox {
+        let mut bounds = BoundingBox::empty();
+        for i in 0..8 {
+            let corner = Point::new(if i & 1 == 0 { self.min.x } else { self.max.x },
+                                    if i & 2 == 0 { self.min.y } else { self.max.y },
+                                    if i & 4 == 0 { self.min.z } else { self.max.z });
+            bounds = bounds.grow(&matrix.transform_point(&corner));
+        }
+        bounds
+    }
+
+    // Index of the axis (0 = x, 1 = y, 2 = z) along which the box is longest.
+    pub fn longest_axis(&self) -> usize {
+        let extent = self.max - self.min;
+        if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    // Slab test. Returns the distance at which the ray enters the box (zero if
+    // it starts inside), or None if the box is missed or entirely behind it.
+    pub fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.intersect_interval(ray).map(|(enter, _)| enter)
+    }
+
+    // The distances at which the ray enters (zero if it starts inside) and
+    // leaves the box.
+    pub fn intersect_interval(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let mut t_min = 0.0f64;
+        let mut t_max = f64::INFINITY;
+
+        let slabs = [
+            (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+        ];
+        for &(origin, direction, min, max) in &slabs {
+            // A ray parallel to a slab can only hit the box if it starts
+            // between the slab's planes.
+            if direction == 0.0 && (origin < min || origin > max) {
+                return None;
+            }
+            let inv = 1.0 / direction;
+            let mut t0 = (min - origin) * inv;
+            let mut t1 = (max - origin) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+}
+
+pub trait Bounded {
+    fn bounding_box(&self) -> BoundingBox;
+}
+
+impl Bounded for Sphere {
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox {
+            min: Point::new(self.center.x - self.radius,
+                            self.center.y - self.radius,
+                            self.center.z - self.radius),
+            max: Point::new(self.center.x + self.radius,
+                            self.center.y + self.radius,
+                            self.center.z + self.radius),
+        }
+    }
+}
+
+impl Bounded for Triangle {
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::empty().grow(&self.a).grow(&self.b).grow(&self.c)
+    }
+}
+
+impl Bounded for Disk {
+    // Along each axis the rim extends radius * sin(angle between axis and normal).
+    fn bounding_box(&self) -> BoundingBox {
+        let n = self.normal.normalize();
+        let extent = |c: f64| self.radius * (1.0 - c * c).max(0.0).sqrt();
+        let (ex, ey, ez) = (extent(n.x), extent(n.y), extent(n.z));
+        BoundingBox {
+            min: Point::new(self.center.x - ex, self.center.y - ey, self.center.z - ez),
+            max: Point::new(self.center.x + ex, self.center.y + ey, self.center.z + ez),
+        }
+    }
+}
+
+impl Bounded for Quad {
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::empty()
+            .grow(&self.corner)
+            .grow(&(self.corner + self.edge_u))
+            .grow(&(self.corner + self.edge_v))
+            .grow(&(self.corner + self.edge_u + self.edge_v))
+    }
+}
+
+impl Bounded for AxisAlignedBox {
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox {
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
+
+impl Bounded for Cylinder {
+    // The bounds of the two end caps, each of which is a disk.
+    fn bounding_box(&self) -> BoundingBox {
+        let axis = self.axis.normalize();
+        let top = self.base + axis * self.height;
+        let extent = |c: f64| self.radius * (1.0 - c * c).max(0.0).sqrt();
+        let rim = Vector3::new(extent(axis.x), extent(axis.y), extent(axis.z));
+        BoundingBox::empty()
+            .grow(&(self.base - rim))
+            .grow(&(self.base + rim))
+            .grow(&(top - rim))
+            .grow(&(top + rim))
+    }
+}
+
+impl Bounded for Cone {
+    // The apex and the bounds of the base, which is a disk.
+    fn bounding_box(&self) -> BoundingBox {
+        let axis = self.axis.normalize();
+        let base = self.apex + axis * self.height;
+        let radius = self.height * self.half_angle.to_radians().tan();
+        let extent = |c: f64| radius * (1.0 - c * c).max(0.0).sqrt();
+        let rim = Vector3::new(extent(axis.x), extent(axis.y), extent(axis.z));
+        BoundingBox::empty()
+            .grow(&self.apex)
+            .grow(&(base - rim))
+            .grow(&(base + rim))
+    }
+}
+
+impl Bounded for Torus {
+    // The bounds of the circle through the middle of the tube, grown by the
+    // tube's radius.
+    fn bounding_box(&self) -> BoundingBox {
+        let axis = self.axis.normalize();
+        let extent = |c: f64| self.major_radius * (1.0 - c * c).max(0.0).sqrt() + self.minor_radius;
+        let half = Vector3::new(extent(axis.x), extent(axis.y), extent(axis.z));
+        BoundingBox {
+            min: self.center - half,
+            max: self.center + half,
+        }
+    }
+}
+
+impl Element {
+    // Planes are infinite and have no bounding box.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        match *self {
+            Element::Sphere(ref s) => Some(s.bounding_box()),
+            Element::Plane(_) => None,
+            Element::Triangle(ref t) => Some(t.bounding_box()),
+            Element::Disk(ref d) => Some(d.bounding_box()),
+            Element::Quad(ref q) => Some(q.bounding_box()),
+            Element::Box(ref b) => Some(b.bounding_box()),
+            Element::Cylinder(ref c) => Some(c.bounding_box()),
+            Element::Cone(ref c) => Some(c.bounding_box()),
+            Element::Torus(ref t) => Some(t.bounding_box()),
+            // Only a union extends beyond its left-hand child.
+            Element::Csg(ref c) => {
+                let left = c.left.bounding_box()?;
+                match c.op {
+                    CsgOp::Union => Some(left.union(&c.right.bounding_box()?)),
+                    CsgOp::Intersection | CsgOp::Difference => Some(left),
+                }
+            }
+            Element::Transformed(ref t) => Some(t.element.bounding_box()?.transformed(t.transform())),
+            Element::Group(ref g) => g.elements.iter()
+                .try_fold(BoundingBox::empty(), |acc, e| Some(acc.union(&e.bounding_box()?))),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum BvhNode {
+    Leaf {
+        bounds: BoundingBox,
+        first: usize,
+        count: usize,
+    },
+    Interior {
+        bounds: BoundingBox,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &BoundingBox {
+        match *self {
+            BvhNode::Leaf { ref bounds, .. } => bounds,
+            BvhNode::Interior { ref bounds, .. } => bounds,
+        }
+    }
+}
+
+// A bounding volume hierarchy over the indices of a scene's elements.
+// Unbounded elements (planes) can't be placed in the tree and are tested
+// against every ray instead.
+#[derive(Clone)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+    unbounded: Vec<usize>,
+    // How many elements the tree was built over.
+    element_count: usize,
+}
+
+impl Bvh {
+    pub fn new(elements: &[Element]) -> Bvh {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for (i, element) in elements.iter().enumerate() {
+            match element.bounding_box() {
+                Some(bounds) => bounded.push((i, bounds)),
+                None => unbounded.push(i),
+            }
+        }
+
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            indices: Vec::with_capacity(bounded.len()),
+            unbounded,
+            element_count: elements.len(),
+        };
+        if !bounded.is_empty() {
+            bvh.build(&mut bounded);
+        }
+        bvh
+    }
+
+    pub fn element_count(&self) -> usize {
+        self.element_count
+    }
+
+    // Recursively splits the elements at the median centroid along the longest
+    // axis, returning the index of the node created.
+    fn build(&mut self, elements: &mut [(usize, BoundingBox)]) -> usize {
+        let bounds = elements.iter()
+            .fold(BoundingBox::empty(), |acc, (_, b)| acc.union(b));
+
+        if elements.len() <= MAX_LEAF_SIZE {
+            let first = self.indices.len();
+            self.indices.extend(elements.iter().map(|&(i, _)| i));
+            self.nodes.push(BvhNode::Leaf {
+                bounds,
+                first,
+                count: elements.len(),
+            });
+            return self.nodes.len() - 1;
+        }
+
+        let centroid_bounds = elements.iter()
+            .fold(BoundingBox::empty(), |acc, (_, b)| acc.grow(&b.centroid()));
+        let axis = centroid_bounds.longest_axis();
+        let key = |b: &BoundingBox| {
+            let c = b.centroid();
+            match axis {
+                0 => c.x,
+                1 => c.y,
+                _ => c.z,
+            }
+        };
+        elements.sort_by(|a, b| key(&a.1).partial_cmp(&key(&b.1)).unwrap());
+
+        // Reserve this node's slot before its children are pushed.
+        let node = self.nodes.len();
+        self.nodes.push(BvhNode::Leaf {
+            bounds,
+            first: 0,
+            count: 0,
+        });
+        let (left_half, right_half) = elements.split_at_mut(elements.len() / 2);
+        let left = self.build(left_half);
+        let right = self.build(right_half);
+        self.nodes[node] = BvhNode::Interior { bounds, left, right };
+        node
+    }
+
+    // Adds the number of elements tested against the ray to `tests`.
+    pub fn trace<'a>(&self, elements: &'a [Element], ray: &Ray, t_min: f64,
+                     tests: &mut u64) -> Option<Intersection<'a>> {
+        // The nearest hit so far, as a distance and the index of the element.
+        let mut nearest: Option<(f64, usize)> = None;
+        let mut consider = |i: usize, nearest: &mut Option<(f64, usize)>| {
+            *tests += 1;
+            let hit = elements[i].intersect(ray, t_min).filter(|d| d.is_finite());
+            if let Some(distance) = hit {
+                if nearest.is_none_or(|(n, _)| distance < n) {
+                    *nearest = Some((distance, i));
+                }
+            }
+        };
+        let intersection = |nearest: Option<(f64, usize)>| {
+            nearest.and_then(|(distance, i)| Intersection::new(ray, distance, &elements[i]))
+        };
+
+        for &i in &self.unbounded {
+            consider(i, &mut nearest);
+        }
+
+        if self.nodes.is_empty() {
+            return intersection(nearest);
+        }
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            match node.bounds().intersect(ray) {
+                Some(entry) if nearest.is_none_or(|(n, _)| entry <= n) => {}
+                _ => continue,
+            }
+            match *node {
+                BvhNode::Leaf { first, count, .. } => {
+                    for &i in &self.indices[first..first + count] {
+                        consider(i, &mut nearest);
+                    }
+                }
+                BvhNode::Interior { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        intersection(nearest)
+    }
+
+    // Whether any element is hit between `t_min` and `t_max`, stopping at the
+    // first found. Adds the number of elements tested to `tests`.
+    pub fn occluded(&self, elements: &[Element], ray: &Ray, t_min: f64, t_max: f64,
+                    tests: &mut u64) -> bool {
+        let mut hits = |i: &usize| {
+            *tests += 1;
+            elements[*i].intersect(ray, t_min).is_some_and(|d| d < t_max)
+        };
+        if self.unbounded.iter().any(&mut hits) {
+            return true;
+        }
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            match node.bounds().intersect(ray) {
+                Some(entry) if entry < t_max => {}
+                _ => continue,
+            }
+            match *node {
+                BvhNode::Leaf { first, count, .. } => {
+                    if self.indices[first..first + count].iter().any(&mut hits) {
+                        return true;
+                    }
+                }
+                BvhNode::Interior { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        false
+    }
+}
+
+#[test]
+fn test_bounding_box_hit_and_miss() {
+    let bounds = BoundingBox {
+        min: Point::new(-1.0, -1.0, -6.0),
+        max: Point::new(1.0, 1.0, -4.0),
+    };
+    let hit = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(bounds.intersect(&hit), Some(4.0));
+
+    let miss = Ray {
+        origin: Point::new(2.0, 0.0, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(bounds.intersect(&miss), None);
+}
@@ -0,0 +1,143 @@
+use crate::vector::Vector3;
+use rayon::prelude::*;
+
+// Passes of the à-trous filter. Each doubles the spacing between the taps,
+// so three passes cover a 17 x 17 pixel neighbourhood.
+const PASSES: u32 = 3;
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+// Higher values make the filter less willing to mix pixels whose normals differ.
+const NORMAL_POWER: i32 = 64;
+// Relative difference in depth at which a neighbour's weight falls to 1/e.
+const DEPTH_SIGMA: f64 = 0.05;
+
+// What the camera sees through the centre of a pixel: the surface normal and
+// distance to the first hit, or None where the ray escapes the scene.
+#[derive(Debug, Clone, Copy)]
+pub struct Guide {
+    pub normal: Vector3,
+    pub depth: f64,
+}
+
+// How much a neighbour should contribute to a pixel's filtered colour, from
+// 1.0 for the same surface down to 0.0 across a geometric edge.
+fn similarity(a: &Option<Guide>, b: &Option<Guide>, step: u32) -> f32 {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let facing = a.normal.dot_prod(&b.normal).max(0.0).powi(NORMAL_POWER);
+            let relative = (a.depth - b.depth).abs() / (a.depth.max(1e-9) * step as f64);
+            (facing * (-relative / DEPTH_SIGMA).exp()) as f32
+        }
+        (None, None) => 1.0,
+        _ => 0.0,
+    }
+}
+
+// Edge-aware à-trous wavelet filter over linear RGB, three floats per pixel
+// in row-major order. Neighbours are weighted by the B3 spline kernel and by
+// how closely their guides match, so noise is smoothed within a surface but
+// not across the boundaries between surfaces.
+pub fn denoise(colour: &[f32], guides: &[Option<Guide>], width: u32, height: u32) -> Vec<f32> {
+    let (w, h) = (width as i64, height as i64);
+    let mut current = colour.to_vec();
+    for pass in 0..PASSES {
+        let step = 1u32 << pass;
+        let mut next = vec![0.0f32; current.len()];
+        // Rows read only from `current`, so each can be filtered on its own.
+        next.par_chunks_mut((w as usize * 3).max(1)).enumerate().for_each(|(y, row)| {
+            let y = y as i64;
+            for x in 0..w {
+                let i = (y * w + x) as usize;
+                let mut sum = [0.0f32; 3];
+                let mut total = 0.0f32;
+                for (ky, wy) in KERNEL.iter().enumerate() {
+                    for (kx, wx) in KERNEL.iter().enumerate() {
+                        let nx = x + (kx as i64 - 2) * step as i64;
+                        let ny = y + (ky as i64 - 2) * step as i64;
+                        if nx < 0 || ny < 0 || nx >= w || ny >= h {
+                            continue;
+                        }
+                        let j = (ny * w + nx) as usize;
+                        let weight = wx * wy * similarity(&guides[i], &guides[j], step);
+                        for c in 0..3 {
+                            sum[c] += current[j * 3 + c] * weight;
+                        }
+                        total += weight;
+                    }
+                }
+                // The centre tap always matches itself, so `total` is never zero.
+                for c in 0..3 {
+                    row[x as usize * 3 + c] = sum[c] / total;
+                }
+            }
+        });
+        current = next;
+    }
+    current
+}
+
+#[cfg(test)]
+fn test_image(width: u32, height: u32, pixel: impl Fn(u32, u32) -> (f32, Option<Guide>))
+              -> (Vec<f32>, Vec<Option<Guide>>) {
+    let mut colour = Vec::new();
+    let mut guides = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let (value, guide) = pixel(x, y);
+            colour.extend_from_slice(&[value, value, value]);
+            guides.push(guide);
+        }
+    }
+    (colour, guides)
+}
+
+#[cfg(test)]
+fn variance(values: &[f32]) -> f32 {
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    values.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / values.len() as f32
+}
+
+#[test]
+fn test_denoise_smooths_flat_region() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    let mut rng = StdRng::seed_from_u64(1);
+    let noise: Vec<f32> = (0..32 * 32).map(|_| rng.gen_range(-0.3..0.3)).collect();
+    let guide = Guide {
+        normal: Vector3::new(0.0, 0.0, 1.0),
+        depth: 5.0,
+    };
+    let (colour, guides) = test_image(32, 32, |x, y| (0.5 + noise[(y * 32 + x) as usize], Some(guide)));
+
+    let denoised = denoise(&colour, &guides, 32, 32);
+    assert!(variance(&denoised) < variance(&colour) / 10.0);
+}
+
+#[test]
+fn test_denoise_keeps_edges_sharp() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    // Two walls meeting at x = 16, one dark and one bright, both noisy.
+    let mut rng = StdRng::seed_from_u64(2);
+    let noise: Vec<f32> = (0..32 * 32).map(|_| rng.gen_range(-0.1..0.1)).collect();
+    let (colour, guides) = test_image(32, 32, |x, y| {
+        let (value, normal) = if x < 16 {
+            (0.2, Vector3::new(0.0, 0.0, 1.0))
+        } else {
+            (0.8, Vector3::new(1.0, 0.0, 0.0))
+        };
+        let guide = Guide {
+            normal,
+            depth: 5.0,
+        };
+        (value + noise[(y * 32 + x) as usize], Some(guide))
+    });
+
+    let denoised = denoise(&colour, &guides, 32, 32);
+    let step = |image: &[f32]| {
+        (0..32).map(|y| image[(y * 32 + 16) * 3] - image[(y * 32 + 15) * 3]).sum::<f32>() / 32.0
+    };
+    assert!((step(&denoised) - 0.6).abs() < 0.02, "step = {}", step(&denoised));
+    assert!((step(&denoised) - step(&colour)).abs() < 0.05);
+}
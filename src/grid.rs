@@ -0,0 +1,192 @@
+use crate::point::Point;
+use crate::vector::Vector3;
+use crate::bvh::BoundingBox;
+use crate::render::{Ray, Intersectable};
+use crate::scene::{Element, Intersection};
+
+// Roughly how many cells to create for each bounded element.
+const CELLS_PER_ELEMENT: f64 = 2.0;
+const MAX_RESOLUTION: usize = 128;
+
+// A uniform grid of voxels over the bounds of a scene's elements, each cell
+// listing the elements whose bounding boxes overlap it. Rays walk the cells in
+// order, so for evenly spread small objects only those near the ray are tested.
+// As with the BVH, unbounded elements (planes) are tested against every ray.
+#[derive(Clone)]
+pub struct UniformGrid {
+    bounds: BoundingBox,
+    resolution: [usize; 3],
+    cell_size: Vector3,
+    cells: Vec<Vec<usize>>,
+    unbounded: Vec<usize>,
+    // How many elements the grid was built over.
+    element_count: usize,
+}
+
+impl UniformGrid {
+    pub fn new(elements: &[Element]) -> UniformGrid {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for (i, element) in elements.iter().enumerate() {
+            match element.bounding_box() {
+                Some(bounds) => bounded.push((i, bounds)),
+                None => unbounded.push(i),
+            }
+        }
+
+        let bounds = bounded.iter()
+            .fold(BoundingBox::empty(), |acc, (_, b)| acc.union(b));
+        let mut grid = UniformGrid {
+            bounds,
+            resolution: [0; 3],
+            cell_size: Vector3::zero(),
+            cells: Vec::new(),
+            unbounded,
+            element_count: elements.len(),
+        };
+        if bounded.is_empty() {
+            return grid;
+        }
+
+        // Pad flat extents so every cell has some thickness.
+        let extent = bounds.max - bounds.min;
+        let padding = extent.max_component().max(1.0) * 1e-6;
+        let extent = Vector3::new(extent.x.max(padding), extent.y.max(padding), extent.z.max(padding));
+        grid.bounds.max = grid.bounds.min + extent;
+
+        // Cells are close to cubes, sized to give about CELLS_PER_ELEMENT each.
+        let volume = extent.x * extent.y * extent.z;
+        let cells_per_unit = (CELLS_PER_ELEMENT * bounded.len() as f64 / volume).cbrt();
+        let cells_along = |length: f64| ((length * cells_per_unit).round() as usize).clamp(1, MAX_RESOLUTION);
+        grid.resolution = [cells_along(extent.x), cells_along(extent.y), cells_along(extent.z)];
+        grid.cell_size = Vector3::new(extent.x / grid.resolution[0] as f64,
+                                      extent.y / grid.resolution[1] as f64,
+                                      extent.z / grid.resolution[2] as f64);
+        grid.cells = vec![Vec::new(); grid.resolution.iter().product()];
+
+        for (i, b) in bounded {
+            let (low, high) = (grid.cell_of(&b.min), grid.cell_of(&b.max));
+            for z in low[2]..=high[2] {
+                for y in low[1]..=high[1] {
+                    for x in low[0]..=high[0] {
+                        let cell = grid.index(&[x, y, z]);
+                        grid.cells[cell].push(i);
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    pub fn element_count(&self) -> usize {
+        self.element_count
+    }
+
+    // The cell containing `point`, clamped to the grid.
+    fn cell_of(&self, point: &Point) -> [usize; 3] {
+        let offset = *point - self.bounds.min;
+        let mut cell = [0; 3];
+        for (axis, c) in cell.iter_mut().enumerate() {
+            let i = (offset.component(axis) / self.cell_size.component(axis)).floor();
+            *c = (i.max(0.0) as usize).min(self.resolution[axis] - 1);
+        }
+        cell
+    }
+
+    fn index(&self, cell: &[usize; 3]) -> usize {
+        (cell[2] * self.resolution[1] + cell[1]) * self.resolution[0] + cell[0]
+    }
+
+    // Adds the number of elements tested against the ray to `tests`.
+    pub fn trace<'a>(&self, elements: &'a [Element], ray: &Ray, t_min: f64,
+                     tests: &mut u64) -> Option<Intersection<'a>> {
+        // The nearest hit so far, as a distance and the index of the element.
+        let mut nearest: Option<(f64, usize)> = None;
+        let mut consider = |i: usize, nearest: &mut Option<(f64, usize)>| {
+            *tests += 1;
+            let hit = elements[i].intersect(ray, t_min).filter(|d| d.is_finite());
+            if let Some(distance) = hit {
+                if nearest.is_none_or(|(n, _)| distance < n) {
+                    *nearest = Some((distance, i));
+                }
+            }
+        };
+
+        for &i in &self.unbounded {
+            consider(i, &mut nearest);
+        }
+        self.walk(ray, |cell, cell_exit| {
+            for &i in cell {
+                consider(i, &mut nearest);
+            }
+            // Any hit before the ray leaves this cell can't be beaten by
+            // elements in later cells.
+            nearest.is_some_and(|(n, _)| n <= cell_exit)
+        });
+        nearest.and_then(|(distance, i)| Intersection::new(ray, distance, &elements[i]))
+    }
+
+    // Whether any element is hit between `t_min` and `t_max`, stopping at the
+    // first found. Adds the number of elements tested to `tests`.
+    pub fn occluded(&self, elements: &[Element], ray: &Ray, t_min: f64, t_max: f64,
+                    tests: &mut u64) -> bool {
+        let mut hits = |i: &usize| {
+            *tests += 1;
+            elements[*i].intersect(ray, t_min).is_some_and(|d| d < t_max)
+        };
+        if self.unbounded.iter().any(&mut hits) {
+            return true;
+        }
+        let mut occluded = false;
+        self.walk(ray, |cell, cell_exit| {
+            occluded = cell.iter().any(&mut hits);
+            occluded || cell_exit >= t_max
+        });
+        occluded
+    }
+
+    // 3D-DDA: visits the cells the ray passes through in order, always
+    // crossing whichever boundary the ray reaches first. `visit` is given each
+    // cell's elements and the distance at which the ray leaves it, and returns
+    // true to stop.
+    fn walk<F: FnMut(&[usize], f64) -> bool>(&self, ray: &Ray, mut visit: F) {
+        let (enter, exit) = match self.bounds.intersect_interval(ray) {
+            Some(interval) if !self.cells.is_empty() => interval,
+            _ => return,
+        };
+
+        let mut cell = self.cell_of(&(ray.origin + ray.direction * enter));
+        let mut step = [0isize; 3];
+        let mut t_next = [f64::INFINITY; 3];
+        let mut t_delta = [f64::INFINITY; 3];
+        let (origin, min) = (Vector3::from(ray.origin), Vector3::from(self.bounds.min));
+        for axis in 0..3 {
+            let direction = ray.direction.component(axis);
+            if direction == 0.0 {
+                continue;
+            }
+            let size = self.cell_size.component(axis);
+            let boundary = if direction > 0.0 { cell[axis] + 1 } else { cell[axis] };
+            let position = min.component(axis) + boundary as f64 * size;
+            step[axis] = if direction > 0.0 { 1 } else { -1 };
+            t_next[axis] = (position - origin.component(axis)) / direction;
+            t_delta[axis] = size / direction.abs();
+        }
+
+        loop {
+            let axis = (0..3)
+                .min_by(|&a, &b| t_next[a].partial_cmp(&t_next[b]).unwrap())
+                .unwrap();
+            let cell_exit = t_next[axis];
+            if visit(&self.cells[self.index(&cell)], cell_exit) || cell_exit > exit {
+                break;
+            }
+            let next = cell[axis] as isize + step[axis];
+            if next < 0 || next as usize >= self.resolution[axis] {
+                break;
+            }
+            cell[axis] = next as usize;
+            t_next[axis] += t_delta[axis];
+        }
+    }
+}
@@ -1,241 +1,3213 @@
 pub mod point;
+pub mod math;
 pub mod vector;
 pub mod scene;
+pub mod bvh;
+pub mod grid;
+pub mod texture;
+pub mod sdf;
+pub mod denoise;
+pub mod stats;
+pub mod args;
 mod render;
 extern crate image;
+extern crate rayon;
+extern crate rand;
 
-use scene::{Scene, Colour, Sphere, Element, Plane, Intersection, DirectionalLight,
-    SphericalLight, Light};
+use scene::{Scene, Camera, Colour, Element, Intersection, Light, Material, MaterialKind, Channel,
+            RenderMode, LightSampling};
 use point::Point;
 use vector::Vector3;
 use render::{Ray, Intersectable};
-use image::{DynamicImage, GenericImage, ImageBuffer, Rgba, Pixel};
+use denoise::Guide;
+use math::saturate;
+use stats::{Counter, RenderStats, StatCounters};
+use image::{DynamicImage, ImageBuffer, ImageFormat, ImageResult, Rgb32FImage, Rgba, RgbaImage};
+use rayon::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 
 
 
+// The size in pixels of the image being rendered, which need not match the
+// scene's own `width` and `height`, and the camera it is seen through, which
+// need not be the scene's `camera`, along with any counters gathering the
+// work the render does.
+#[derive(Debug, Clone, Copy)]
+struct Resolution<'a> {
+    width: u32,
+    height: u32,
+    camera: Camera,
+    // The camera's view basis, worked out once rather than for every ray.
+    basis: (Vector3, Vector3, Vector3),
+    stats: Option<&'a Arc<StatCounters>>,
+}
+
+impl<'a> Resolution<'a> {
+    fn of(scene: &Scene) -> Resolution<'static> {
+        Resolution {
+            width: scene.width,
+            height: scene.height,
+            camera: scene.camera,
+            basis: scene.camera.basis(),
+            stats: None,
+        }
+    }
+
+    // The same image seen through another camera.
+    fn through(self, camera: Camera) -> Resolution<'a> {
+        Resolution {
+            camera,
+            basis: camera.basis(),
+            ..self
+        }
+    }
+
+    fn pixels(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    // A single tile covering the whole image.
+    fn whole(&self) -> Tile {
+        Tile {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    fn ray(&self, x: f64, y: f64, lens: (f64, f64)) -> Ray {
+        Ray::create_camera_ray_in(&self.camera, &self.basis, self.width, self.height, x, y, lens)
+    }
+}
+
+// Renders the scene to tightly packed RGBA8 bytes, four per pixel. Pixels are
+// stored row-major: the top row first, each row running left to right.
+pub fn render_to_buffer(scene: &Scene) -> Vec<u8> {
+    render_to_buffer_with_progress(scene, Resolution::of(scene), |_| {}, &AtomicBool::new(false))
+}
+
+fn render_to_buffer_with_progress(scene: &Scene, size: Resolution, on_progress: impl FnMut(f32),
+                                  cancel: &AtomicBool) -> Vec<u8> {
+    to_rgba_buffer(scene, &render_linear(scene, size, on_progress, cancel))
+}
+
+// Tone maps linear RGB floats, three per pixel, to RGBA8 bytes.
+fn to_rgba_buffer(scene: &Scene, linear: &[f32]) -> Vec<u8> {
+    let mut buffer = vec![0u8; linear.len() / 3 * 4];
+    buffer.par_chunks_mut(4)
+        .zip(linear.par_chunks(3))
+        .for_each(|(pixel, rgb)| {
+            let colour = Colour {
+                red: rgb[0],
+                green: rgb[1],
+                blue: rgb[2],
+            };
+            pixel.copy_from_slice(&to_rgba(&colour, scene).0);
+        });
+    buffer
+}
+
+// Renders the scene to linear RGB floats, three per pixel in the same
+// row-major order as `render_to_buffer`. No tone mapping, clamping or gamma
+// is applied, so values may exceed 1.0.
+fn render_linear(scene: &Scene, size: Resolution, on_progress: impl FnMut(f32),
+                 cancel: &AtomicBool) -> Vec<f32> {
+    let tile_size = scene.tile_size.max(1);
+    let tiles = tiles(size.width, size.height, tile_size, tile_size);
+    let colour = render_tiles(scene, size, &size.whole(), &tiles, on_progress, cancel);
+    // Denoising a cancelled render would smear the missing tiles into the rest.
+    if scene.denoise && !scene.render_mode.is_data() && !cancel.load(Ordering::Relaxed) {
+        denoise::denoise(&colour, &render_guides(scene, size), size.width, size.height)
+    } else {
+        colour
+    }
+}
+
+// The surface seen through the centre of each pixel, to guide denoising.
+fn render_guides(scene: &Scene, size: Resolution) -> Vec<Option<Guide>> {
+    (0..size.pixels())
+        .into_par_iter()
+        .map(|i| stats::gather(size.stats, || {
+            let (x, y) = ((i % size.width as usize) as f64, (i / size.width as usize) as f64);
+            let ray = size.ray(x + 0.5, y + 0.5, (0.0, 0.0));
+            scene.trace(&ray).map(|hit| Guide {
+                normal: hit.surface_normal,
+                depth: hit.distance,
+            })
+        }))
+        .collect()
+}
+
 pub fn render(scene: &Scene) -> DynamicImage {
-    let mut img = DynamicImage::new_rgb8(scene.width, scene.height);
-    let black = Rgba::from_channels(0, 0, 0, 255);
+    render_with_progress(scene, |_| {})
+}
+
+// Renders the view from one of the scene's named cameras, or returns None if
+// it has no camera of that name.
+pub fn render_from(scene: &Scene, camera_name: &str) -> Option<DynamicImage> {
+    let size = Resolution::of(scene).through(*scene.camera_named(camera_name)?);
+    let buffer = render_to_buffer_with_progress(scene, size, |_| {}, &AtomicBool::new(false));
+    Some(DynamicImage::ImageRgba8(ImageBuffer::from_raw(size.width, size.height, buffer).unwrap()))
+}
+
+// Renders at the given size instead of the scene's. The camera's field of
+// view still spans the shorter side of the image.
+pub fn render_at(scene: &Scene, width: u32, height: u32) -> DynamicImage {
+    let size = Resolution {
+        width,
+        height,
+        ..Resolution::of(scene)
+    };
+    let buffer = render_to_buffer_with_progress(scene, size, |_| {}, &AtomicBool::new(false));
+    DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, buffer).unwrap())
+}
+
+pub fn render_with_progress(scene: &Scene, on_progress: impl FnMut(f32)) -> DynamicImage {
+    let buffer = render_to_buffer_with_progress(scene, Resolution::of(scene), on_progress,
+                                                &AtomicBool::new(false));
+    DynamicImage::ImageRgba8(ImageBuffer::from_raw(scene.width, scene.height, buffer).unwrap())
+}
+
+// Renders the scene, stopping early if `should_cancel` becomes true. Tiles
+// already started are finished, but no more are begun, and those never
+// rendered are left black.
+pub fn render_cancellable(scene: &Scene, should_cancel: &AtomicBool) -> DynamicImage {
+    let buffer = render_to_buffer_with_progress(scene, Resolution::of(scene), |_| {}, should_cancel);
+    DynamicImage::ImageRgba8(ImageBuffer::from_raw(scene.width, scene.height, buffer).unwrap())
+}
+
+// Renders the scene as `render` does, counting the rays cast and the
+// intersection tests made along the way.
+pub fn render_with_stats(scene: &Scene) -> (DynamicImage, RenderStats) {
+    let counters = Arc::new(StatCounters::default());
+    let size = Resolution {
+        stats: Some(&counters),
+        ..Resolution::of(scene)
+    };
+    let buffer = render_to_buffer_with_progress(scene, size, |_| {}, &AtomicBool::new(false));
+    let img = DynamicImage::ImageRgba8(ImageBuffer::from_raw(size.width, size.height, buffer).unwrap());
+    (img, counters.totals())
+}
+
+pub fn render_hdr(scene: &Scene) -> Rgb32FImage {
+    let buffer = render_linear(scene, Resolution::of(scene), |_| {}, &AtomicBool::new(false));
+    ImageBuffer::from_raw(scene.width, scene.height, buffer).unwrap()
+}
+
+// Writes a linear render as a 32-bit float OpenEXR file, whatever the
+// extension of `path`.
+pub fn save_hdr(img: &Rgb32FImage, path: &str) -> ImageResult<()> {
+    img.save_with_format(path, ImageFormat::OpenExr)
+}
+
+// Renders `frames` images of the scene into `out_dir` as frame_0000.png,
+// frame_0001.png and so on, creating the directory if needed. Each frame's
+// camera comes from `camera_path`, which is given the fraction of the way
+// through the animation: 0.0 for the first frame and 1.0 for the last.
+pub fn render_animation(base_scene: &Scene, frames: u32, camera_path: impl Fn(f32) -> Camera,
+                        out_dir: &str) -> ImageResult<()> {
+    std::fs::create_dir_all(out_dir)?;
+    let mut scene = base_scene.clone();
+    for frame in 0..frames {
+        let t = if frames > 1 {
+            frame as f32 / (frames - 1) as f32
+        } else {
+            0.0
+        };
+        scene.camera = camera_path(t);
+        let path = std::path::Path::new(out_dir).join(format!("frame_{:04}.png", frame));
+        render(&scene).save(path)?;
+    }
+    Ok(())
+}
+
+// A rectangular block of pixels rendered as one unit of work.
+#[derive(Debug, Clone, Copy)]
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+// Covers the image in tiles, clipping those along the right and bottom edges.
+fn tiles(width: u32, height: u32, tile_width: u32, tile_height: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(tile_height as usize) {
+        for x in (0..width).step_by(tile_width as usize) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: tile_width.min(width - x),
+                height: tile_height.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+// Renders only the pixels from (x0, y0) up to but not including (x1, y1),
+// clipped to the image. Rays are cast as for the full image, and with
+// `rng_seed` set each pixel draws the same random samples, so regions rendered
+// separately tile together into exactly the image `render` gives.
+//
+// The exception is denoising, which needs the whole image: it is skipped, so
+// for a scene with `denoise` set the regions are noisier than `render`'s image
+// and do not match it.
+pub fn render_region(scene: &Scene, x0: u32, y0: u32, x1: u32, y1: u32) -> RgbaImage {
+    let size = Resolution::of(scene);
+    let (x1, y1) = (x1.min(size.width), y1.min(size.height));
+    let region = Tile {
+        x: x0.min(x1),
+        y: y0.min(y1),
+        width: x1 - x0.min(x1),
+        height: y1 - y0.min(y1),
+    };
+    let tile_size = scene.tile_size.max(1);
+    let tiles: Vec<_> = tiles(region.width, region.height, tile_size, tile_size)
+        .into_iter()
+        .map(|tile| Tile {
+            x: region.x + tile.x,
+            y: region.y + tile.y,
+            ..tile
+        })
+        .collect();
+    let linear = render_tiles(scene, size, &region, &tiles, |_| {}, &AtomicBool::new(false));
+    ImageBuffer::from_raw(region.width, region.height, to_rgba_buffer(scene, &linear)).unwrap()
+}
+
+// Renders each scene as `render` does, returning the images in the same order
+// as `scenes`. The scenes share rayon's pool, so several small ones render at
+// once rather than each waiting on its own threads.
+pub fn render_batch(scenes: &[Scene]) -> Vec<DynamicImage> {
+    scenes.par_iter().map(render).collect()
+}
+
+// Tiles are handed out in order from a shared counter to the calling thread
+// and to workers spawned on the current rayon pool (the one `install` was
+// called on, or the global pool), so several renders in one pool share its
+// threads. Each tile renders into its own buffer. Between its own tiles the
+// calling thread copies finished ones into the image and reports the fraction
+// of tiles done to `on_progress`. Once `cancel` is set, tiles not yet started
+// are skipped. The image returned covers just `region` of the full `size`,
+// which must contain every tile.
+fn render_tiles(scene: &Scene, size: Resolution, region: &Tile, tiles: &[Tile],
+                mut on_progress: impl FnMut(f32), cancel: &AtomicBool) -> Vec<f32> {
+    let row_length = region.width as usize * 3;
+    let mut buffer = vec![0.0f32; region.width as usize * region.height as usize * 3];
+    let mut copy_tile = |tile: &Tile, pixels: &[f32]| {
+        let tile_row_length = tile.width as usize * 3;
+        for (row, tile_row) in pixels.chunks(tile_row_length).enumerate() {
+            let start = ((tile.y - region.y) as usize + row) * row_length +
+                        (tile.x - region.x) as usize * 3;
+            buffer[start..start + tile_row_length].copy_from_slice(tile_row);
+        }
+    };
+
+    let next = AtomicUsize::new(0);
+    let finished = AtomicUsize::new(0);
+    // Renders the next unclaimed tile, returning false once none are left.
+    let render_next = |sender: &mpsc::Sender<(Tile, Vec<f32>)>| {
+        let tile = match tiles.get(next.fetch_add(1, Ordering::Relaxed)) {
+            Some(tile) => tile,
+            None => return false,
+        };
+        if !cancel.load(Ordering::Relaxed) {
+            sender.send((*tile, render_tile(scene, size, tile))).unwrap();
+            finished.fetch_add(1, Ordering::Release);
+        }
+        true
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let mut reported = 0;
+    let mut report = |on_progress: &mut dyn FnMut(f32)| {
+        let done = finished.load(Ordering::Acquire);
+        if done > reported {
+            reported = done;
+            on_progress(done as f32 / tiles.len() as f32);
+        }
+    };
+    rayon::in_place_scope(|s| {
+        for _ in 1..rayon::current_num_threads() {
+            let sender = sender.clone();
+            let render_next = &render_next;
+            s.spawn(move |_| while render_next(&sender) {});
+        }
+        while render_next(&sender) {
+            for (tile, pixels) in receiver.try_iter() {
+                copy_tile(&tile, &pixels);
+            }
+            report(&mut on_progress);
+        }
+    });
+    // The scope has waited for the workers, so every tile has been sent.
+    for (tile, pixels) in receiver.try_iter() {
+        copy_tile(&tile, &pixels);
+    }
+    report(&mut on_progress);
+
+    buffer
+}
+
+fn render_tile(scene: &Scene, size: Resolution, tile: &Tile) -> Vec<f32> {
+    stats::gather(size.stats, || {
+        let mut pixels = Vec::with_capacity(tile.width as usize * tile.height as usize * 3);
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                let colour = sample_pixel(scene, size, x, y).0;
+                pixels.extend_from_slice(&[colour.red, colour.green, colour.blue]);
+            }
+        }
+        pixels
+    })
+}
+
+// Returns the pixel's colour and the number of rays it took. With adaptive
+// sampling on (`max_samples` above `samples_per_pixel`), pixels whose samples
+// still disagree after the first batch keep sampling until their variance
+// drops to `variance_threshold` or `max_samples` is reached.
+fn sample_pixel(scene: &Scene, size: Resolution, x: u32, y: u32) -> (Colour, u32) {
+    let mut rng = pixel_rng(scene, x, y);
+    let adaptive = scene.max_samples > scene.samples_per_pixel;
+    if (scene.samples_per_pixel <= 1 && !adaptive) || scene.render_mode.is_data() {
+        let ray = size.ray(x as f64 + 0.5, y as f64 + 0.5, (0.0, 0.0));
+        return (cast_primary_ray(scene, &size.camera, &ray, &mut rng), 1);
+    }
+    // Variance needs at least two samples.
+    let initial = if adaptive {
+        scene.samples_per_pixel.max(2)
+    } else {
+        scene.samples_per_pixel
+    };
+
+    let mut total = Colour::black();
+    let mut total_squares = Colour::black();
+    let mut count = 0;
+    while count < initial ||
+          (count < scene.max_samples &&
+           sample_variance(&total, &total_squares, count) > scene.variance_threshold) {
+        let (dx, dy) = pixel_offset(count, scene.samples_per_pixel, &mut rng);
+        let ray = size.ray(x as f64 + dx, y as f64 + dy, random_in_unit_disk(&mut rng));
+        let colour = cast_primary_ray(scene, &size.camera, &ray, &mut rng);
+        total = total + colour;
+        total_squares = total_squares + &colour * &colour;
+        count += 1;
+    }
+    (total * (1.0 / count as f32), count)
+}
+
+// Where within the pixel to place sample number `sample` of the first
+// `samples`. Stratified: the pixel is split into an n x n grid, for the
+// largest n with n² <= samples, and the first n² samples each fall at a random
+// point in a different cell. Any further samples are placed anywhere.
+fn pixel_offset<R: Rng>(sample: u32, samples: u32, rng: &mut R) -> (f64, f64) {
+    let n = (samples as f64).sqrt().floor() as u32;
+    if sample >= n * n {
+        return (rng.gen(), rng.gen());
+    }
+    let (column, row) = (sample % n, sample / n);
+    ((column as f64 + rng.gen::<f64>()) / n as f64,
+     (row as f64 + rng.gen::<f64>()) / n as f64)
+}
+
+// Random numbers for sampling one pixel. With a seed set, each pixel gets its
+// own stream derived from the seed and its position, so the result doesn't
+// depend on which thread renders it or in what order.
+fn pixel_rng(scene: &Scene, x: u32, y: u32) -> StdRng {
+    match scene.rng_seed {
+        Some(seed) => {
+            let position = ((y as u64) << 32) | x as u64;
+            StdRng::seed_from_u64(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ position)
+        }
+        None => StdRng::from_rng(rand::thread_rng()).unwrap(),
+    }
+}
+
+fn random_in_unit_disk<R: Rng>(rng: &mut R) -> (f64, f64) {
+    loop {
+        let (u, v) = (rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0));
+        if u * u + v * v <= 1.0 {
+            return (u, v);
+        }
+    }
+}
+
+// Unbiased variance of `count` samples from their sum and sum of squares,
+// averaged over the three channels.
+fn sample_variance(total: &Colour, total_squares: &Colour, count: u32) -> f32 {
+    let n = count as f32;
+    let channel = |sum: f32, sum_squares: f32| (sum_squares - sum * sum / n) / (n - 1.0);
+    (channel(total.red, total_squares.red) +
+     channel(total.green, total_squares.green) +
+     channel(total.blue, total_squares.blue)) / 3.0
+}
+
+fn to_rgba(colour: &Colour, scene: &Scene) -> Rgba<u8> {
+    if scene.render_mode.is_data() {
+        let encode = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        return Rgba([encode(colour.red), encode(colour.green), encode(colour.blue), 255]);
+    }
+    let mut colour = scene.tone_map.apply(&(*colour * 2f32.powf(scene.exposure))).clamp();
+    if scene.grayscale {
+        colour = Colour::from_one(colour.luminance());
+    }
+    if scene.srgb_output {
+        let colour = colour.linear_to_srgb();
+        let encode = |c: f32| (c * 255.0).round() as u8;
+        return Rgba([encode(colour.red), encode(colour.green), encode(colour.blue), 255]);
+    }
+    let encode = |c: f32| (c.powf(1.0 / scene.gamma) * 255.0).round() as u8;
+    Rgba([encode(colour.red), encode(colour.green), encode(colour.blue), 255])
+}
+
+#[test]
+fn test_wireframe_draws_triangle_edges() {
+    use scene::{Background, Triangle, Transformed, Group};
+    use math::Matrix4;
+
+    let scene = Scene {
+        width: 64,
+        height: 64,
+        elements: vec![Element::Triangle(Triangle {
+            a: Point::new(-2.0, -2.0, -5.0),
+            b: Point::new(2.0, -2.0, -5.0),
+            c: Point::new(0.0, 2.0, -5.0),
+            material: Material::diffuse(Colour::from_one(0.5), 0.18),
+            normals: None,
+        })],
+        background: Background::Solid(Colour::from_one(0.25)),
+        render_mode: RenderMode::Wireframe,
+        ..Scene::default()
+    };
+    let img = render(&scene).to_rgba8();
+    let background = img.get_pixel(0, 0).0;
+    let white = [255, 255, 255, 255];
+
+    // Across the middle row: background, the left edge, the inside of the
+    // triangle as background, the right edge, and background again.
+    let row: Vec<_> = (0..64).map(|x| img.get_pixel(x, 32).0).collect();
+    assert!(row.iter().all(|&p| p == background || p == white));
+    let first = row.iter().position(|&p| p == white).unwrap();
+    let last = row.iter().rposition(|&p| p == white).unwrap();
+    assert!(first > 0 && last < 63);
+    assert_eq!(row[32], background);
+    assert!(row[first..32].contains(&background) && row[32..last].contains(&background));
+
+    // The same triangle moved into place by a transform inside a group draws
+    // the same edges.
+    let triangle = Element::Triangle(Triangle {
+        a: Point::new(-2.0, -2.0, 0.0),
+        b: Point::new(2.0, -2.0, 0.0),
+        c: Point::new(0.0, 2.0, 0.0),
+        material: Material::diffuse(Colour::from_one(0.5), 0.18),
+        normals: None,
+    });
+    let moved = Transformed::new(triangle, Matrix4::translate(&Vector3::new(0.0, 0.0, -5.0))).unwrap();
+    let grouped = Scene {
+        elements: vec![Element::Group(Group::new(vec![Element::Transformed(moved)], None).unwrap())],
+        ..scene
+    };
+    assert_eq!(render(&grouped).to_rgba8(), img);
+}
+
+#[test]
+fn test_focus_peaking_marks_the_plane_of_focus() {
+    use scene::Plane;
+
+    // A wall facing the camera at `z`, lit only by the ambient light.
+    let scene_with_wall = |z: f64| Scene {
+        width: 8,
+        height: 8,
+        camera: Camera {
+            aperture: 0.2,
+            focus_distance: 5.0,
+            ..Camera::default()
+        },
+        elements: vec![Element::Plane(Plane {
+            origin: Point::new(0.0, 0.0, z),
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            material: Material::diffuse(Colour::from_one(1.0), 0.5),
+        })],
+        ambient: Colour::from_one(1.0),
+        render_mode: RenderMode::FocusPeaking,
+        rng_seed: Some(5),
+        ..Scene::default()
+    };
+    let marked = |z: f64| {
+        let img = render(&scene_with_wall(z)).to_rgba8();
+        img.pixels().filter(|p| p.0 == [0, 255, 0, 255]).count()
+    };
+    assert_eq!(marked(-5.0), 64);
+    assert_eq!(marked(-5.05), 64);
+    assert_eq!(marked(-2.0), 0);
+    assert_eq!(marked(-12.0), 0);
+
+    // Unmarked surfaces are shaded as usual.
+    let behind = scene_with_wall(-12.0);
+    let usual = Scene {
+        render_mode: RenderMode::Raytrace,
+        ..behind.clone()
+    };
+    assert_eq!(render_to_buffer(&behind), render_to_buffer(&usual));
+}
+
+#[test]
+fn test_render_stats_count_primary_rays() {
+    use scene::{Element, Sphere};
+
+    let scene = Scene {
+        width: 16,
+        height: 12,
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: Material::diffuse(Colour::black(), 0.18),
+        })],
+        ..Scene::default()
+    };
+    let (img, stats) = render_with_stats(&scene);
+    assert_eq!((img.width(), img.height()), (16, 12));
+    assert_eq!(stats.primary_rays, 16 * 12);
+    assert_eq!((stats.shadow_rays, stats.reflection_rays), (0, 0));
+    assert_eq!(stats.intersection_tests, 16 * 12);
+    assert!(scene.stats.is_none());
+}
+
+#[test]
+fn test_render_from_named_cameras() {
+    use scene::{Element, Sphere, SceneBuilder, SphericalLight, Attenuation};
+
+    let camera_at = |x: f64| Camera {
+        position: Point::new(x, 0.0, 0.0),
+        look_at: Point::new(0.0, 0.0, -5.0),
+        ..Camera::default()
+    };
+    let scene = SceneBuilder::new()
+        .width(24)
+        .height(16)
+        .add_element(Element::Sphere(Sphere {
+            center: Point::new(1.0, 0.0, -5.0),
+            radius: 1.0,
+            material: Material::diffuse(Colour::from_one(1.0), 0.18),
+        }))
+        .add_light(Light::Spherical(SphericalLight {
+            position: Point::new(0.0, 5.0, 0.0),
+            colour: Colour::from_one(1.0),
+            intensity: 500.0,
+            radius: 0.0,
+            attenuation: Attenuation::InverseSquare,
+            name: String::new(),
+            enabled: true,
+        }))
+        .add_camera("left", camera_at(-4.0))
+        .add_camera("right", camera_at(4.0))
+        .build();
+
+    let left = render_from(&scene, "left").unwrap();
+    let right = render_from(&scene, "right").unwrap();
+    assert_eq!((left.width(), left.height()), (24, 16));
+    assert_ne!(left.to_rgba8().into_raw(), right.to_rgba8().into_raw());
+    assert!(render_from(&scene, "top").is_none());
+
+    let moved = Scene {
+        camera: camera_at(-4.0),
+        ..scene.clone()
+    };
+    assert_eq!(left.to_rgba8(), render(&moved).to_rgba8());
+}
+
+#[test]
+fn test_render_batch_keeps_input_order() {
+    use scene::{Background, SceneBuilder};
+
+    // Empty scenes of different sizes, each filled with its own background.
+    let scenes: Vec<Scene> = [(8, 4, 0.2), (5, 6, 0.5), (3, 3, 1.0)]
+        .iter()
+        .map(|&(width, height, grey)| SceneBuilder::new()
+            .width(width)
+            .height(height)
+            .background(Background::Solid(Colour::from_one(grey)))
+            .build())
+        .collect();
+
+    let images = render_batch(&scenes);
+    assert_eq!(images.len(), scenes.len());
+    for (scene, img) in scenes.iter().zip(&images) {
+        assert_eq!((img.width(), img.height()), (scene.width, scene.height));
+        assert_eq!(img.to_rgba8().into_raw(), render(scene).to_rgba8().into_raw());
+    }
+    let corners: Vec<_> = images.iter().map(|img| img.to_rgba8().get_pixel(0, 0).0).collect();
+    assert!(corners[0] != corners[1] && corners[1] != corners[2]);
+}
+
+#[test]
+fn test_disabled_light_goes_dark() {
+    use scene::{Sphere, SphericalLight, Attenuation};
+
+    let mut scene = Scene {
+        width: 16,
+        height: 16,
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 2.0,
+            material: Material::diffuse(Colour::from_one(1.0), 0.5),
+        })],
+        light: vec![Light::Spherical(SphericalLight {
+            position: Point::new(0.0, 5.0, 0.0),
+            colour: Colour::from_one(1.0),
+            intensity: 5000.0,
+            radius: 0.0,
+            attenuation: Attenuation::InverseSquare,
+            name: "key".to_string(),
+            enabled: true,
+        })],
+        ..Scene::default()
+    };
+    let lit = render_to_buffer(&scene);
+    assert!(lit.chunks(4).any(|p| p[0] > 0));
+    assert!(scene.light_by_name("key").is_some_and(|light| light.enabled()));
+
+    assert!(scene.set_light_enabled("key", false));
+    assert!(render_to_buffer(&scene).chunks(4).all(|p| p == [0, 0, 0, 255]));
+
+    assert!(scene.set_light_enabled("key", true));
+    assert_eq!(render_to_buffer(&scene), lit);
+    assert!(!scene.set_light_enabled("fill", false));
+    assert!(scene.light_by_name("fill").is_none());
+
+    // Lights left unnamed can't be picked out by the empty name.
+    scene.light.push(Light::Spherical(SphericalLight {
+        position: Point::new(0.0, 5.0, 0.0),
+        colour: Colour::from_one(1.0),
+        intensity: 5000.0,
+        radius: 0.0,
+        attenuation: Attenuation::InverseSquare,
+        name: String::new(),
+        enabled: true,
+    }));
+    assert!(!scene.set_light_enabled("", false));
+    assert!(scene.light_by_name("").is_none());
+    assert!(scene.light[1].enabled());
+}
+
+#[test]
+fn test_gamma_correction_brightens_mid_grey() {
+    let grey = Colour {
+        red: 0.5,
+        green: 0.5,
+        blue: 0.5,
+    };
+
+    let linear = to_rgba(&grey, &Scene {
+        gamma: 1.0,
+        ..Scene::default()
+    });
+    // Rounded to the nearest step, as sRGB and data output are.
+    assert_eq!(linear.0[0], 128);
+
+    let corrected = to_rgba(&grey, &Scene {
+        gamma: 2.2,
+        ..Scene::default()
+    });
+    assert!((corrected.0[0] as i32 - 188).abs() <= 3);
+    assert_eq!(corrected.0[0], corrected.0[1]);
+    assert_eq!(corrected.0[0], corrected.0[2]);
+}
+
+#[test]
+fn test_srgb_output_encoding() {
+    let dark = Colour {
+        red: 0.002,
+        green: 0.5,
+        blue: 1.0,
+    };
+    let encoded = to_rgba(&dark, &Scene {
+        srgb_output: true,
+        ..Scene::default()
+    });
+    // The linear segment near black, then the power curve.
+    assert_eq!(encoded.0[0], (0.002f32 * 12.92 * 255.0).round() as u8);
+    assert!((encoded.0[1] as i32 - 188).abs() <= 1);
+    assert_eq!(encoded.0[2], 255);
+}
+
+#[test]
+fn test_grayscale_output() {
+    let green = Colour {
+        red: 0.0,
+        green: 1.0,
+        blue: 0.0,
+    };
+    assert!((green.luminance() - 0.7152).abs() < 1e-6);
+
+    let scene = Scene {
+        gamma: 1.0,
+        grayscale: true,
+        ..Scene::default()
+    };
+    let expected = (0.7152f32 * 255.0) as u8;
+    assert_eq!(to_rgba(&green, &scene).0, [expected, expected, expected, 255]);
+
+    // Grey is already its own luminance.
+    let grey = Colour::from_one(0.4);
+    assert_eq!(to_rgba(&grey, &scene), to_rgba(&grey, &Scene {
+        gamma: 1.0,
+        ..Scene::default()
+    }));
+}
+
+#[test]
+fn test_exposure_scales_in_stops() {
+    let grey = Colour {
+        red: 0.25,
+        green: 0.25,
+        blue: 0.25,
+    };
+    let scene_with = |exposure: f32| Scene {
+        gamma: 1.0,
+        exposure,
+        ..Scene::default()
+    };
+
+    let base = to_rgba(&grey, &scene_with(0.0)).0[0] as i32;
+    let brighter = to_rgba(&grey, &scene_with(1.0)).0[0] as i32;
+    let darker = to_rgba(&grey, &scene_with(-1.0)).0[0] as i32;
+    assert_eq!(base, 64);
+    assert!((brighter - 2 * base).abs() <= 1);
+    assert!((darker - base / 2).abs() <= 1);
+}
+
+#[test]
+fn test_reinhard_tone_map_keeps_highlights() {
+    use scene::ToneMap;
+
+    let bright = Colour {
+        red: 4.0,
+        green: 2.0,
+        blue: 0.5,
+    };
+
+    let clamped = to_rgba(&bright, &Scene {
+        tone_map: ToneMap::Clamp,
+        ..Scene::default()
+    });
+    assert_eq!((clamped.0[0], clamped.0[1]), (255, 255));
+
+    let mapped = to_rgba(&bright, &Scene {
+        tone_map: ToneMap::Reinhard,
+        ..Scene::default()
+    });
+    assert!(mapped.0[0] < 255 && mapped.0[1] < 255 && mapped.0[2] < 255);
+    assert!(mapped.0[0] > mapped.0[1]);
+    assert!(mapped.0[1] > mapped.0[2]);
+}
+
+#[test]
+fn test_can_render_scene() {
+    use scene::{Element, Sphere, Material};
+
+    let scene = Scene {
+        width: 800,
+        height: 600,
+        elements: vec![Element::Sphere(Sphere {
+            center: Point {
+                x: 0.0,
+                y: 0.0,
+                z: -5.0,
+            },
+            radius: 5.0,
+            material: Material::diffuse(Colour {
+                red: 0.4,
+                green: 1.0,
+                blue: 0.4,
+            }, 0.18),
+        })],
+        light: Vec::new(),
+        ..Scene::default()
+    };
+
+
+    let img: DynamicImage = render(&scene);
+    assert_eq!(scene.width, img.width());
+    assert_eq!(scene.height, img.height());
+}
+
+#[test]
+fn test_scene_builder_renders() {
+    use scene::{SceneBuilder, Element, Sphere, Material};
+
+    let scene = SceneBuilder::new()
+        .width(16)
+        .height(12)
+        .fov(60.0)
+        .add_element(Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: Material::diffuse(Colour {
+                red: 0.4,
+                green: 1.0,
+                blue: 0.4,
+            }, 0.18),
+        }))
+        .build();
+    assert!(scene.bvh.is_some());
+
+    let img = render(&scene);
+    assert_eq!((img.width(), img.height()), (16, 12));
+    assert!(scene.trace(&Ray::create_prime_ray(8, 6, &scene)).is_some());
+}
+
+#[test]
+fn test_render_at_overrides_dimensions() {
+    use scene::{Element, Sphere, Material};
+
+    let scene = Scene {
+        width: 40,
+        height: 30,
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: Material::diffuse(Colour {
+                red: 0.4,
+                green: 1.0,
+                blue: 0.4,
+            }, 0.18),
+        })],
+        ambient: Colour {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        },
+        ..Scene::default()
+    };
+
+    let small = render_at(&scene, 400, 300).to_rgba8();
+    let large = render_at(&scene, 800, 600).to_rgba8();
+    assert_eq!(small.dimensions(), (400, 300));
+    assert_eq!(large.dimensions(), (800, 600));
+    // The sphere stays centred at the same angular size.
+    assert_eq!(small.get_pixel(200, 150), large.get_pixel(400, 300));
+    assert_eq!(small.get_pixel(5, 5), large.get_pixel(10, 10));
+}
+
+#[test]
+fn test_rendered_pixels_are_opaque() {
+    use scene::{Element, Sphere, Material};
+
+    let scene = Scene {
+        width: 20,
+        height: 20,
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: Material::diffuse(Colour {
+                red: 0.4,
+                green: 1.0,
+                blue: 0.4,
+            }, 0.18),
+        })],
+        ..Scene::default()
+    };
+
+    let img = render(&scene).to_rgba8();
+    // The centre pixel hits the sphere, the corner misses everything.
+    assert_eq!(img.get_pixel(10, 10).0[3], 255);
+    assert_eq!(img.get_pixel(0, 0).0[3], 255);
+}
+
+#[test]
+fn test_render_to_buffer_matches_image() {
+    use scene::{Element, Sphere, Material};
+
+    let scene = Scene {
+        width: 24,
+        height: 16,
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: Material::diffuse(Colour {
+                red: 0.4,
+                green: 1.0,
+                blue: 0.4,
+            }, 0.18),
+        })],
+        ambient: Colour {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        },
+        ..Scene::default()
+    };
+
+    let buffer = render_to_buffer(&scene);
+    assert_eq!(buffer.len(), 24 * 16 * 4);
+
+    let img = render(&scene).to_rgba8();
+    let last = buffer.len() - 4;
+    assert_eq!(&buffer[..4], &img.get_pixel(0, 0).0);
+    assert_eq!(&buffer[last..], &img.get_pixel(23, 15).0);
+    let centre = (8 * 24 + 12) * 4;
+    assert_eq!(&buffer[centre..centre + 4], &img.get_pixel(12, 8).0);
+}
+
+#[test]
+fn test_render_progress_reaches_one() {
+    let scene = Scene {
+        width: 8,
+        height: 6,
+        ..Scene::default()
+    };
+
+    let mut reported = Vec::new();
+    render_with_progress(&scene, |fraction| reported.push(fraction));
+    assert!(!reported.is_empty());
+    assert!(reported.windows(2).all(|w| w[0] <= w[1]));
+    assert_eq!(*reported.last().unwrap(), 1.0);
+}
+
+#[test]
+fn test_render_progress_inside_pool_reports_each_tile() {
+    let scene = Scene {
+        width: 16,
+        height: 16,
+        tile_size: 4,
+        ..Scene::default()
+    };
+
+    // On a one-thread pool the calling thread renders every tile itself.
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+    let mut reported = Vec::new();
+    pool.install(|| render_with_progress(&scene, |fraction| reported.push(fraction)));
+    let expected: Vec<f32> = (1..=16).map(|done| done as f32 / 16.0).collect();
+    assert_eq!(reported, expected);
+}
+
+#[test]
+fn test_tiled_render_matches_row_split() {
+    use scene::{Element, Sphere, Material};
+
+    let scene = Scene {
+        width: 23,
+        height: 17,
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.5, 0.0, -4.0),
+            radius: 1.5,
+            material: Material::diffuse(Colour {
+                red: 0.4,
+                green: 1.0,
+                blue: 0.4,
+            }, 0.18),
+        })],
+        ambient: Colour {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        },
+        tile_size: 5,
+        ..Scene::default()
+    };
+
+    let never = AtomicBool::new(false);
+    let size = Resolution::of(&scene);
+    let rows = render_tiles(&scene, size, &size.whole(), &tiles(23, 17, 23, 1), |_| {}, &never);
+    assert_eq!(render_linear(&scene, Resolution::of(&scene), |_| {}, &never), rows);
+}
+
+#[test]
+fn test_quadrant_regions_match_full_render() {
+    use scene::{Element, Sphere, Plane, Background, SphericalLight, Attenuation};
+
+    // The sphere casts a soft shadow on the floor, which samples the light at
+    // random points.
+    let scene = Scene {
+        width: 37,
+        height: 29,
+        elements: vec![
+            Element::Sphere(Sphere {
+                center: Point::new(0.5, -0.25, -5.0),
+                radius: 1.5,
+                material: Material::diffuse(Colour::from_one(0.5), 0.18),
+            }),
+            Element::Plane(Plane {
+                origin: Point::new(0.0, -2.0, 0.0),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                material: Material::diffuse(Colour::from_one(0.8), 0.5),
+            }),
+        ],
+        light: vec![Light::Spherical(SphericalLight {
+            position: Point::new(-2.0, 4.0, -3.0),
+            colour: Colour::from_one(1.0),
+            intensity: 500.0,
+            radius: 1.0,
+            attenuation: Attenuation::InverseSquare,
+            name: String::new(),
+            enabled: true,
+        })],
+        shadow_samples: 8,
+        ambient: Colour::from_one(0.2),
+        background: Background::Solid(Colour::from_one(0.25)),
+        samples_per_pixel: 4,
+        tile_size: 8,
+        rng_seed: Some(5),
+        ..Scene::default()
+    };
+    let full = render(&scene).to_rgba8();
+
+    let (mid_x, mid_y) = (15, 12);
+    let mut combined = RgbaImage::new(scene.width, scene.height);
+    for (x0, y0, x1, y1) in [(0, 0, mid_x, mid_y), (mid_x, 0, scene.width, mid_y),
+                             (0, mid_y, mid_x, scene.height), (mid_x, mid_y, scene.width, scene.height)] {
+        let region = render_region(&scene, x0, y0, x1, y1);
+        assert_eq!(region.dimensions(), (x1 - x0, y1 - y0));
+        image::imageops::replace(&mut combined, &region, x0 as i64, y0 as i64);
+    }
+    assert_eq!(combined, full);
+}
+
+#[test]
+fn test_cancelled_render_keeps_finished_tiles() {
+    use scene::{Element, Sphere, Background};
+
+    let scene = Scene {
+        width: 128,
+        height: 128,
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 2.0,
+            material: Material::diffuse(Colour::from_one(0.5), 0.18),
+        })],
+        ambient: Colour::from_one(1.0),
+        background: Background::Solid(Colour::from_one(0.25)),
+        samples_per_pixel: 4,
+        tile_size: 8,
+        rng_seed: Some(3),
+        ..Scene::default()
+    };
+    let size = Resolution::of(&scene);
+    let full = render_linear(&scene, size, |_| {}, &AtomicBool::new(false));
+
+    // Cancel as soon as the first tile arrives.
+    let cancel = AtomicBool::new(false);
+    let partial = render_linear(&scene, size, |_| cancel.store(true, Ordering::Relaxed), &cancel);
+    let rendered = partial.chunks(3).filter(|rgb| rgb.iter().any(|&c| c > 0.0)).count();
+    assert!(rendered >= 64 && rendered < size.pixels());
+    for (p, f) in partial.chunks(3).zip(full.chunks(3)) {
+        assert!(p == f || p == [0.0, 0.0, 0.0]);
+    }
+
+    // Cancelling before starting renders nothing.
+    let img = render_cancellable(&scene, &AtomicBool::new(true)).to_rgba8();
+    assert!(img.pixels().all(|p| p.0 == [0, 0, 0, 255]));
+}
+
+#[test]
+fn test_seeded_renders_are_byte_identical() {
+    use scene::{Element, Sphere, Plane, Background, AreaLight};
+
+    let scene_with = |rng_seed: u64, tile_size: u32| Scene {
+        width: 32,
+        height: 24,
+        elements: vec![
+            Element::Sphere(Sphere {
+                center: Point::new(0.0, 0.0, -4.0),
+                radius: 1.0,
+                material: Material::diffuse(Colour::from_one(0.8), 0.5),
+            }),
+            Element::Plane(Plane {
+                origin: Point::new(0.0, -1.0, 0.0),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                material: Material::diffuse(Colour::from_one(0.5), 0.5),
+            }),
+        ],
+        // The area light's soft shadows draw from the pixel's generator too.
+        light: vec![Light::Area(AreaLight {
+            corner: Point::new(-2.0, 4.0, -6.0),
+            edge_u: Vector3::new(4.0, 0.0, 0.0),
+            edge_v: Vector3::new(0.0, 0.0, 4.0),
+            colour: Colour::from_one(1.0),
+            intensity: 100.0,
+            name: String::new(),
+            enabled: true,
+        })],
+        shadow_samples: 8,
+        background: Background::Solid(Colour::from_one(1.0)),
+        render_mode: RenderMode::PathTrace,
+        samples_per_pixel: 4,
+        tile_size,
+        rng_seed: Some(rng_seed),
+        ..Scene::default()
+    };
+
+    // Different tilings hand the pixels to threads in a different order.
+    let first = render_to_buffer(&scene_with(42, 16));
+    assert_eq!(first, render_to_buffer(&scene_with(42, 16)));
+    assert_eq!(first, render_to_buffer(&scene_with(42, 5)));
+    assert_ne!(first, render_to_buffer(&scene_with(43, 16)));
+}
+
+#[test]
+fn test_hdr_export_keeps_values_above_one() {
+    use scene::{Element, Sphere, Material};
+
+    let scene = Scene {
+        width: 8,
+        height: 8,
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -3.0),
+            radius: 1.0,
+            material: Material::diffuse(Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            }, 1.0),
+        })],
+        ambient: Colour {
+            red: 4.0,
+            green: 4.0,
+            blue: 4.0,
+        },
+        ..Scene::default()
+    };
+
+    let img = render_hdr(&scene);
+    assert!(img.get_pixel(4, 4).0[0] > 1.0);
+
+    // Tests run at once in other processes mustn't share the file.
+    let name = format!("rust-raytrace-hdr-test-{}.exr", std::process::id());
+    let path = std::env::temp_dir().join(name);
+    let path = path.to_str().unwrap();
+    let saved = save_hdr(&img, path);
+    let loaded = image::open(path);
+    let _ = std::fs::remove_file(path);
+    saved.unwrap();
+    let loaded = loaded.unwrap().to_rgb32f();
+    assert_eq!(loaded.get_pixel(4, 4).0, img.get_pixel(4, 4).0);
+}
+
+#[test]
+fn test_render_animation_writes_each_frame() {
+    use scene::{Element, Sphere};
+
+    let scene = Scene {
+        width: 8,
+        height: 8,
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: Material::diffuse(Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            }, 1.0),
+        })],
+        ambient: Colour {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        },
+        ..Scene::default()
+    };
+    // The camera pans right, so the sphere moves across the frame.
+    let camera_path = |t: f32| Camera {
+        position: Point::new(t as f64 * 2.0, 0.0, 0.0),
+        look_at: Point::new(t as f64 * 2.0, 0.0, -5.0),
+        ..Camera::default()
+    };
+
+    // Tests run at once in other processes mustn't share the directory.
+    let name = format!("rust-raytrace-animation-test-{}", std::process::id());
+    let dir = std::env::temp_dir().join(name);
+    let dir = dir.to_str().unwrap();
+    let rendered = render_animation(&scene, 3, camera_path, dir);
+    let frames: Result<Vec<_>, _> = (0..3)
+        .map(|i| image::open(format!("{}/frame_{:04}.png", dir, i)))
+        .collect();
+    let _ = std::fs::remove_dir_all(dir);
+    rendered.unwrap();
+    let frames: Vec<_> = frames.unwrap().into_iter().map(|frame| frame.to_rgba8()).collect();
+
+    assert_ne!(frames[0].as_raw(), frames[1].as_raw());
+    assert_ne!(frames[1].as_raw(), frames[2].as_raw());
+    assert_ne!(frames[0].as_raw(), frames[2].as_raw());
+}
+
+#[test]
+fn test_stratified_samples_cover_each_cell_once() {
+    let mut rng = StdRng::seed_from_u64(5);
+    let mut hits = [0; 16];
+    for sample in 0..16 {
+        let (dx, dy) = pixel_offset(sample, 16, &mut rng);
+        assert!((0.0..1.0).contains(&dx) && (0.0..1.0).contains(&dy));
+        hits[(dy * 4.0) as usize * 4 + (dx * 4.0) as usize] += 1;
+    }
+    assert_eq!(hits, [1; 16]);
+
+    // Samples beyond the grid may land anywhere in the pixel.
+    let (dx, dy) = pixel_offset(16, 16, &mut rng);
+    assert!((0.0..1.0).contains(&dx) && (0.0..1.0).contains(&dy));
+}
+
+#[test]
+fn test_adaptive_sampling_spends_rays_on_edges() {
+    use scene::{Element, Plane, Sphere, Material};
+
+    let solid = |red: f32| Material::diffuse(Colour {
+        red,
+        green: 0.5,
+        blue: 0.5,
+    }, 1.0);
+    let scene = Scene {
+        width: 32,
+        height: 32,
+        elements: vec![
+            Element::Plane(Plane {
+                origin: Point::new(0.0, 0.0, -10.0),
+                normal: Vector3::new(0.0, 0.0, 1.0),
+                material: solid(0.0),
+            }),
+            Element::Sphere(Sphere {
+                center: Point::new(0.0, 0.0, -5.0),
+                radius: 1.0,
+                material: solid(1.0),
+            }),
+        ],
+        ambient: Colour {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        },
+        samples_per_pixel: 4,
+        max_samples: 64,
+        variance_threshold: 1e-4,
+        ..Scene::default()
+    };
+
+    // Find a pixel along the middle row whose left and right edges see
+    // different elements.
+    let hits_sphere = |x: f64| match scene.trace(&Ray::create_prime_ray_at(x, 16.5, &scene)) {
+        Some(i) => matches!(*i.elements, Element::Sphere(_)),
+        None => false,
+    };
+    let edge = (16..32).find(|&x| hits_sphere(x as f64) && !hits_sphere(x as f64 + 1.0)).unwrap();
+
+    // Jitter is random, so an edge pixel's first batch can occasionally land
+    // on one side only; over several runs it must still sample more.
+    let runs = 20;
+    let flat_samples: u32 = (0..runs).map(|_| sample_pixel(&scene, Resolution::of(&scene), 1, 1).1).sum();
+    let edge_samples: u32 = (0..runs).map(|_| sample_pixel(&scene, Resolution::of(&scene), edge, 16).1).sum();
+    assert_eq!(flat_samples, 4 * runs);
+    assert!(edge_samples > flat_samples);
+}
+
+#[test]
+fn test_single_sample_matches_pixel_centre_render() {
+    use scene::{Element, Sphere, Light, DirectionalLight, Material};
+
+    let scene = Scene {
+        width: 40,
+        height: 30,
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -3.0),
+            radius: 1.0,
+            material: Material::diffuse(Colour {
+                red: 0.9,
+                green: 0.5,
+                blue: 0.2,
+            }, 0.5),
+        })],
+        light: vec![Light::Directional(DirectionalLight {
+            direction: Vector3::new(-1.0, -1.0, -1.0).normalize(),
+            colour: Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 5.0,
+            name: String::new(),
+            enabled: true,
+        })],
+        samples_per_pixel: 1,
+        ..Scene::default()
+    };
+
+    let img = render(&scene).to_rgba8();
+    let mut rng = StdRng::seed_from_u64(0);
+    for (x, y, pixel) in img.enumerate_pixels() {
+        let ray = Ray::create_prime_ray(x, y, &scene);
+        let expected = scene.trace(&ray)
+            .map(|i| to_rgba(&get_colour(&scene, &ray, &i, Bounces::default(), &mut rng), &scene))
+            .unwrap_or(Rgba([0, 0, 0, 255]));
+        assert_eq!(pixel.0, expected.0);
+    }
+}
+
+#[test]
+fn test_floor_lit_from_above_faces_camera() {
+    use scene::{Element, Plane, Material, Light, DirectionalLight};
+
+    let scene = Scene {
+        width: 32,
+        height: 32,
+        elements: vec![Element::Plane(Plane {
+            origin: Point::new(0.0, -2.0, 0.0),
+            normal: Vector3::new(0.0, 1.0, 0.0),
+            material: Material::diffuse(Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            }, 1.0),
+        })],
+        light: vec![Light::Directional(DirectionalLight {
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            colour: Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 1.0,
+            name: String::new(),
+            enabled: true,
+        })],
+        ..Scene::default()
+    };
+
+    // The bottom row of the image looks down at the floor.
+    let ray = Ray::create_prime_ray(16, 31, &scene);
+    let hit = scene.trace(&ray).unwrap();
+    let colour = get_colour(&scene, &ray, &hit, Bounces::default(), &mut StdRng::seed_from_u64(0));
+    assert!(colour.red > 0.0);
+}
+
+#[test]
+fn test_directional_light_brightness_ignores_direction_length() {
+    use scene::{Element, Plane, Material, Light, DirectionalLight};
+
+    let white = Colour {
+        red: 1.0,
+        green: 1.0,
+        blue: 1.0,
+    };
+    let floor_lit_along = |direction: Vector3| {
+        let scene = Scene {
+            width: 32,
+            height: 32,
+            elements: vec![Element::Plane(Plane {
+                origin: Point::new(0.0, -2.0, 0.0),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                material: Material::diffuse(white, 1.0),
+            })],
+            light: vec![Light::Directional(DirectionalLight {
+                direction,
+                colour: white,
+                intensity: 1.0,
+                name: String::new(),
+                enabled: true,
+            })],
+            ..Scene::default()
+        };
+        let ray = Ray::create_prime_ray(16, 31, &scene);
+        let hit = scene.trace(&ray).unwrap();
+        get_colour(&scene, &ray, &hit, Bounces::default(), &mut StdRng::seed_from_u64(0)).red
+    };
+
+    let unit = floor_lit_along(Vector3::new(0.0, -0.6, -0.8));
+    assert!(unit > 0.0);
+    assert!((floor_lit_along(Vector3::new(0.0, -1.5, -2.0)) - unit).abs() < 1e-6);
+}
+
+#[test]
+fn test_ambient_light_reaches_shadowed_points() {
+    use scene::{Element, Plane, Sphere, Material, Light, DirectionalLight};
+
+    let floor_colour = Colour {
+        red: 0.5,
+        green: 0.5,
+        blue: 0.5,
+    };
+    let ambient = Colour {
+        red: 0.2,
+        green: 0.4,
+        blue: 0.6,
+    };
+    let scene = Scene {
+        elements: vec![
+            Element::Plane(Plane {
+                origin: Point::new(0.0, -2.0, 0.0),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                material: Material::diffuse(floor_colour, 0.5),
+            }),
+            Element::Sphere(Sphere {
+                center: Point::new(0.0, 0.0, -5.0),
+                radius: 1.0,
+                material: Material::diffuse(floor_colour, 0.5),
+            }),
+        ],
+        light: vec![Light::Directional(DirectionalLight {
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            colour: Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 10.0,
+            name: String::new(),
+            enabled: true,
+        })],
+        ambient,
+        ..Scene::default()
+    };
+
+    // Aim at the floor directly beneath the sphere.
+    let ray = Ray {
+        origin: Point::new(0.0, 0.0, -2.0),
+        direction: Vector3::new(0.0, -2.0, -3.0).normalize(),
+    };
+    let hit = scene.trace(&ray).unwrap();
+    let colour = get_colour(&scene, &ray, &hit, Bounces::default(), &mut StdRng::seed_from_u64(0));
+
+    let expected = &floor_colour * &ambient * 0.5;
+    assert!(colour.red > 0.0);
+    assert!((colour.red - expected.red).abs() < 1e-6);
+    assert!((colour.green - expected.green).abs() < 1e-6);
+    assert!((colour.blue - expected.blue).abs() < 1e-6);
+}
+
+#[test]
+fn test_specular_highlight_faces_reflected_light() {
+    use scene::{Element, Plane, Material, DirectionalLight};
+
+    let shiny = Material {
+        specular: 1.0,
+        shininess: 50.0,
+        ..Material::diffuse(Colour {
+            red: 0.5,
+            green: 0.5,
+            blue: 0.5,
+        }, 0.18)
+    };
+    // Light shines from behind the camera, down the view direction.
+    let scene_with_normal = |normal: Vector3| Scene {
+        elements: vec![Element::Plane(Plane {
+            origin: Point::new(0.0, 0.0, -5.0),
+            normal,
+            material: shiny.clone(),
+        })],
+        light: vec![Light::Directional(DirectionalLight {
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            colour: Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 1.0,
+            name: String::new(),
+            enabled: true,
+        })],
+        ..Scene::default()
+    };
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let facing = cast_ray(&scene_with_normal(Vector3::new(0.0, 0.0, 1.0)), &ray, Bounces::default(),
+                          &mut rng);
+    let angled = cast_ray(&scene_with_normal(Vector3::new(0.5, 0.0, 0.866)), &ray, Bounces::default(),
+                          &mut rng);
+    assert!(facing.red > 0.0 && angled.red > 0.0);
+    assert!(facing.red > 10.0 * angled.red);
+}
+
+#[test]
+fn test_fog_thickens_with_distance() {
+    use scene::{Element, Plane, Material, Fog};
+
+    let fog = Fog {
+        colour: Colour {
+            red: 0.0,
+            green: 0.0,
+            blue: 1.0,
+        },
+        density: 0.1,
+    };
+    let scene_at = |z: f64| Scene {
+        elements: vec![Element::Plane(Plane {
+            origin: Point::new(0.0, 0.0, z),
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            material: Material::diffuse(Colour {
+                red: 1.0,
+                green: 0.0,
+                blue: 0.0,
+            }, 0.18),
+        })],
+        ambient: Colour {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        },
+        fog: Some(fog),
+        ..Scene::default()
+    };
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+
+    let near = cast_ray(&scene_at(-1.0), &ray, Bounces::default(), &mut StdRng::seed_from_u64(0));
+    let far = cast_ray(&scene_at(-20.0), &ray, Bounces::default(), &mut StdRng::seed_from_u64(0));
+    assert!(far.blue > near.blue);
+    assert!(far.red < near.red);
+
+    let miss = cast_ray(&scene_at(1.0), &ray, Bounces::default(), &mut StdRng::seed_from_u64(0));
+    assert_eq!((miss.red, miss.green, miss.blue), (0.0, 0.0, 1.0));
+}
+
+#[test]
+fn test_emissive_sphere_glows_without_lights() {
+    use scene::{Element, Sphere, Material};
+
+    let glow = Colour {
+        red: 0.9,
+        green: 0.6,
+        blue: 0.1,
+    };
+    let scene = Scene {
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: Material {
+                emission: glow,
+                ..Material::diffuse(Colour::black(), 0.0)
+            },
+        })],
+        light: Vec::new(),
+        ..Scene::default()
+    };
+
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    let colour = cast_ray(&scene, &ray, Bounces::default(), &mut StdRng::seed_from_u64(0));
+    assert_eq!((colour.red, colour.green, colour.blue), (glow.red, glow.green, glow.blue));
+}
+
+#[test]
+fn test_metal_reflections_take_its_colour() {
+    use scene::{Element, Sphere};
+
+    let red = Colour {
+        red: 1.0,
+        green: 0.0,
+        blue: 0.0,
+    };
+    let white = Colour {
+        red: 1.0,
+        green: 1.0,
+        blue: 1.0,
+    };
+    // A red mirror ball, reflecting a white sky.
+    let scene_with = |kind: MaterialKind| Scene {
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: Material {
+                kind,
+                reflectivity: 0.8,
+                ..Material::diffuse(red, 0.5)
+            },
+        })],
+        ambient: white,
+        background: scene::Background::Solid(white),
+        ..Scene::default()
+    };
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    let shade_with = |scene: &Scene| {
+        let hit = scene.trace(&ray).unwrap();
+        shade(scene, &ray, &hit, Bounces::default(), &mut StdRng::seed_from_u64(0))
+    };
+
+    let metal = shade_with(&scene_with(MaterialKind::Metal));
+    assert!(metal.reflection.red > 0.5);
+    assert_eq!(metal.reflection.green, 0.0);
+    assert_eq!((metal.diffuse.red, metal.diffuse.green), (0.0, 0.0));
+
+    let dielectric = shade_with(&scene_with(MaterialKind::Dielectric));
+    assert!(dielectric.reflection.green > 0.5);
+    assert_eq!(dielectric.reflection.green, dielectric.reflection.red);
+    assert!(dielectric.diffuse.red > 0.0);
+}
+
+#[test]
+fn test_metal_keeps_its_character_without_reflections() {
+    use scene::{Element, Sphere, DirectionalLight};
+
+    let white = Colour {
+        red: 1.0,
+        green: 1.0,
+        blue: 1.0,
+    };
+    // A red metal ball lit from behind the camera, with no reflection bounces
+    // to spare.
+    let scene = Scene {
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: Material {
+                kind: MaterialKind::Metal,
+                reflectivity: 0.8,
+                specular: 1.0,
+                ..Material::diffuse(Colour {
+                    red: 1.0,
+                    green: 0.0,
+                    blue: 0.0,
+                }, 0.5)
+            },
+        })],
+        light: vec![Light::Directional(DirectionalLight {
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            colour: white,
+            intensity: 1.0,
+            name: String::new(),
+            enabled: true,
+        })],
+        ambient: white,
+        max_reflection_depth: 0,
+        ..Scene::default()
+    };
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    let hit = scene.trace(&ray).unwrap();
+    let metal = shade(&scene, &ray, &hit, Bounces::default(), &mut StdRng::seed_from_u64(0));
+
+    let diffuse = metal.diffuse;
+    assert_eq!((diffuse.red, diffuse.green, diffuse.blue), (0.0, 0.0, 0.0));
+    assert!(metal.specular.red > 0.0);
+    assert_eq!((metal.specular.green, metal.specular.blue), (0.0, 0.0));
+}
+
+#[test]
+fn test_diffuse_colour_ignores_reflectivity() {
+    use scene::{Element, Plane, DirectionalLight};
+
+    let matte = Material::diffuse(Colour {
+        red: 0.8,
+        green: 0.4,
+        blue: 0.2,
+    }, 0.5);
+    let mirror = Material {
+        reflectivity: 0.9,
+        ..matte.clone()
+    };
+    let scene_with = |material: Material| Scene {
+        elements: vec![Element::Plane(Plane {
+            origin: Point::new(0.0, -1.0, 0.0),
+            normal: Vector3::new(0.0, 1.0, 0.0),
+            material,
+        })],
+        light: vec![Light::Directional(DirectionalLight {
+            direction: Vector3::new(0.0, -1.0, -1.0).normalize(),
+            colour: Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 2.0,
+            name: String::new(),
+            enabled: true,
+        })],
+        ..Scene::default()
+    };
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, -1.0, -2.0).normalize(),
+    };
+    let diffuse_of = |scene: &Scene| {
+        let hit = scene.trace(&ray).unwrap();
+        let surface = SurfacePoint::new(&hit);
+        let arriving = light_arriving(scene, &surface, &mut StdRng::seed_from_u64(0));
+        diffuse_colour(scene, &surface, &arriving, &scene.ambient)
+    };
+
+    let from_matte = diffuse_of(&scene_with(matte));
+    let from_mirror = diffuse_of(&scene_with(mirror));
+    assert!(from_matte.red > 0.0);
+    assert_eq!((from_matte.red, from_matte.green, from_matte.blue),
+               (from_mirror.red, from_mirror.green, from_mirror.blue));
+}
+
+#[test]
+fn test_importance_sampled_lights_average_to_all_lights() {
+    use scene::{Element, Plane, SphericalLight, Attenuation};
+
+    let light = |x: f64, intensity: f32, colour: Colour| Light::Spherical(SphericalLight {
+        position: Point::new(x, 2.0, -4.0),
+        colour,
+        intensity,
+        radius: 0.0,
+        attenuation: Attenuation::InverseSquare,
+        name: String::new(),
+        enabled: true,
+    });
+    let scene_with = |light_sampling: LightSampling| Scene {
+        elements: vec![Element::Plane(Plane {
+            origin: Point::new(0.0, -1.0, 0.0),
+            normal: Vector3::new(0.0, 1.0, 0.0),
+            material: Material::diffuse(Colour::from_one(1.0), 0.5),
+        })],
+        light: vec![
+            light(-3.0, 400.0, Colour { red: 1.0, green: 0.2, blue: 0.2 }),
+            light(0.0, 100.0, Colour { red: 0.2, green: 1.0, blue: 0.2 }),
+            light(4.0, 900.0, Colour { red: 0.2, green: 0.2, blue: 1.0 }),
+        ],
+        light_sampling,
+        ..Scene::default()
+    };
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, -1.0, -4.0).normalize(),
+    };
+    let diffuse_of = |scene: &Scene, rng: &mut StdRng| {
+        let hit = scene.trace(&ray).unwrap();
+        let surface = SurfacePoint::new(&hit);
+        diffuse_colour(scene, &surface, &light_arriving(scene, &surface, rng), &scene.ambient)
+    };
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let all = diffuse_of(&scene_with(LightSampling::All), &mut rng);
+    let sampled = scene_with(LightSampling::Importance);
+    let samples = 20000;
+    let mean = (0..samples)
+        .map(|_| diffuse_of(&sampled, &mut rng))
+        .fold(Colour::black(), |acc, c| acc + c) * (1.0 / samples as f32);
+    for (m, a) in [(mean.red, all.red), (mean.green, all.green), (mean.blue, all.blue)] {
+        assert!((m - a).abs() < 0.03 * a, "{} vs {}", m, a);
+    }
+
+    // The light is chosen with the generator passed in, so seeded renders
+    // choose the same one.
+    let picks = |seed: u64| {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..8).map(|_| diffuse_of(&sampled, &mut rng).red).collect::<Vec<_>>()
+    };
+    assert_eq!(picks(3), picks(3));
+}
+
+#[test]
+fn test_debug_channel_isolates_reflection() {
+    use scene::{Element, Sphere};
+
+    let scene = Scene {
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: Material::diffuse(Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            }, 1.0),
+        })],
+        ambient: Colour {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        },
+        debug_channel: Some(Channel::Reflection),
+        ..Scene::default()
+    };
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+
+    // The sphere is lit but not reflective, so its reflection channel is black.
+    assert!(cast_ray(&scene, &ray, Bounces::default(), &mut StdRng::seed_from_u64(0)).red > 0.0);
+    let reflection = cast_primary_ray(&scene, &scene.camera, &ray, &mut pixel_rng(&scene, 0, 0));
+    assert_eq!((reflection.red, reflection.green, reflection.blue), (0.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_gradient_background_for_missed_rays() {
+    use scene::Background;
+
+    let top = Colour {
+        red: 0.5,
+        green: 0.7,
+        blue: 1.0,
+    };
+    let bottom = Colour {
+        red: 1.0,
+        green: 1.0,
+        blue: 1.0,
+    };
+    let scene = Scene {
+        background: Background::Gradient { top, bottom },
+        ..Scene::default()
+    };
+    let looking = |y: f64| cast_ray(&scene, &Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, y, 0.0),
+    }, Bounces::default(), &mut StdRng::seed_from_u64(0));
+
+    let up = looking(1.0);
+    assert_eq!((up.red, up.green, up.blue), (top.red, top.green, top.blue));
+    let down = looking(-1.0);
+    assert_eq!((down.red, down.green, down.blue), (bottom.red, bottom.green, bottom.blue));
+}
+
+#[test]
+fn test_path_tracing_lights_floor_from_emissive_ceiling() {
+    use scene::{Element, Plane};
+
+    let white = Colour {
+        red: 1.0,
+        green: 1.0,
+        blue: 1.0,
+    };
+    let room = |ceiling_emission: Colour| Scene {
+        width: 16,
+        height: 16,
+        elements: vec![
+            Element::Plane(Plane {
+                origin: Point::new(0.0, -1.0, 0.0),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                material: Material::diffuse(white, 0.8),
+            }),
+            Element::Plane(Plane {
+                origin: Point::new(0.0, 1.0, 0.0),
+                normal: Vector3::new(0.0, -1.0, 0.0),
+                material: Material {
+                    emission: ceiling_emission,
+                    ..Material::diffuse(white, 0.8)
+                },
+            }),
+        ],
+        light: Vec::new(),
+        samples_per_pixel: 16,
+        render_mode: RenderMode::PathTrace,
+        rng_seed: Some(7),
+        ..Scene::default()
+    };
+
+    // The bottom row of the image looks down at the floor.
+    let lit = room(white);
+    let dark = room(Colour::black());
+    let lit_floor = sample_pixel(&lit, Resolution::of(&lit), 8, 15).0;
+    let dark_floor = sample_pixel(&dark, Resolution::of(&dark), 8, 15).0;
+    assert!(lit_floor.red > 0.1);
+    assert_eq!(dark_floor.red, 0.0);
+
+    // The same seed gives the same result.
+    let again = sample_pixel(&lit, Resolution::of(&lit), 8, 15).0;
+    assert_eq!(again.red, lit_floor.red);
+}
+
+#[test]
+fn test_path_tracing_adds_ambient_only_at_first_hit() {
+    use scene::{Element, Plane};
+
+    let floor_colour = Colour {
+        red: 0.8,
+        green: 0.6,
+        blue: 0.4,
+    };
+    let scene = Scene {
+        elements: vec![Element::Plane(Plane {
+            origin: Point::new(0.0, -1.0, 0.0),
+            normal: Vector3::new(0.0, 1.0, 0.0),
+            material: Material::diffuse(floor_colour, 0.5),
+        })],
+        ambient: Colour::from_one(1.0),
+        render_mode: RenderMode::PathTrace,
+        ..Scene::default()
+    };
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, -1.0, -1.0).normalize(),
+    };
+    let mut rng = StdRng::seed_from_u64(0);
+
+    // Bounces off the floor only reach the black background, so all that is
+    // left is the ambient light at the camera ray's hit.
+    let first = path_radiance(&scene, &ray, Bounces::default(), &Colour::from_one(1.0), &mut rng);
+    let expected = floor_colour * 0.5;
+    assert_eq!((first.red, first.green, first.blue), (expected.red, expected.green, expected.blue));
+
+    let bounced = path_radiance(&scene, &ray, Bounces::default().reflected(), &Colour::from_one(1.0),
+                                &mut rng);
+    assert_eq!((bounced.red, bounced.green, bounced.blue), (0.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_depth_mode_shows_nearer_surfaces_darker() {
+    use scene::{Element, Sphere};
+
+    let sphere = |x: f64, z: f64| Element::Sphere(Sphere {
+        center: Point::new(x, 0.0, z),
+        radius: 1.0,
+        material: Material::diffuse(Colour::from_one(1.0), 0.18),
+    });
+    let scene = Scene {
+        width: 32,
+        height: 16,
+        elements: vec![sphere(-2.0, -5.0), sphere(4.0, -10.0)],
+        render_mode: RenderMode::Depth,
+        depth_near: 0.0,
+        depth_far: 20.0,
+        samples_per_pixel: 4,
+        ..Scene::default()
+    };
+    let img = render(&scene).to_rgba8();
+    // The nearest point of each sphere, along the middle row.
+    let nearest_on = |left: bool| (0..32)
+        .filter_map(|x| scene.trace(&Ray::create_prime_ray(x, 8, &scene)).map(|i| (x, i.distance)))
+        .filter(|&(_, distance)| (distance < 7.0) == left)
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .unwrap();
+    let (near_x, near_distance) = nearest_on(true);
+    let (far_x, _) = nearest_on(false);
+    let near = img.get_pixel(near_x, 8).0;
+    let far = img.get_pixel(far_x, 8).0;
+
+    // Depth is written without gamma or tone mapping.
+    assert_eq!(near[0], (near_distance as f32 / 20.0 * 255.0).round() as u8);
+    assert_eq!(near[0], near[1]);
+    assert!(near[0] < far[0]);
+    assert_eq!(img.get_pixel(0, 0).0[0], 255);
+}
+
+#[test]
+fn test_normals_mode_colours_by_normal() {
+    use scene::{Element, Sphere};
+
+    let scene = Scene {
+        width: 17,
+        height: 17,
+        elements: vec![Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 2.0,
+            material: Material::diffuse(Colour::from_one(1.0), 0.18),
+        })],
+        render_mode: RenderMode::Normals,
+        ..Scene::default()
+    };
+    let img = render(&scene).to_rgba8();
+
+    // The middle of the sphere faces the camera, along +Z.
+    let centre = img.get_pixel(8, 8).0;
+    assert_eq!((centre[0], centre[1], centre[2]), (128, 128, 255));
+    // Further up the sphere the normal tilts towards +Y.
+    assert!(img.get_pixel(8, 6).0[1] > 128);
+    assert_eq!(img.get_pixel(0, 0).0, [0, 0, 0, 255]);
+}
+
+#[test]
+fn test_object_id_mode_separates_elements() {
+    use scene::{Element, Sphere};
+
+    let sphere = |x: f64| Element::Sphere(Sphere {
+        center: Point::new(x, 0.0, -5.0),
+        radius: 1.0,
+        material: Material::diffuse(Colour::from_one(1.0), 0.18),
+    });
+    let mut elements: Vec<Element> = (0..300).map(|i| sphere(i as f64 * 10.0 + 100.0)).collect();
+    elements.push(sphere(-1.0));
+    elements.push(sphere(1.0));
+    let scene = Scene {
+        width: 32,
+        height: 16,
+        elements,
+        render_mode: RenderMode::ObjectId,
+        samples_per_pixel: 4,
+        ..Scene::default()
+    };
+    let img = render(&scene).to_rgba8();
+    let id = |x: u32, y: u32| {
+        let p = img.get_pixel(x, y).0;
+        p[0] as u32 | (p[1] as u32) << 8 | (p[2] as u32) << 16
+    };
+
+    // Ids beyond 255 carry into the green channel.
+    assert_eq!(id(13, 8), 301);
+    assert_eq!(id(18, 8), 302);
+    assert_eq!(id(0, 0), 0);
+    // Edges are never blended into ids belonging to neither side.
+    for x in 0..32 {
+        assert!([0, 301, 302].contains(&id(x, 8)));
+    }
+}
+
+#[test]
+fn test_russian_roulette_keeps_average_radiance() {
+    use scene::{Element, Plane};
+
+    let white = Colour::from_one(1.0);
+    let room = |rr_min_depth: u32| Scene {
+        width: 16,
+        height: 16,
+        elements: vec![
+            Element::Plane(Plane {
+                origin: Point::new(0.0, -1.0, 0.0),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                material: Material::diffuse(white, 0.7),
+            }),
+            Element::Plane(Plane {
+                origin: Point::new(0.0, 1.0, 0.0),
+                normal: Vector3::new(0.0, -1.0, 0.0),
+                material: Material {
+                    emission: white,
+                    ..Material::diffuse(white, 0.7)
+                },
+            }),
+        ],
+        samples_per_pixel: 4096,
+        max_reflection_depth: 6,
+        render_mode: RenderMode::PathTrace,
+        rr_min_depth,
+        rng_seed: Some(11),
+        ..Scene::default()
+    };
+
+    let fixed = room(u32::MAX);
+    let roulette = room(1);
+    let fixed_floor = sample_pixel(&fixed, Resolution::of(&fixed), 8, 15).0;
+    let roulette_floor = sample_pixel(&roulette, Resolution::of(&roulette), 8, 15).0;
+    assert!(fixed_floor.red > 0.5);
+    assert!((roulette_floor.red - fixed_floor.red).abs() < 0.05 * fixed_floor.red,
+            "{} vs {}", roulette_floor.red, fixed_floor.red);
+}
+
+#[test]
+fn test_facing_mirrors_reflect_max_reflection_depth_times() {
+    use scene::{Element, Plane, Material};
+
+    let mirror = |z: f64, normal_z: f64| Element::Plane(Plane {
+        origin: Point::new(0.0, 0.0, z),
+        normal: Vector3::new(0.0, 0.0, normal_z),
+        material: Material {
+            reflectivity: 1.0,
+            ..Material::diffuse(Colour::from_one(1.0), 0.18)
+        },
+    });
+    // One pixel looking straight at a mirror, so each reflection ray is
+    // one more image of the mirror behind.
+    let reflections = |max_reflection_depth: u32, max_refraction_depth: u32| {
+        let scene = Scene {
+            width: 1,
+            height: 1,
+            elements: vec![mirror(-5.0, 1.0), mirror(5.0, -1.0)],
+            light: Vec::new(),
+            max_reflection_depth,
+            max_refraction_depth,
+            ..Scene::default()
+        };
+        render_with_stats(&scene).1.reflection_rays
+    };
+    assert_eq!(reflections(3, 0), 3);
+    assert_eq!(reflections(7, 0), 7);
+    assert_eq!(reflections(3, 20), 3);
+    assert_eq!(reflections(0, 20), 0);
+}
+
+#[test]
+fn test_facing_mirrors_terminate() {
+    use scene::{Element, Plane, Material};
+
+    let mirror = |z: f64, normal_z: f64| Element::Plane(Plane {
+        origin: Point::new(0.0, 0.0, z),
+        normal: Vector3::new(0.0, 0.0, normal_z),
+        material: Material {
+            reflectivity: 1.0,
+            ..Material::diffuse(Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            }, 0.18)
+        },
+    });
+    let scene = Scene {
+        width: 16,
+        height: 16,
+        elements: vec![mirror(-5.0, -1.0), mirror(5.0, 1.0)],
+        light: Vec::new(),
+        max_reflection_depth: 8,
+        ..Scene::default()
+    };
+
+    let ray = Ray::create_prime_ray(8, 8, &scene);
+    let hit = scene.trace(&ray).unwrap();
+    let colour = get_colour(&scene, &ray, &hit, Bounces::default(), &mut StdRng::seed_from_u64(0));
+    assert_eq!((colour.red, colour.green, colour.blue), (0.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_soft_shadow_penumbra_is_partially_lit() {
+    use scene::{Element, AxisAlignedBox, Material, SphericalLight, Attenuation};
+
+    // A slab covering x < 0 halfway between the floor and the light.
+    let scene = Scene {
+        elements: vec![Element::Box(AxisAlignedBox {
+            min: Point::new(-100.0, 4.0, -100.0),
+            max: Point::new(0.0, 5.0, 100.0),
+            material: Material::diffuse(Colour::black(), 0.18),
+        })],
+        light: vec![Light::Spherical(SphericalLight {
+            position: Point::new(0.0, 10.0, 0.0),
+            colour: Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 1000.0,
+            radius: 2.0,
+            attenuation: Attenuation::InverseSquare,
+            name: String::new(),
+            enabled: true,
+        })],
+        shadow_samples: 64,
+        ..Scene::default()
+    };
+    let light = &scene.light[0];
+    let up = Vector3::new(0.0, 1.0, 0.0);
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let lit = light_visibility(&scene, light, &Point::new(10.0, 0.0, 0.0), &up, &mut rng);
+    let dark = light_visibility(&scene, light, &Point::new(-10.0, 0.0, 0.0), &up, &mut rng);
+    let penumbra = light_visibility(&scene, light, &Point::zero(), &up, &mut rng);
+    assert_eq!(lit, 1.0);
+    assert_eq!(dark, 0.0);
+    assert!(penumbra > dark && penumbra < lit);
+}
+
+#[test]
+fn test_jittered_point_light_shadow_has_soft_edge() {
+    use scene::{Element, AxisAlignedBox, Material, SphericalLight, Attenuation};
+
+    // The same slab over x < 0, but lit by a point light.
+    let scene_with = |shadow_jitter: f64| Scene {
+        elements: vec![Element::Box(AxisAlignedBox {
+            min: Point::new(-100.0, 4.0, -100.0),
+            max: Point::new(0.0, 5.0, 100.0),
+            material: Material::diffuse(Colour::black(), 0.18),
+        })],
+        light: vec![Light::Spherical(SphericalLight {
+            position: Point::new(0.0, 10.0, 0.0),
+            colour: Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 1000.0,
+            radius: 0.0,
+            attenuation: Attenuation::InverseSquare,
+            name: String::new(),
+            enabled: true,
+        })],
+        shadow_samples: 64,
+        shadow_jitter,
+        ..Scene::default()
+    };
+    let up = Vector3::new(0.0, 1.0, 0.0);
+    let edge = [-0.05, 0.05].map(|x| Point::new(x, 0.0, 0.0));
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let hard = scene_with(0.0);
+    let hard_edge = edge.map(|p| light_visibility(&hard, &hard.light[0], &p, &up, &mut rng));
+    assert_eq!(hard_edge, [0.0, 1.0]);
+
+    let soft = scene_with(0.1);
+    let light = &soft.light[0];
+    assert_eq!(light_visibility(&soft, light, &Point::new(10.0, 0.0, 0.0), &up, &mut rng), 1.0);
+    assert_eq!(light_visibility(&soft, light, &Point::new(-10.0, 0.0, 0.0), &up, &mut rng), 0.0);
+    for p in &edge {
+        let visibility = light_visibility(&soft, light, p, &up, &mut rng);
+        assert!(visibility > 0.0 && visibility < 1.0);
+    }
+
+    // The jitter comes from the generator passed in, so it repeats.
+    let seeded = |seed: u64| {
+        let mut rng = StdRng::seed_from_u64(seed);
+        edge.map(|p| light_visibility(&soft, light, &p, &up, &mut rng))
+    };
+    assert_eq!(seeded(7), seeded(7));
+}
+
+#[test]
+fn test_disabled_shadows_light_occluded_points() {
+    use scene::{Element, AxisAlignedBox, Plane, DirectionalLight};
+
+    // A floor with a slab hanging over the origin, lit from straight above.
+    let scene = Scene {
+        elements: vec![
+            Element::Plane(Plane {
+                origin: Point::zero(),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                material: Material::diffuse(Colour::black(), 0.18),
+            }),
+            Element::Box(AxisAlignedBox {
+                min: Point::new(-1.0, 4.0, -1.0),
+                max: Point::new(1.0, 5.0, 1.0),
+                material: Material::diffuse(Colour::black(), 0.18),
+            }),
+        ],
+        light: vec![Light::Directional(DirectionalLight {
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            colour: Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 1.0,
+            name: String::new(),
+            enabled: true,
+        })],
+        shadows_enabled: false,
+        ..Scene::default()
+    };
+    let arriving_at = |scene: &Scene, x: f64| {
+        let ray = Ray {
+            origin: Point::new(x, 1.0, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        let hit = scene.trace(&ray).unwrap();
+        light_arriving(scene, &SurfacePoint::new(&hit), &mut StdRng::seed_from_u64(0))[0]
+    };
+
+    assert_eq!(arriving_at(&scene, 0.0), arriving_at(&scene, 10.0));
+    let shadowed = Scene {
+        shadows_enabled: true,
+        ..scene
+    };
+    assert!(arriving_at(&shadowed, 0.0) < arriving_at(&shadowed, 10.0));
+}
+
+#[test]
+fn test_area_light_penumbra_is_partially_lit() {
+    use scene::{Element, AxisAlignedBox, Material, AreaLight};
+
+    // A 4 x 4 light facing down, half hidden from the origin by a slab over x < 0.
+    let scene = Scene {
+        elements: vec![Element::Box(AxisAlignedBox {
+            min: Point::new(-100.0, 4.0, -100.0),
+            max: Point::new(0.0, 5.0, 100.0),
+            material: Material::diffuse(Colour::black(), 0.18),
+        })],
+        light: vec![Light::Area(AreaLight {
+            corner: Point::new(-2.0, 10.0, -2.0),
+            edge_u: Vector3::new(4.0, 0.0, 0.0),
+            edge_v: Vector3::new(0.0, 0.0, 4.0),
+            colour: Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 1000.0,
+            name: String::new(),
+            enabled: true,
+        })],
+        shadow_samples: 256,
+        ..Scene::default()
+    };
+    let light = &scene.light[0];
+    let up = Vector3::new(0.0, 1.0, 0.0);
+
+    assert!(light.intensity(&Point::zero()) > 0.0);
+    assert_eq!(light.intensity(&Point::new(0.0, 20.0, 0.0)), 0.0);
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let lit = light_visibility(&scene, light, &Point::new(20.0, 0.0, 0.0), &up, &mut rng);
+    let dark = light_visibility(&scene, light, &Point::new(-20.0, 0.0, 0.0), &up, &mut rng);
+    let penumbra = light_visibility(&scene, light, &Point::zero(), &up, &mut rng);
+    assert_eq!(lit, 1.0);
+    assert_eq!(dark, 0.0);
+    assert!(penumbra > 0.3 && penumbra < 0.7, "penumbra = {}", penumbra);
+}
+
+#[test]
+fn test_directional_shadow_has_no_acne() {
+    use scene::{Element, Plane, AxisAlignedBox, Material, DirectionalLight};
 
-    for x in 0..scene.width {
-        for y in 0..scene.height {
-            let ray = Ray::create_prime_ray(x, y, scene);
+    // Light at 45 degrees over a box, which shadows the floor for 0 <= x <= 3.
+    let scene = Scene {
+        elements: vec![
+            Element::Plane(Plane {
+                origin: Point::zero(),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                material: Material::diffuse(Colour::black(), 0.18),
+            }),
+            Element::Box(AxisAlignedBox {
+                min: Point::new(-1.0, 1.0, -10.0),
+                max: Point::new(1.0, 2.0, 10.0),
+                material: Material::diffuse(Colour::black(), 0.18),
+            }),
+        ],
+        light: vec![Light::Directional(DirectionalLight {
+            direction: Vector3::new(1.0, -1.0, 0.0).normalize(),
+            colour: Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 1.0,
+            name: String::new(),
+            enabled: true,
+        })],
+        shadow_bias: 0.0,
+        ..Scene::default()
+    };
+    let light = &scene.light[0];
+    let mut rng = StdRng::seed_from_u64(0);
 
-            let intersection = scene.trace(&ray);
-            let color = intersection.map(|i| to_rgba(&get_colour(scene, &ray, &i)))
-                .unwrap_or(black);
-            img.put_pixel(x, y, color);
+    // Hit points found by tracing carry the rounding error that causes acne.
+    for i in 0..200 {
+        let x = -6.0 + i as f64 * 0.06;
+        let ray = Ray {
+            origin: Point::new(0.0, 0.5, 7.0),
+            direction: (Point::new(x, 0.0, -3.0) - Point::new(0.0, 0.5, 7.0)).normalize(),
+        };
+        let hit = scene.trace(&ray).unwrap();
+        let hit_point = hit.hit_point;
+        let visibility = light_visibility(&scene, light, &hit_point, &hit.surface_normal, &mut rng);
+        if hit_point.x < -0.01 || hit_point.x > 3.01 {
+            assert_eq!(visibility, 1.0, "acne at x = {}", hit_point.x);
+        } else if hit_point.x > 0.01 && hit_point.x < 2.99 {
+            assert_eq!(visibility, 0.0, "light leak at x = {}", hit_point.x);
         }
     }
-    img
 }
 
-fn to_rgba(colour: &Colour) -> Rgba<u8> {
-    Rgba::from_channels((colour.red * 255.0) as u8, (colour.green * 255.0) as u8, (colour.blue * 255.0) as u8, 0)
+#[test]
+fn test_sphere_shadow_on_plane_has_no_speckle() {
+    use scene::{Element, Plane, Sphere, Material, SphericalLight, Attenuation};
+
+    // A unit sphere resting on the floor, lit from directly above.
+    let scene = Scene {
+        elements: vec![
+            Element::Plane(Plane {
+                origin: Point::zero(),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                material: Material::diffuse(Colour::black(), 0.18),
+            }),
+            Element::Sphere(Sphere {
+                center: Point::new(0.0, 1.0, 0.0),
+                radius: 1.0,
+                material: Material::diffuse(Colour::black(), 0.18),
+            }),
+        ],
+        light: vec![Light::Spherical(SphericalLight {
+            position: Point::new(0.0, 20.0, 0.0),
+            colour: Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 1000.0,
+            radius: 0.0,
+            attenuation: Attenuation::InverseSquare,
+            name: String::new(),
+            enabled: true,
+        })],
+        shadow_bias: 0.0,
+        ..Scene::default()
+    };
+    let light = &scene.light[0];
+    let mut rng = StdRng::seed_from_u64(0);
+
+    // Trace down onto the floor from a grazing camera so hit points carry rounding error.
+    let eye = Point::new(0.0, 0.3, 9.0);
+    for i in 0..40 {
+        for j in 0..40 {
+            let target = Point::new(-2.0 + i as f64 * 0.1, 0.0, -2.0 + j as f64 * 0.1);
+            let ray = Ray {
+                origin: eye,
+                direction: (target - eye).normalize(),
+            };
+            let hit = match scene.trace(&ray) {
+                Some(hit) if matches!(hit.elements, Element::Plane(_)) => hit,
+                _ => continue,
+            };
+            let hit_point = hit.hit_point;
+            let visibility = light_visibility(&scene, light, &hit_point, &hit.surface_normal, &mut rng);
+            let from_axis = (hit_point.x * hit_point.x + hit_point.z * hit_point.z).sqrt();
+            if from_axis < 0.9 {
+                assert_eq!(visibility, 0.0, "speckle at {:?}", hit_point);
+            } else if from_axis > 1.2 {
+                assert_eq!(visibility, 1.0, "acne at {:?}", hit_point);
+            }
+        }
+    }
 }
 
 #[test]
-fn test_can_render_scene() {
+#[ignore]
+fn bench_render_scales_with_threads() {
+    use scene::{Element, Sphere, Light, SphericalLight, Material, Attenuation};
+    use std::time::Instant;
+
+    let mut elements = Vec::new();
+    for i in 0..40 {
+        elements.push(Element::Sphere(Sphere {
+            center: Point::new((i % 8) as f64 - 3.5, (i / 8) as f64 - 2.0, -8.0),
+            radius: 0.45,
+            material: Material {
+                reflectivity: 0.5,
+                ..Material::diffuse(Colour {
+                    red: 0.8,
+                    green: 0.3,
+                    blue: 0.3,
+                }, 0.18)
+            },
+        }));
+    }
     let scene = Scene {
-        width: 800,
-        height: 600,
-        fov: 90.0,
-        sphere: Sphere {
-            center: Point {
-                x: 0.0,
-                y: 0.0,
-                z: -5.0,
+        elements,
+        light: vec![Light::Spherical(SphericalLight {
+            position: Point::new(0.0, 5.0, 0.0),
+            colour: Colour {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
             },
-            radius: 5.0,
+            intensity: 10000.0,
+            radius: 0.0,
+            attenuation: Attenuation::InverseSquare,
+            name: String::new(),
+            enabled: true,
+        })],
+        ..Scene::default()
+    };
+
+    let time_with = |threads: usize| {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        let start = Instant::now();
+        pool.install(|| render(&scene));
+        start.elapsed()
+    };
+
+    let single = time_with(1);
+    let threads = rayon::current_num_threads();
+    let parallel = time_with(threads);
+    println!("1 thread: {:?}, {} threads: {:?}", single, threads, parallel);
+    if threads > 1 {
+        assert!(parallel < single);
+    }
+}
+
+// Fraction of the light that reaches the hit point unblocked. Lights with an
+// area are sampled at `shadow_samples` points to give soft shadow edges, as
+// are other lights when `shadow_jitter` is set. The samples come from the
+// pixel's `rng`, so seeded renders repeat exactly.
+fn light_visibility(scene: &Scene, light: &Light, hit_point: &Point, surface_normal: &Vector3,
+                    rng: &mut StdRng) -> f32 {
+    if !scene.shadows_enabled {
+        return 1.0;
+    }
+    let origin = *hit_point + (*surface_normal * scene.shadow_bias);
+    let unblocked = |direction: Vector3, distance: f64| {
+        let shadow_ray = Ray {
+            origin,
+            direction,
+        };
+        scene.count(Counter::ShadowRays, 1);
+        !scene.trace_shadow(&shadow_ray, distance - scene.shadow_epsilon)
+    };
+
+    // `sample_ray` picks the direction and length of each shadow ray.
+    let mut soft = |sample_ray: &mut dyn FnMut(&mut StdRng) -> (Vector3, f64)| {
+        let visible = (0..scene.shadow_samples)
+            .filter(|_| {
+                let (direction, distance) = sample_ray(rng);
+                unblocked(direction, distance)
+            })
+            .count();
+        visible as f32 / scene.shadow_samples as f32
+    };
+    let towards = |point: Point| {
+        let to_sample = point - origin;
+        (to_sample.normalize(), to_sample.length())
+    };
+
+    match *light {
+        Light::Spherical(ref s) if s.radius > 0.0 && scene.shadow_samples > 1 => {
+            soft(&mut |rng| towards(s.sample_point(rng)))
+        }
+        Light::Area(ref a) if scene.shadow_samples > 1 => soft(&mut |rng| towards(a.sample_point(rng))),
+        // Lights without a size: spread the rays over a narrow cone around
+        // the light's direction and average them (percentage-closer
+        // filtering), which blurs the otherwise hard shadow edge.
+        _ if scene.shadow_samples > 1 && scene.shadow_jitter > 0.0 => {
+            let direction = light.direction_from(hit_point);
+            let distance = light.distance(hit_point);
+            soft(&mut |rng| {
+                let offset = Vector3::random_in_disk(&direction, rng) * scene.shadow_jitter;
+                ((direction + offset).normalize(), distance)
+            })
+        }
+        _ => {
+            if unblocked(light.direction_from(hit_point), light.distance(hit_point)) {
+                1.0
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+// One corner of the image holds a cluster of mirrored spheres that is far
+// more expensive to shade than the empty remainder.
+#[test]
+#[ignore]
+fn bench_tiled_vs_row_split() {
+    use scene::{Element, Sphere, Light, SphericalLight, Material, Attenuation};
+    use std::time::Instant;
+
+    let mut elements = Vec::new();
+    for i in 0..25 {
+        elements.push(Element::Sphere(Sphere {
+            center: Point::new(-3.0 + (i % 5) as f64 * 0.4, 2.0 - (i / 5) as f64 * 0.4, -5.0),
+            radius: 0.2,
+            material: Material {
+                reflectivity: 0.9,
+                ..Material::diffuse(Colour {
+                    red: 0.9,
+                    green: 0.9,
+                    blue: 0.9,
+                }, 0.18)
+            },
+        }));
+    }
+    let scene = Scene {
+        elements,
+        light: vec![Light::Spherical(SphericalLight {
+            position: Point::new(0.0, 5.0, 0.0),
             colour: Colour {
-                red: 0.4,
+                red: 1.0,
                 green: 1.0,
-                blue: 0.4,
+                blue: 1.0,
             },
-        },
+            intensity: 10000.0,
+            radius: 0.0,
+            attenuation: Attenuation::InverseSquare,
+            name: String::new(),
+            enabled: true,
+        })],
+        max_reflection_depth: 16,
+        ..Scene::default()
+    };
+
+    let time = |tiles: &[Tile]| {
+        let start = Instant::now();
+        let size = Resolution::of(&scene);
+        let buffer = render_tiles(&scene, size, &size.whole(), tiles, |_| {}, &AtomicBool::new(false));
+        (start.elapsed(), buffer)
     };
+    let (row_time, row_buffer) = time(&tiles(scene.width, scene.height, scene.width, 1));
+    let (tile_time, tile_buffer) = time(&tiles(scene.width, scene.height, 16, 16));
+    println!("row split: {:?}, 16x16 tiles: {:?}", row_time, tile_time);
+    assert_eq!(row_buffer, tile_buffer);
+}
 
+// The separate contributions to a shaded point's colour, each already
+// weighted by the material so that they sum to the final colour (before fog).
+#[derive(Debug, Clone, Copy)]
+pub struct ShadingComponents {
+    pub diffuse: Colour,
+    pub specular: Colour,
+    pub reflection: Colour,
+    pub refraction: Colour,
+    pub emission: Colour,
+}
 
-    let img: DynamicImage = render(&scene);
-    assert_eq!(scene.width, img.width());
-    assert_eq!(scene.height, img.height());
+impl ShadingComponents {
+    pub fn total(&self) -> Colour {
+        self.diffuse + self.specular + self.reflection + self.refraction + self.emission
+    }
+
+    pub fn channel(&self, channel: Channel) -> Colour {
+        match channel {
+            Channel::Diffuse => self.diffuse,
+            Channel::Specular => self.specular,
+            Channel::Reflection => self.reflection,
+            Channel::Refraction => self.refraction,
+            Channel::Emission => self.emission,
+        }
+    }
 }
 
-fn get_colour(scene: &Scene, ray: &Ray, intersection: &Intersection) -> Colour {
-    let hit_point = ray.origin + (ray.direction * intersection.distance);
-    
-    let surface_normal = intersection.elements.surface_normal(&hit_point);
+// What a ray sees at the point where it hit a surface.
+struct SurfacePoint<'a> {
+    point: Point,
+    normal: Vector3,
+    colour: Colour,
+    material: &'a Material,
+}
 
-    let mut colour = Colour {
-        red: 0.0,
-        green: 0.0,
-        blue: 0.0,
+impl<'a> SurfacePoint<'a> {
+    fn new(intersection: &Intersection<'a>) -> SurfacePoint<'a> {
+        let point = intersection.hit_point;
+        let element = intersection.elements;
+        let material = element.material();
+        let mut normal = intersection.surface_normal;
+        if let Some(ref map) = material.normal_map {
+            let tangent = element.tangent(&point, &normal);
+            normal = map.perturb(&normal, &tangent, &element.texture_coords(&point));
+        }
+        SurfacePoint {
+            point,
+            normal,
+            colour: element.colour(&point),
+            material,
+        }
+    }
+}
+
+// How many times a ray has been reflected and refracted on its way from the
+// camera. Each kind of bounce stops at its own limit in the scene, so light
+// can pass deep into glass while mirrors only repeat a few times.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Bounces {
+    reflection: u32,
+    refraction: u32,
+}
+
+impl Bounces {
+    fn reflected(self) -> Bounces {
+        Bounces {
+            reflection: self.reflection + 1,
+            ..self
+        }
+    }
+
+    fn refracted(self) -> Bounces {
+        Bounces {
+            refraction: self.refraction + 1,
+            ..self
+        }
+    }
+
+    fn total(&self) -> u32 {
+        self.reflection + self.refraction
+    }
+
+    fn can_reflect(&self, scene: &Scene) -> bool {
+        self.reflection < scene.max_reflection_depth
+    }
+
+    fn can_refract(&self, scene: &Scene) -> bool {
+        self.refraction < scene.max_refraction_depth
+    }
+}
+
+fn get_colour(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: Bounces,
+              rng: &mut StdRng) -> Colour {
+    apply_fog(scene, shade(scene, ray, intersection, depth, rng).total(), intersection.distance)
+}
+
+fn shade(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: Bounces,
+         rng: &mut StdRng) -> ShadingComponents {
+    let surface = SurfacePoint::new(intersection);
+    let arriving = light_arriving(scene, &surface, rng);
+
+    let mut components = ShadingComponents {
+        diffuse: diffuse_colour(scene, &surface, &arriving, &scene.ambient),
+        specular: specular_colour(scene, ray, &surface, &arriving),
+        reflection: Colour::black(),
+        refraction: Colour::black(),
+        emission: surface.material.emission,
     };
 
-    for light in &scene.light {
-        let direction_to_light = light.direction_from(&hit_point);
-        let shadow_ray = Ray {
-            origin: hit_point + (surface_normal * scene.shadow_bias),
-            direction: direction_to_light,
-        };
-        let shadow_intersection = scene.trace(&shadow_ray);
-        let in_light = shadow_intersection.is_none() ||
-                       shadow_intersection.unwrap().distance > light.distance(&hit_point);
-        let light_intensity = if in_light {
-            light.intensity(&hit_point)
-        } else {
-            0.0
-        };
+    // Reflection and transparency each replace a share of what came before.
+    if depth.can_reflect(scene) {
+        let reflectivity = reflectance(ray, &surface);
+        if reflectivity > 0.0 {
+            components.diffuse = components.diffuse * (1.0 - reflectivity);
+            components.specular = components.specular * (1.0 - reflectivity);
+            components.reflection = reflection_colour(scene, ray, &surface, depth, rng) * reflectivity;
+        }
+    }
+
+    // Metals tint what they reflect and have no diffuse, however deep the ray.
+    if surface.material.kind == MaterialKind::Metal {
+        components.diffuse = Colour::black();
+        components.specular = &components.specular * &surface.colour;
+        components.reflection = &components.reflection * &surface.colour;
+    }
+
+    let transparency = surface.material.transparency;
+    if transparency > 0.0 && depth.can_refract(scene) {
+        let transmitted = get_transmitted_colour(scene, ray, &surface.point, &surface.normal,
+                                                 surface.material.refractive_index as f64,
+                                                 depth, rng);
+        components.diffuse = components.diffuse * (1.0 - transparency);
+        components.specular = components.specular * (1.0 - transparency);
+        components.reflection = components.reflection * (1.0 - transparency);
+        components.refraction = transmitted * transparency;
+    }
+    components
+}
+
+// The intensity of each of the scene's lights reaching the surface, after
+// shadowing. When sampling by importance, all but the one light chosen are
+// zero.
+fn light_arriving(scene: &Scene, surface: &SurfacePoint, rng: &mut StdRng) -> Vec<f32> {
+    let visible = |light: &Light, intensity: f32, rng: &mut StdRng| {
+        // Disabled lights have no intensity, and need no shadow rays.
+        if intensity <= 0.0 {
+            return 0.0;
+        }
+        intensity * light_visibility(scene, light, &surface.point, &surface.normal, rng)
+    };
+    let intensities = scene.light.iter().map(|light| light.intensity(&surface.point));
+    if scene.light_sampling == LightSampling::All {
+        return scene.light.iter().zip(intensities).map(|(light, i)| visible(light, i, rng)).collect();
+    }
+
+    let intensities: Vec<f32> = intensities.collect();
+    let total: f32 = intensities.iter().sum();
+    let mut arriving = vec![0.0; scene.light.len()];
+    if total <= 0.0 {
+        return arriving;
+    }
+    let mut pick = rng.gen::<f32>() * total;
+    let chosen = intensities.iter()
+        .position(|&i| {
+            pick -= i;
+            pick < 0.0
+        })
+        .unwrap_or_else(|| intensities.iter().rposition(|&i| i > 0.0).unwrap());
+    let intensity = intensities[chosen];
+    arriving[chosen] = visible(&scene.light[chosen], intensity, rng) * (total / intensity);
+    arriving
+}
 
-        let light_power = (surface_normal.dot_prod(&direction_to_light) as f32).max(0.0) *
-                          light_intensity;
-        
-        let light_reflected = intersection.elements.albedo() / std::f32::consts::PI;
+// Lambertian reflection of the `ambient` light and the scene's lights.
+fn diffuse_colour(scene: &Scene, surface: &SurfacePoint, arriving: &[f32],
+                  ambient: &Colour) -> Colour {
+    let albedo = surface.material.albedo;
+    let mut colour = &surface.colour * ambient * albedo;
+
+    for (light, &intensity) in scene.light.iter().zip(arriving) {
+        let direction_to_light = light.direction_from(&surface.point);
+        let light_power = saturate(surface.normal.dot_prod(&direction_to_light)) as f32 * intensity;
+        let light_reflected = albedo / std::f32::consts::PI;
 
         let light_colour = light.colour() * light_power * light_reflected;
-        colour = colour + (intersection.elements.colour() * &light_colour);
+        colour = colour + (&surface.colour * &light_colour);
+    }
+    colour
+}
+
+// Phong highlights in the colour of each light.
+fn specular_colour(scene: &Scene, ray: &Ray, surface: &SurfacePoint, arriving: &[f32]) -> Colour {
+    let material = surface.material;
+    let mut colour = Colour::black();
+    if material.specular <= 0.0 {
+        return colour;
+    }
 
+    for (light, &intensity) in scene.light.iter().zip(arriving) {
+        let direction_to_light = light.direction_from(&surface.point);
+        if surface.normal.dot_prod(&direction_to_light) <= 0.0 || intensity <= 0.0 {
+            continue;
+        }
+        let reflected = (-direction_to_light).reflect(&surface.normal);
+        let highlight = (saturate(reflected.dot_prod(&-ray.direction)) as f32).powf(material.shininess);
+        colour = colour + light.colour() * (highlight * material.specular * intensity);
     }
-    colour.clamp()
+    colour
 }
 
-fn main() {
-    let mut elements = Vec::new();
-    let mut lights = Vec::new();
+// What is seen in a perfect mirror at the surface.
+fn reflection_colour(scene: &Scene, ray: &Ray, surface: &SurfacePoint, depth: Bounces,
+                     rng: &mut StdRng) -> Colour {
+    let reflection_ray = Ray {
+        origin: surface.point + (surface.normal * scene.shadow_bias),
+        direction: ray.direction.reflect(&surface.normal),
+    };
+    cast_ray(scene, &reflection_ray, depth.reflected(), rng)
+}
 
-    let sp = Sphere {
-        center: Point {
-            x: 0.0,
-            y: 0.0,
-            z: -5.0,
-        },
-        radius: 1.0,
-        colour: Colour {
-            red: 0.0,
-            green: 0.0,
-            blue: 1.0,
-        },
-        albedo: 0.18,
+fn apply_fog(scene: &Scene, colour: Colour, distance: f64) -> Colour {
+    match scene.fog {
+        Some(ref fog) => fog.apply(&colour, distance),
+        None => colour,
+    }
+}
+
+// Light passing through a transparent surface: a Fresnel-weighted mix of the
+// reflected ray and the ray refracted by Snell's law. Both count against the
+// refraction limit, as the reflection is part of crossing the boundary.
+fn get_transmitted_colour(scene: &Scene, ray: &Ray, hit_point: &Point, surface_normal: &Vector3,
+                          index: f64, depth: Bounces, rng: &mut StdRng) -> Colour {
+    // Work with a normal facing the incoming ray, and swap the indices when
+    // the ray is leaving the object.
+    let (normal, eta) = if ray.direction.dot_prod(surface_normal) < 0.0 {
+        (*surface_normal, 1.0 / index)
+    } else {
+        (-*surface_normal, index)
     };
 
-    let sp1 = Sphere {
-        center: Point {
-            x: -3.0,
-            y: 1.0,
-            z: -6.0,
-        },
-        radius: 2.0,
-        colour: Colour {
-            red: 1.0,
-            green: 0.0,
-            blue: 0.0,
-        },
-        albedo: 0.18,
+    let reflection_ray = Ray {
+        origin: *hit_point + (normal * scene.shadow_bias),
+        direction: ray.direction.reflect(&normal),
     };
+    let reflected = cast_ray(scene, &reflection_ray, depth.refracted(), rng);
 
-    let sp2 = Sphere {
-        center: Point {
-            x: 2.0,
-            y: 2.0,
-            z: -4.0,
-        },
-        radius: 2.25,
-        colour: Colour {
+    match ray.direction.refract(&normal, eta) {
+        Some(direction) => {
+            let kr = fresnel(&ray.direction, &normal, eta) as f32;
+            let refraction_ray = Ray {
+                origin: *hit_point - (normal * scene.shadow_bias),
+                direction,
+            };
+            let refracted = cast_ray(scene, &refraction_ray, depth.refracted(), rng);
+            reflected * kr + refracted * (1.0 - kr)
+        }
+        // Total internal reflection.
+        None => reflected,
+    }
+}
+
+// Fraction of light reflected at a dielectric boundary, where `normal` faces
+// the incoming ray and `eta` is the ratio of refractive indices n1 / n2.
+fn fresnel(incident: &Vector3, normal: &Vector3, eta: f64) -> f64 {
+    let cos_i = -incident.dot_prod(normal);
+    let sin_t2 = eta * eta * (1.0 - cos_i * cos_i);
+    if sin_t2 >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin_t2).sqrt();
+    let r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_s * r_s + r_p * r_p) / 2.0
+}
+
+// Schlick's approximation to the Fresnel reflectance for light arriving at
+// `cos_theta` to the normal of a surface with refractive index `ior`.
+fn schlick(cos_theta: f64, ior: f64) -> f64 {
+    let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - saturate(cos_theta)).powi(5)
+}
+
+// Share of a reflective surface's colour that comes from its reflection.
+// `reflectivity` applies head-on and rises towards 1.0 at grazing angles.
+fn reflectance(ray: &Ray, surface: &SurfacePoint) -> f32 {
+    let material = surface.material;
+    if material.reflectivity <= 0.0 {
+        return 0.0;
+    }
+    let cos_theta = ray.direction.dot_prod(&surface.normal).abs();
+    let grazing = schlick(cos_theta, material.refractive_index as f64) as f32;
+    material.reflectivity + (1.0 - material.reflectivity) * grazing
+}
+
+#[test]
+fn test_schlick_reflectance() {
+    // Head-on, glass reflects R0 = ((1 - 1.5) / (1 + 1.5))^2 = 0.04.
+    assert!((schlick(1.0, 1.5) - 0.04).abs() < 1e-12);
+    assert!(schlick(0.0, 1.5) > 0.999);
+    assert!(schlick(0.5, 1.5) > schlick(0.9, 1.5));
+
+    // With matching indices only grazing light is reflected.
+    assert_eq!(schlick(1.0, 1.0), 0.0);
+    assert_eq!(schlick(0.0, 1.0), 1.0);
+}
+
+#[test]
+fn test_fresnel_at_normal_incidence() {
+    let incident = Vector3::new(0.0, 0.0, -1.0);
+    let normal = Vector3::new(0.0, 0.0, 1.0);
+    let kr = fresnel(&incident, &normal, 1.0 / 1.5);
+    assert!((kr - 0.04).abs() < 1e-9);
+
+    // Past the critical angle everything is reflected.
+    let glancing = Vector3::new(1.0, 0.0, -0.2).normalize();
+    assert_eq!(fresnel(&glancing, &normal, 1.5), 1.0);
+}
+
+// Colour seen by a ray from `camera`. A scene with a debug channel set
+// shows only that part of the shading at the first surface hit.
+fn cast_primary_ray(scene: &Scene, camera: &Camera, ray: &Ray, rng: &mut StdRng) -> Colour {
+    scene.count(Counter::PrimaryRays, 1);
+    match scene.render_mode {
+        RenderMode::PathTrace => {
+            return trace_path(scene, ray, Bounces::default(), &Colour::from_one(1.0), rng);
+        }
+        RenderMode::Depth => return depth_colour(scene, ray),
+        RenderMode::Normals => return normal_colour(scene, ray),
+        RenderMode::ObjectId => return object_id_colour(scene, ray),
+        RenderMode::Wireframe => return wireframe_colour(scene, ray),
+        RenderMode::FocusPeaking => return focus_peaking_colour(scene, camera, ray, rng),
+        _ => {}
+    }
+    match scene.debug_channel {
+        Some(channel) => scene.trace(ray)
+            .map(|i| shade(scene, ray, &i, Bounces::default(), rng).channel(channel))
+            .unwrap_or_else(Colour::black),
+        None => cast_ray(scene, ray, Bounces::default(), rng),
+    }
+}
+
+// Grey for the distance to the first surface along the ray, scaled between
+// the scene's near and far depths. Rays that hit nothing are white.
+fn depth_colour(scene: &Scene, ray: &Ray) -> Colour {
+    let depth = scene.trace(ray).map_or(1.0, |i| {
+        saturate((i.distance - scene.depth_near) / (scene.depth_far - scene.depth_near))
+    });
+    Colour::from_one(depth as f32)
+}
+
+// The world-space normal of the first surface along the ray as a colour, or
+// black if nothing is hit.
+fn normal_colour(scene: &Scene, ray: &Ray) -> Colour {
+    scene.trace(ray).map_or_else(Colour::black, |i| {
+        let n = (i.surface_normal + Vector3::from_one(1.0)) * 0.5;
+        Colour {
+            red: n.x as f32,
+            green: n.y as f32,
+            blue: n.z as f32,
+        }
+    })
+}
+
+// White where the ray hits a triangle near one of its edges, found from the
+// smallest of the hit point's barycentric coordinates. Triangles inside
+// transforms and groups count too. Everything else shows the background.
+fn wireframe_colour(scene: &Scene, ray: &Ray) -> Colour {
+    let on_edge = |i: &Intersection| {
+        // Follow any transforms down to the element, taking the hit point
+        // into its space.
+        let (mut element, mut point) = (i.elements, i.hit_point);
+        while let Element::Transformed(ref t) = *element {
+            point = t.inverse().transform_point(&point);
+            element = &t.element;
+        }
+        match *element {
+            Element::Triangle(ref triangle) => {
+                let coords = triangle.texture_coords(&point);
+                let (u, v) = (coords.u as f64, coords.v as f64);
+                u.min(v).min(1.0 - u - v) < scene.wireframe_width
+            }
+            _ => false,
+        }
+    };
+    if scene.trace(ray).is_some_and(|i| on_edge(&i)) {
+        Colour::from_one(1.0)
+    } else {
+        scene.background.colour(&ray.direction)
+    }
+}
+
+// The usual shading, except that surfaces near the plane of focus are marked
+// in green. Distances are measured along the view direction, as the camera
+// measures `focus_distance`.
+fn focus_peaking_colour(scene: &Scene, camera: &Camera, ray: &Ray, rng: &mut StdRng) -> Colour {
+    let (_, _, forward) = camera.basis();
+    let in_focus = scene.trace(ray).is_some_and(|i| {
+        let depth = (i.hit_point - camera.position).dot_prod(&forward);
+        (depth - camera.focus_distance).abs() <= scene.focus_tolerance
+    });
+    if in_focus {
+        Colour {
             red: 0.0,
             green: 1.0,
             blue: 0.0,
-        },
-        albedo: 0.18,
-    };
+        }
+    } else {
+        cast_ray(scene, ray, Bounces::default(), rng)
+    }
+}
 
-    let pl = Plane {
-        origin: Point {
-            x: 0.0,
-            y: -2.0,
-            z: 0.0,
-        },
-        normal: Vector3 {
-            x: 0.0,
-            y: -1.0,
-            z: 0.0,
-        },
-        colour: Colour {
-            red: 0.2,
-            green: 0.2,
-            blue: 0.2,
-        },
-        albedo: 0.18,
+// The id of the element first hit by the ray, spread over the three channels
+// a byte at a time so that it survives being written as 8-bit colour.
+fn object_id_colour(scene: &Scene, ray: &Ray) -> Colour {
+    let id = scene.trace(ray).map_or(0, |i| scene.element_id(i.elements));
+    let byte = |shift: u32| ((id >> shift) & 0xff) as f32 / 255.0;
+    Colour {
+        red: byte(0),
+        green: byte(8),
+        blue: byte(16),
+    }
+}
+
+// Radiance arriving along `ray`, estimated from a single random path. Each
+// surface adds its emission and the direct light from the scene's lights,
+// then the path continues in one random direction: a cosine-weighted bounce
+// for diffuse surfaces, a mirror reflection, or a refraction.
+//
+// `throughput` is the fraction of the light found that will reach the camera.
+// From `rr_min_depth` on, the path survives with a probability of its
+// luminance, and surviving paths are scaled up to keep the estimate unbiased.
+fn trace_path(scene: &Scene, ray: &Ray, depth: Bounces, throughput: &Colour,
+              rng: &mut StdRng) -> Colour {
+    if depth.total() < scene.rr_min_depth {
+        return path_radiance(scene, ray, depth, throughput, rng);
+    }
+    let survival = throughput.luminance().min(1.0);
+    if rng.gen::<f32>() >= survival {
+        return Colour::black();
+    }
+    path_radiance(scene, ray, depth, &(*throughput * (1.0 / survival)), rng) * (1.0 / survival)
+}
+
+fn path_radiance(scene: &Scene, ray: &Ray, depth: Bounces, throughput: &Colour,
+                 rng: &mut StdRng) -> Colour {
+    if depth.total() > 0 {
+        scene.count(Counter::ReflectionRays, 1);
+    }
+    let intersection = match scene.trace(ray) {
+        Some(intersection) => intersection,
+        None => return scene.background.colour(&ray.direction),
     };
+    let surface = SurfacePoint::new(&intersection);
+    let material = surface.material;
+    let mut colour = material.emission;
+    let (can_reflect, can_refract) = (depth.can_reflect(scene), depth.can_refract(scene));
+    if !can_reflect && !can_refract {
+        return colour;
+    }
 
-    let pl2 = Plane {
-        origin: Point {
-            x: 0.0,
-            y: 0.0,
-            z: -20.0,
-        },
-        normal: Vector3 {
-            x: 0.0,
-            y: 0.0,
-            z: -1.0,
-        },
-        colour: Colour {
-            red: 0.6,
-            green: 0.8,
-            blue: 1.0,
-        },
-        albedo: 0.18,
+    let arriving = light_arriving(scene, &surface, rng);
+    let specular = specular_colour(scene, ray, &surface, &arriving);
+    // Ambient light stands in for the indirect light that bounces sample, so
+    // it is only added where the camera ray lands.
+    let ambient = if depth.total() == 0 {
+        scene.ambient
+    } else {
+        Colour::black()
+    };
+    let facing_normal = if surface.normal.dot_prod(&ray.direction) > 0.0 {
+        -surface.normal
+    } else {
+        surface.normal
+    };
+    // Metals have no diffuse layer to bounce light from.
+    let metal = material.kind == MaterialKind::Metal;
+    let mut surface_colour = if metal {
+        &surface.colour * &specular
+    } else if !can_reflect {
+        // Diffuse bounces count as reflections.
+        diffuse_colour(scene, &surface, &arriving, &ambient) + specular
+    } else {
+        let bounce = Ray {
+            origin: surface.point + (facing_normal * scene.shadow_bias),
+            direction: Vector3::random_in_hemisphere(&facing_normal, rng),
+        };
+        // With cosine-weighted bounces the Lambertian cosine and pdf cancel,
+        // leaving just the surface's reflectance.
+        let weight = surface.colour * material.albedo;
+        let indirect = &weight * &trace_path(scene, &bounce, depth.reflected(), &(throughput * &weight),
+                                             rng);
+        diffuse_colour(scene, &surface, &arriving, &ambient) + specular + indirect
     };
 
-    let li = DirectionalLight {
-        direction: Vector3 {
-            x: 0.25,
-            y: 0.0,
-            z: -2.0,
-        },
-        
-        colour: Colour {
-            red: 1.0,
-            green: 1.0,
-            blue: 1.0,
-        },
-        intensity: 20.0,
+    let reflectivity = reflectance(ray, &surface);
+    if reflectivity > 0.0 && can_reflect {
+        let reflection_ray = Ray {
+            origin: surface.point + (facing_normal * scene.shadow_bias),
+            direction: ray.direction.reflect(&facing_normal),
+        };
+        let weight = if metal {
+            surface.colour * reflectivity
+        } else {
+            Colour::from_one(reflectivity)
+        };
+        let mut reflected = trace_path(scene, &reflection_ray, depth.reflected(),
+                                       &(throughput * &weight), rng);
+        if metal {
+            reflected = &reflected * &surface.colour;
+        }
+        surface_colour = surface_colour.lerp(&reflected, reflectivity);
+    }
+    if material.transparency > 0.0 && can_refract {
+        let weight = *throughput * material.transparency;
+        let transmitted = trace_transmission(scene, ray, &surface, depth, &weight, rng);
+        surface_colour = surface_colour.lerp(&transmitted, material.transparency);
+    }
+    colour = colour + surface_colour;
+    colour
+}
+
+// Follows either the reflected or the refracted ray at a transparent surface,
+// choosing between them with the Fresnel reflectance as the probability.
+fn trace_transmission(scene: &Scene, ray: &Ray, surface: &SurfacePoint, depth: Bounces,
+                      throughput: &Colour, rng: &mut StdRng) -> Colour {
+    let index = surface.material.refractive_index as f64;
+    let (normal, eta) = if ray.direction.dot_prod(&surface.normal) < 0.0 {
+        (surface.normal, 1.0 / index)
+    } else {
+        (-surface.normal, index)
     };
 
-    let li2 = SphericalLight {
-        position: Point {
-            x: -2.0,
-            y: 10.0,
-            z: -3.0,
+    let refracted = ray.direction.refract(&normal, eta)
+        .filter(|_| rng.gen::<f64>() >= fresnel(&ray.direction, &normal, eta));
+    let next = match refracted {
+        Some(direction) => Ray {
+            origin: surface.point - (normal * scene.shadow_bias),
+            direction,
         },
-        
-        colour: Colour {
-            red: 3.0,
-            green: 0.8,
-            blue: 0.3,
+        None => Ray {
+            origin: surface.point + (normal * scene.shadow_bias),
+            direction: ray.direction.reflect(&normal),
         },
-        intensity: 40000.0,
     };
+    trace_path(scene, &next, depth.refracted(), throughput, rng)
+}
 
-    elements.push(Element::Sphere(sp));
-    elements.push(Element::Sphere(sp1));
-    elements.push(Element::Sphere(sp2));
-    elements.push(Element::Plane(pl));
-    elements.push(Element::Plane(pl2));
-
-    lights.push(Light::Directional(li));
-    lights.push(Light::Spherical(li2));
+fn cast_ray(scene: &Scene, ray: &Ray, depth: Bounces, rng: &mut StdRng) -> Colour {
+    if depth.total() > 0 {
+        scene.count(Counter::ReflectionRays, 1);
+    }
+    scene.trace(ray)
+        .map(|i| get_colour(scene, ray, &i, depth, rng))
+        .unwrap_or_else(|| match scene.fog {
+            Some(fog) => fog.colour,
+            None if depth.total() > 0 && !scene.background.in_reflections() => Colour::black(),
+            None => scene.background.colour(&ray.direction),
+        })
+}
 
-    let scene = Scene {
-        width: 800,
-        height: 600,
-        fov: 90.0,
-        elements: elements,
-        light: lights,
-        shadow_bias: 0.0001,
+#[cfg(feature = "serde")]
+fn main() {
+    let settings = match args::Settings::parse(std::env::args().skip(1)) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let path = &settings.scene;
+    let mut scene = match Scene::from_json(path) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
     };
+    settings.apply(&mut scene);
 
     let img: DynamicImage = render(&scene);
-    img.save("test.png").unwrap();
+    if let Err(e) = img.save(&settings.output) {
+        eprintln!("{}: {}", settings.output, e);
+        std::process::exit(1);
+    }
+
+}
 
+#[cfg(not(feature = "serde"))]
+fn main() {
+    eprintln!("reading scene files needs the `serde` feature");
+    std::process::exit(1);
 }
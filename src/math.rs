@@ -0,0 +1,308 @@
+use crate::point::Point;
+use crate::vector::Vector3;
+use std::ops::Mul;
+
+// A 4x4 matrix acting on points and vectors as column vectors, so in `a * b`
+// the transform `b` is applied first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn identity() -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Matrix4 { m }
+    }
+
+    pub fn translate(offset: &Vector3) -> Matrix4 {
+        let mut matrix = Matrix4::identity();
+        matrix.m[0][3] = offset.x;
+        matrix.m[1][3] = offset.y;
+        matrix.m[2][3] = offset.z;
+        matrix
+    }
+
+    pub fn scale(factors: &Vector3) -> Matrix4 {
+        let mut matrix = Matrix4::identity();
+        matrix.m[0][0] = factors.x;
+        matrix.m[1][1] = factors.y;
+        matrix.m[2][2] = factors.z;
+        matrix
+    }
+
+    // Rotation by `degrees` about `axis`, anticlockwise when looking back
+    // along the axis towards the origin.
+    pub fn rotate(axis: &Vector3, degrees: f64) -> Matrix4 {
+        Quaternion::from_axis_angle(axis, degrees).to_matrix()
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Matrix4 { m }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting. Returns None when the
+    // matrix is singular, as for a scale by zero.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row == col {
+                    continue;
+                }
+                let factor = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[col][j];
+                    inv[row][j] -= factor * inv[col][j];
+                }
+            }
+        }
+        Some(Matrix4 { m: inv })
+    }
+
+    pub fn transform_point(&self, p: &Point) -> Point {
+        let m = &self.m;
+        Point::new(m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+                   m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+                   m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3])
+    }
+
+    // Directions ignore the translation part of the matrix.
+    pub fn transform_vector(&self, v: &Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3::new(m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+                     m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+                     m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z)
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Matrix4 { m }
+    }
+}
+
+// A rotation stored as a unit quaternion, which composes without the drift
+// that builds up when rotation matrices are multiplied repeatedly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub fn identity() -> Quaternion {
+        Quaternion {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    // Rotation by `degrees` about `axis`, in the same sense as Matrix4::rotate.
+    pub fn from_axis_angle(axis: &Vector3, degrees: f64) -> Quaternion {
+        let a = axis.normalize();
+        let (sin, cos) = (degrees.to_radians() / 2.0).sin_cos();
+        Quaternion {
+            w: cos,
+            x: a.x * sin,
+            y: a.y * sin,
+            z: a.z * sin,
+        }
+    }
+
+    pub fn normalize(&self) -> Quaternion {
+        let length = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        Quaternion {
+            w: self.w / length,
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+        }
+    }
+
+    pub fn to_matrix(&self) -> Matrix4 {
+        let Quaternion { w, x, y, z } = self.normalize();
+        Matrix4 {
+            m: [
+                [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y), 0.0],
+                [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x), 0.0],
+                [2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y), 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn rotate_vector(&self, v: &Vector3) -> Vector3 {
+        self.to_matrix().transform_vector(v)
+    }
+}
+
+// `a * b` rotates by `b` and then by `a`.
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, o: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            x: self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            y: self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            z: self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        }
+    }
+}
+
+// Clamps to [0, 1], as for cosines of angles that mustn't go negative.
+pub fn saturate(x: f64) -> f64 {
+    x.clamp(0.0, 1.0)
+}
+
+// The real roots in [low, high] of the polynomial with `coefficients`,
+// highest power first, in increasing order. The roots of the derivative split
+// the range into pieces over which the polynomial only rises or only falls,
+// and each piece changing sign holds one root, found by bisection. Roots
+// where the polynomial touches zero without crossing it can be missed.
+pub fn polynomial_roots(coefficients: &[f64], low: f64, high: f64) -> Vec<f64> {
+    let degree = coefficients.len().saturating_sub(1);
+    if degree == 0 {
+        return Vec::new();
+    }
+    let derivative: Vec<f64> = coefficients[..degree].iter()
+        .enumerate()
+        .map(|(i, c)| c * (degree - i) as f64)
+        .collect();
+    let evaluate = |t: f64| coefficients.iter().fold(0.0, |acc, c| acc * t + c);
+
+    let mut bounds = vec![low];
+    bounds.extend(polynomial_roots(&derivative, low, high));
+    bounds.push(high);
+    let mut roots = Vec::new();
+    for pair in bounds.windows(2) {
+        let (mut a, mut b) = (pair[0], pair[1]);
+        let (value_a, value_b) = (evaluate(a), evaluate(b));
+        if value_a == 0.0 {
+            if roots.last() != Some(&a) {
+                roots.push(a);
+            }
+            continue;
+        }
+        if value_a.signum() == value_b.signum() {
+            continue;
+        }
+        for _ in 0..100 {
+            let middle = 0.5 * (a + b);
+            if middle <= a || middle >= b {
+                break;
+            }
+            if evaluate(middle).signum() == value_a.signum() {
+                a = middle;
+            } else {
+                b = middle;
+            }
+        }
+        roots.push(0.5 * (a + b));
+    }
+    if evaluate(high) == 0.0 && roots.last() != Some(&high) {
+        roots.push(high);
+    }
+    roots
+}
+
+#[test]
+fn test_translate_then_scale() {
+    let matrix = Matrix4::translate(&Vector3::new(1.0, 2.0, 3.0)) *
+                 Matrix4::scale(&Vector3::new(2.0, 2.0, 2.0));
+    let p = matrix.transform_point(&Point::new(1.0, 1.0, 1.0));
+    assert_eq!((p.x, p.y, p.z), (3.0, 4.0, 5.0));
+    let v = matrix.transform_vector(&Vector3::new(1.0, 1.0, 1.0));
+    assert_eq!((v.x, v.y, v.z), (2.0, 2.0, 2.0));
+
+    assert!(Matrix4::scale(&Vector3::new(1.0, 0.0, 1.0)).inverse().is_none());
+}
+
+#[test]
+fn test_rotation_about_y() {
+    let x = Vector3::new(1.0, 0.0, 0.0);
+    let quarter = Quaternion::from_axis_angle(&Vector3::new(0.0, 1.0, 0.0), 90.0);
+    assert!(quarter.rotate_vector(&x).approx_eq(&Vector3::new(0.0, 0.0, -1.0), 1e-12));
+    let m = Matrix4::rotate(&Vector3::new(0.0, 1.0, 0.0), 90.0);
+    assert!(m.transform_vector(&x).approx_eq(&Vector3::new(0.0, 0.0, -1.0), 1e-12));
+
+    // Two quarter turns make a half turn.
+    let half = (quarter * quarter).rotate_vector(&x);
+    assert!(half.approx_eq(&Vector3::new(-1.0, 0.0, 0.0), 1e-12));
+}
+
+#[test]
+fn test_inverse_round_trips_point() {
+    let m = Matrix4::translate(&Vector3::new(1.0, -2.0, 3.0)) *
+            Matrix4::rotate(&Vector3::new(1.0, 1.0, 0.0), 30.0) *
+            Matrix4::scale(&Vector3::new(2.0, 0.5, 3.0));
+    let p = Point::new(0.3, -1.2, 4.5);
+    let back = m.inverse().unwrap().transform_point(&m.transform_point(&p));
+    assert!(back.approx_eq(&p, 1e-12));
+    let identity = m * m.inverse().unwrap();
+    for (i, row) in identity.m.iter().enumerate() {
+        for (j, value) in row.iter().enumerate() {
+            let expected = if i == j { 1.0 } else { 0.0 };
+            assert!((value - expected).abs() < 1e-12);
+        }
+    }
+}
+
+#[test]
+fn test_polynomial_roots() {
+    // (t - 1)(t - 2)(t - 3)(t - 4) = t^4 - 10t^3 + 35t^2 - 50t + 24
+    let quartic = [1.0, -10.0, 35.0, -50.0, 24.0];
+    let roots = polynomial_roots(&quartic, -10.0, 10.0);
+    assert_eq!(roots.len(), 4);
+    for (root, expected) in roots.iter().zip([1.0, 2.0, 3.0, 4.0]) {
+        assert!((root - expected).abs() < 1e-9);
+    }
+    assert_eq!(polynomial_roots(&quartic, 1.5, 2.5).len(), 1);
+
+    // t^2 + 1 has no real roots.
+    assert!(polynomial_roots(&[1.0, 0.0, 1.0], -10.0, 10.0).is_empty());
+}
+
+#[test]
+fn test_saturate() {
+    assert_eq!(saturate(-0.5), 0.0);
+    assert_eq!(saturate(0.25), 0.25);
+    assert_eq!(saturate(1.5), 1.0);
+}
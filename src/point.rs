@@ -1,7 +1,10 @@
 use std::ops::{Add, Sub};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use crate::vector::Vector3;
 
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Point {
     pub x: f64,
     pub y: f64,
@@ -24,11 +27,64 @@ impl Point {
 
     pub fn new(x: f64, y: f64, z: f64) -> Point {
         Point {
-            x: x,
-            y: y,
-            z: z,
+            x,
+            y,
+            z,
         }
     }
+
+    pub fn distance(&self, other: &Point) -> f64 {
+        (*self - *other).length()
+    }
+
+    pub fn midpoint(&self, other: &Point) -> Point {
+        Point::new((self.x + other.x) / 2.0,
+                   (self.y + other.y) / 2.0,
+                   (self.z + other.z) / 2.0)
+    }
+
+    // True when every coordinate is within `eps` of the other point's.
+    pub fn approx_eq(&self, other: &Point, eps: f64) -> bool {
+        (self.x - other.x).abs() <= eps &&
+        (self.y - other.y).abs() <= eps &&
+        (self.z - other.z).abs() <= eps
+    }
+}
+
+impl From<Vector3> for Point {
+    fn from(v: Vector3) -> Point {
+        Point::new(v.x, v.y, v.z)
+    }
+}
+
+impl From<Point> for Vector3 {
+    fn from(p: Point) -> Vector3 {
+        Vector3::new(p.x, p.y, p.z)
+    }
+}
+
+impl From<[f64; 3]> for Point {
+    fn from(a: [f64; 3]) -> Point {
+        Point::new(a[0], a[1], a[2])
+    }
+}
+
+impl From<Point> for [f64; 3] {
+    fn from(p: Point) -> [f64; 3] {
+        [p.x, p.y, p.z]
+    }
+}
+
+impl From<(f64, f64, f64)> for Point {
+    fn from((x, y, z): (f64, f64, f64)) -> Point {
+        Point::new(x, y, z)
+    }
+}
+
+impl From<Point> for (f64, f64, f64) {
+    fn from(p: Point) -> (f64, f64, f64) {
+        (p.x, p.y, p.z)
+    }
 }
 
 impl Add<Vector3> for Point {
@@ -67,7 +123,11 @@ impl Sub<Point> for Vector3 {
     type Output = Point;
 
     fn sub(self, other: Point) -> Point {
-        self - other
+        Point {
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
+        }
     }
 }
 
@@ -81,4 +141,42 @@ impl Sub<Point> for Point {
             z: self.z - other.z,
         }
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_point_approx_eq() {
+    let p = Point::new(4.0, 0.5, -7.0);
+    let nudged = Point::new(4.0 + 1e-12, 0.5, -7.0 - 1e-12);
+    assert!(p.approx_eq(&nudged, 1e-9));
+    assert!(!p.approx_eq(&nudged, 1e-15));
+}
+
+#[test]
+fn test_point_vector_conversion() {
+    let v: Vector3 = Point::new(1.0, -2.0, 3.5).into();
+    assert_eq!((v.x, v.y, v.z), (1.0, -2.0, 3.5));
+    let p = Point::from(v);
+    assert_eq!((p.x, p.y, p.z), (1.0, -2.0, 3.5));
+}
+
+#[test]
+fn test_distance_and_midpoint() {
+    let a = Point::zero();
+    let b = Point::new(3.0, 4.0, 0.0);
+    assert_eq!(a.distance(&b), 5.0);
+    assert_eq!(b.distance(&a), 5.0);
+
+    let mid = Point::new(-1.0, 2.0, 5.0).midpoint(&Point::new(3.0, -2.0, 6.0));
+    assert_eq!((mid.x, mid.y, mid.z), (1.0, 0.0, 5.5));
+}
+
+#[test]
+fn test_point_array_and_tuple_conversion() {
+    let p = Point::from([1.0, -2.0, 3.5]);
+    assert_eq!((p.x, p.y, p.z), (1.0, -2.0, 3.5));
+    assert_eq!(<[f64; 3]>::from(p), [1.0, -2.0, 3.5]);
+
+    let p: Point = (0.25, 8.0, -4.0).into();
+    assert_eq!((p.x, p.y, p.z), (0.25, 8.0, -4.0));
+    let t: (f64, f64, f64) = p.into();
+    assert_eq!(t, (0.25, 8.0, -4.0));
+}
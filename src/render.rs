@@ -1,6 +1,9 @@
 use crate::point::Point;
 use crate::vector::Vector3;
-use crate::scene::{Scene, Colour, Sphere, Element, Plane};
+use crate::scene::{Scene, Camera, Sphere, Element, Plane, Triangle, Disk, Quad, AxisAlignedBox, Cylinder,
+                   Cone, Torus, Csg, CsgOp, Transformed, Group, Projection};
+use crate::texture::TextureCoords;
+use crate::math::polynomial_roots;
 
 pub struct Ray {
     pub origin: Point,
@@ -9,49 +12,144 @@ pub struct Ray {
 
 impl Ray {
     pub fn create_prime_ray(x: u32, y: u32, scene: &Scene) -> Ray {
-        assert!(scene.width > scene.height);
-        let fov_adjustment = (scene.fov.to_radians() / 2.0).tan();
-        let aspect_ratio = (scene.width as f64) / (scene.height as f64);
-        let sensor_x = ((((x as f64 + 0.5) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio) * fov_adjustment;
-        let sensor_y = (1.0 - ((y as f64 + 0.5) / scene.height as f64) * 2.0) * fov_adjustment;
+        Ray::create_prime_ray_at(x as f64 + 0.5, y as f64 + 0.5, scene)
+    }
 
-        Ray {
-            origin: Point::zero(),
-            direction: Vector3 {
-                x: sensor_x,
-                y: sensor_y,
-                z: -1.0,
+    // Like create_prime_ray, but takes a continuous position on the image so
+    // that samples can be placed anywhere within a pixel.
+    pub fn create_prime_ray_at(x: f64, y: f64, scene: &Scene) -> Ray {
+        Ray::create_lens_ray(x, y, (0.0, 0.0), scene)
+    }
+
+    // Like create_prime_ray_at, but for a perspective camera with an aperture
+    // the ray starts from `lens`, a point on the unit disk scaled to the
+    // aperture, and passes through the point in focus for that pixel.
+    pub fn create_lens_ray(x: f64, y: f64, lens: (f64, f64), scene: &Scene) -> Ray {
+        Ray::create_camera_ray(&scene.camera, scene.width, scene.height, x, y, lens)
+    }
+
+    // Like create_lens_ray, for an image of the given size rather than the scene's.
+    pub fn create_camera_ray(camera: &Camera, width: u32, height: u32,
+                             x: f64, y: f64, lens: (f64, f64)) -> Ray {
+        Ray::create_camera_ray_in(camera, &camera.basis(), width, height, x, y, lens)
+    }
+
+    // Like create_camera_ray, with the camera's `basis` worked out beforehand
+    // so that a render need only do so once.
+    pub fn create_camera_ray_in(camera: &Camera, basis: &(Vector3, Vector3, Vector3),
+                                width: u32, height: u32, x: f64, y: f64, lens: (f64, f64)) -> Ray {
+        // The sensor spans [-1, 1] along the shorter image dimension, so stretch whichever axis is longer.
+        let (aspect_x, aspect_y) = if width >= height {
+            ((width as f64) / (height as f64), 1.0)
+        } else {
+            (1.0, (height as f64) / (width as f64))
+        };
+        let sensor_x = ((x / width as f64) * 2.0 - 1.0) * aspect_x;
+        let sensor_y = (1.0 - (y / height as f64) * 2.0) * aspect_y;
+
+        let (right, up, forward) = *basis;
+        match camera.projection {
+            Projection::Perspective { fov } => {
+                let fov_adjustment = (fov.to_radians() / 2.0).tan();
+                let direction = (right * (sensor_x * fov_adjustment) +
+                                 up * (sensor_y * fov_adjustment) +
+                                 forward)
+                    .normalize();
+                if camera.aperture <= 0.0 {
+                    return Ray {
+                        origin: camera.position,
+                        direction,
+                    };
+                }
+
+                // Points on the focal plane are sharp from every part of the lens.
+                let focus = camera.position +
+                            direction * (camera.focus_distance / direction.dot_prod(&forward));
+                let radius = camera.aperture / 2.0;
+                let origin = camera.position + right * (lens.0 * radius) + up * (lens.1 * radius);
+                Ray {
+                    origin,
+                    direction: (focus - origin).normalize(),
+                }
             }
-            .normalize(),
+            Projection::Orthographic { scale } => Ray {
+                origin: camera.position + right * (sensor_x * scale) + up * (sensor_y * scale),
+                direction: forward,
+            },
         }
     }
 }
 
 pub trait Intersectable {
-    fn intersect(&self, ray: &Ray) -> Option<f64>;
+    // Distance along the ray to the nearest hit no closer than `t_min`, which
+    // lets secondary rays skip the surface they start on.
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64>;
+
+    // `direction` is the direction of the ray that hit `point`, for surfaces
+    // that can be seen from either side.
+    fn surface_normal(&self, point: &Point, direction: &Vector3) -> Vector3;
 
-    fn surface_normal(&self, point: &Point) -> Vector3;
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords;
 }
 
 impl Intersectable for Element {
-    fn intersect(&self, ray: &Ray) -> Option<f64> {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        match *self {
+            Element::Sphere(ref s) => s.intersect(ray, t_min),
+            Element::Plane(ref p) => p.intersect(ray, t_min),
+            Element::Triangle(ref t) => t.intersect(ray, t_min),
+            Element::Disk(ref d) => d.intersect(ray, t_min),
+            Element::Quad(ref q) => q.intersect(ray, t_min),
+            Element::Box(ref b) => b.intersect(ray, t_min),
+            Element::Cylinder(ref c) => c.intersect(ray, t_min),
+            Element::Cone(ref c) => c.intersect(ray, t_min),
+            Element::Torus(ref t) => t.intersect(ray, t_min),
+            Element::Csg(ref c) => c.intersect(ray, t_min),
+            Element::Transformed(ref t) => t.intersect(ray, t_min),
+            Element::Group(ref g) => g.intersect(ray, t_min),
+        }
+    }
+
+    fn surface_normal(&self, hit_point: &Point, direction: &Vector3) -> Vector3 {
         match *self {
-            Element::Sphere(s) => s.intersect(ray),
-            Element::Plane(p) => p.intersect(ray),
+            Element::Sphere(ref s) => s.surface_normal(hit_point, direction),
+            Element::Plane(ref p) => p.surface_normal(hit_point, direction),
+            Element::Triangle(ref t) => t.surface_normal(hit_point, direction),
+            Element::Disk(ref d) => d.surface_normal(hit_point, direction),
+            Element::Quad(ref q) => q.surface_normal(hit_point, direction),
+            Element::Box(ref b) => b.surface_normal(hit_point, direction),
+            Element::Cylinder(ref c) => c.surface_normal(hit_point, direction),
+            Element::Cone(ref c) => c.surface_normal(hit_point, direction),
+            Element::Torus(ref t) => t.surface_normal(hit_point, direction),
+            Element::Csg(ref c) => c.surface_normal(hit_point, direction),
+            Element::Transformed(ref t) => t.surface_normal(hit_point, direction),
+            Element::Group(ref g) => g.surface_normal(hit_point, direction),
         }
     }
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
         match *self {
-            Element::Sphere(s) => s.surface_normal(hit_point),
-            Element::Plane(p) => p.surface_normal(hit_point),
+            Element::Sphere(ref s) => s.texture_coords(hit_point),
+            Element::Plane(ref p) => p.texture_coords(hit_point),
+            Element::Triangle(ref t) => t.texture_coords(hit_point),
+            Element::Disk(ref d) => d.texture_coords(hit_point),
+            Element::Quad(ref q) => q.texture_coords(hit_point),
+            Element::Box(ref b) => b.texture_coords(hit_point),
+            Element::Cylinder(ref c) => c.texture_coords(hit_point),
+            Element::Cone(ref c) => c.texture_coords(hit_point),
+            Element::Torus(ref t) => t.texture_coords(hit_point),
+            Element::Csg(ref c) => c.texture_coords(hit_point),
+            Element::Transformed(ref t) => t.texture_coords(hit_point),
+            Element::Group(ref g) => g.texture_coords(hit_point),
         }
     }
 }
 
 
-impl Intersectable for Sphere {
-    fn intersect(&self, ray: &Ray) -> Option<f64> {
+impl Sphere {
+    // Distances at which the ray's line enters and leaves the sphere; either
+    // may be behind the ray's origin.
+    pub fn intersect_interval(&self, ray: &Ray) -> Option<(f64, f64)> {
         let l: Vector3 = self.center - ray.origin;
         let adj = l.dot_prod(&ray.direction);
         let d2 = l.dot_prod(&l) - (adj * adj);
@@ -60,37 +158,1522 @@ impl Intersectable for Sphere {
             return None;
         }
         let thc = (radius2 - d2).sqrt();
-        let t0 = adj - thc;
-        let t1 = adj + thc;
- 
-        if t0 < 0.0 && t1 < 0.0 {
-            return None;
-        }
- 
-        let distance = if t0 < t1 { t0 } else { t1 };
-        Some(distance)
+        Some((adj - thc, adj + thc))
+    }
+}
+
+impl Intersectable for Sphere {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        // A ray starting inside the sphere has t0 behind it and hits at t1.
+        let (t0, t1) = self.intersect_interval(ray)?;
+        nearest_of_interval(t0, t1, t_min)
     }
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+    fn surface_normal(&self, hit_point: &Point, _direction: &Vector3) -> Vector3 {
         (*hit_point - self.center).normalize()
     }
+
+    // Longitude around the Y axis maps to u and latitude from the north pole to v.
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let hit_vec = *hit_point - self.center;
+        TextureCoords {
+            u: ((1.0 + hit_vec.z.atan2(hit_vec.x) / std::f64::consts::PI) * 0.5) as f32,
+            v: ((hit_vec.y / self.radius).clamp(-1.0, 1.0).acos() / std::f64::consts::PI) as f32,
+        }
+    }
  }
 
+// Distance along the ray to the infinite plane through `origin`, hit from either side.
+fn intersect_plane(origin: &Point, normal: &Vector3, ray: &Ray, t_min: f64) -> Option<f64> {
+    let denom = normal.dot_prod(&ray.direction);
+    if denom.abs() > 1e-6 {
+        let v = *origin - ray.origin;
+        let distance = v.dot_prod(normal) / denom;
+        if distance >= t_min {
+            return Some(distance);
+        }
+    }
+    None
+}
+
+// Flips a two-sided surface's normal to face the incoming ray.
+fn face_forward(normal: &Vector3, direction: &Vector3) -> Vector3 {
+    if normal.dot_prod(direction) > 0.0 {
+        -*normal
+    } else {
+        *normal
+    }
+}
+
+// The first of a solid's entry and exit distances that is no closer than `t_min`.
+fn nearest_of_interval(t_enter: f64, t_exit: f64, t_min: f64) -> Option<f64> {
+    if t_exit < t_min {
+        None
+    } else if t_enter < t_min {
+        Some(t_exit)
+    } else {
+        Some(t_enter)
+    }
+}
+
+// Two perpendicular unit vectors lying in the plane with the given normal.
+fn plane_axes(normal: &Vector3) -> (Vector3, Vector3) {
+    let mut x_axis = normal.cross_prod(&Vector3::new(0.0, 0.0, 1.0));
+    if x_axis.length() == 0.0 {
+        x_axis = normal.cross_prod(&Vector3::new(0.0, 1.0, 0.0));
+    }
+    let x_axis = x_axis.normalize();
+    let y_axis = normal.cross_prod(&x_axis).normalize();
+    (x_axis, y_axis)
+}
+
+impl Element {
+    // A unit vector along the surface at `hit_point` pointing the way u
+    // increases, perpendicular to `normal`. Spheres, planes, disks and quads follow
+    // their texture coordinates; other shapes get an arbitrary direction.
+    pub fn tangent(&self, hit_point: &Point, normal: &Vector3) -> Vector3 {
+        match *self {
+            Element::Sphere(ref s) => {
+                let hit_vec = *hit_point - s.center;
+                let around = Vector3::new(-hit_vec.z, 0.0, hit_vec.x);
+                if around.length() > 1e-9 {
+                    return around.normalize();
+                }
+            }
+            Element::Plane(ref p) => return plane_axes(&p.normal).0,
+            Element::Disk(ref d) => return plane_axes(&d.normal).0,
+            Element::Quad(ref q) => return q.edge_u.normalize(),
+            _ => {}
+        }
+        plane_axes(normal).0
+    }
+}
+
 impl Intersectable for Plane {
-    fn intersect(&self, ray: &Ray) -> Option<f64> {
-        let normal = &self.normal;
-        let denom = normal.dot_prod(&ray.direction);
-        if denom > 1e-6 {
-            let v = self.origin - ray.origin;
-            let distance = v.dot_prod(&normal) / denom;
-            if distance >= 0.0 {
-                return Some(distance);
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        intersect_plane(&self.origin, &self.normal, ray, t_min)
+    }
+
+    // Planes are two-sided: the normal is flipped to face the incoming ray.
+    fn surface_normal(&self, _hit_point: &Point, direction: &Vector3) -> Vector3 {
+        face_forward(&self.normal, direction)
+    }
+
+    // Distances along two axes lying in the plane, measured from its origin.
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let (x_axis, y_axis) = plane_axes(&self.normal);
+        let hit_vec = *hit_point - self.origin;
+        TextureCoords {
+            u: hit_vec.dot_prod(&x_axis) as f32,
+            v: hit_vec.dot_prod(&y_axis) as f32,
+        }
+    }
+}
+
+impl Intersectable for Disk {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        let distance = intersect_plane(&self.center, &self.normal, ray, t_min)?;
+        let hit_point = ray.origin + ray.direction * distance;
+        if (hit_point - self.center).norm() <= self.radius * self.radius {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    fn surface_normal(&self, _hit_point: &Point, direction: &Vector3) -> Vector3 {
+        face_forward(&self.normal.normalize(), direction)
+    }
+
+    // The disk's bounding square mapped onto [0, 1] in each direction.
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let (x_axis, y_axis) = plane_axes(&self.normal);
+        let hit_vec = *hit_point - self.center;
+        TextureCoords {
+            u: ((hit_vec.dot_prod(&x_axis) / self.radius + 1.0) / 2.0) as f32,
+            v: ((hit_vec.dot_prod(&y_axis) / self.radius + 1.0) / 2.0) as f32,
+        }
+    }
+}
+
+impl Quad {
+    // Coordinates of a point in the quad's plane along its two edges, with
+    // the quad itself covering [0, 1] in each.
+    fn edge_coords(&self, point: &Point) -> (f64, f64) {
+        let n = self.edge_u.cross_prod(&self.edge_v);
+        let w = n / n.norm();
+        let d = *point - self.corner;
+        (w.dot_prod(&d.cross_prod(&self.edge_v)), w.dot_prod(&self.edge_u.cross_prod(&d)))
+    }
+}
+
+impl Intersectable for Quad {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        let normal = self.edge_u.cross_prod(&self.edge_v);
+        let distance = intersect_plane(&self.corner, &normal, ray, t_min)?;
+        let (u, v) = self.edge_coords(&(ray.origin + ray.direction * distance));
+        if (0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v) {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    fn surface_normal(&self, _hit_point: &Point, direction: &Vector3) -> Vector3 {
+        face_forward(&self.edge_u.cross_prod(&self.edge_v).normalize(), direction)
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let (u, v) = self.edge_coords(hit_point);
+        TextureCoords {
+            u: u as f32,
+            v: v as f32,
+        }
+    }
+}
+
+impl AxisAlignedBox {
+    // Index of the axis whose face the point lies on, and whether it is the max face.
+    fn face_at(&self, point: &Point) -> (usize, bool) {
+        let faces = [
+            (point.x - self.min.x, self.max.x - point.x),
+            (point.y - self.min.y, self.max.y - point.y),
+            (point.z - self.min.z, self.max.z - point.z),
+        ];
+        let mut nearest = (0, false);
+        let mut nearest_distance = f64::INFINITY;
+        for (axis, &(to_min, to_max)) in faces.iter().enumerate() {
+            if to_min.abs() < nearest_distance {
+                nearest = (axis, false);
+                nearest_distance = to_min.abs();
+            }
+            if to_max.abs() < nearest_distance {
+                nearest = (axis, true);
+                nearest_distance = to_max.abs();
             }
         }
-        None
+        nearest
+    }
+}
+
+impl AxisAlignedBox {
+    // Slab method. Distances at which the ray's line enters and leaves the
+    // box; either may be behind the ray's origin.
+    pub fn intersect_interval(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let mut t_near = f64::NEG_INFINITY;
+        let mut t_far = f64::INFINITY;
+
+        let slabs = [
+            (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+        ];
+        for &(origin, direction, min, max) in &slabs {
+            if direction == 0.0 {
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+            let mut t0 = (min - origin) / direction;
+            let mut t1 = (max - origin) / direction;
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_near = t_near.max(t0);
+            t_far = t_far.min(t1);
+        }
+
+        if t_far < t_near {
+            None
+        } else {
+            Some((t_near, t_far))
+        }
+    }
+}
+
+impl Intersectable for AxisAlignedBox {
+    // A ray starting inside the box hits the face it leaves through.
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        let (t_near, t_far) = self.intersect_interval(ray)?;
+        nearest_of_interval(t_near, t_far, t_min)
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _direction: &Vector3) -> Vector3 {
+        let (axis, is_max) = self.face_at(hit_point);
+        let sign = if is_max { 1.0 } else { -1.0 };
+        match axis {
+            0 => Vector3::new(sign, 0.0, 0.0),
+            1 => Vector3::new(0.0, sign, 0.0),
+            _ => Vector3::new(0.0, 0.0, sign),
+        }
+    }
+
+    // Each face is mapped onto [0, 1] along its two in-plane axes.
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let size = self.max - self.min;
+        let local = *hit_point - self.min;
+        let (u, v) = match self.face_at(hit_point).0 {
+            0 => (local.z / size.z, local.y / size.y),
+            1 => (local.x / size.x, local.z / size.z),
+            _ => (local.x / size.x, local.y / size.y),
+        };
+        TextureCoords {
+            u: u as f32,
+            v: v as f32,
+        }
+    }
+}
+
+impl Cylinder {
+    // Distances at which the ray's line enters and leaves the solid cylinder:
+    // the overlap of its spans inside the round side and between the caps.
+    pub fn intersect_interval(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let axis = self.axis.normalize();
+        let offset = ray.origin - self.base;
+        let d_along = ray.direction.dot_prod(&axis);
+        let o_along = offset.dot_prod(&axis);
+        let d_perp = ray.direction - axis * d_along;
+        let o_perp = offset - axis * o_along;
+
+        let a = d_perp.norm();
+        let c = o_perp.norm() - self.radius * self.radius;
+        let (side_in, side_out) = if a > 1e-12 {
+            let b = 2.0 * d_perp.dot_prod(&o_perp);
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant < 0.0 {
+                return None;
+            }
+            let root = discriminant.sqrt();
+            ((-b - root) / (2.0 * a), (-b + root) / (2.0 * a))
+        } else if c <= 0.0 {
+            (f64::NEG_INFINITY, f64::INFINITY)
+        } else {
+            return None;
+        };
+
+        let (caps_in, caps_out) = if d_along.abs() > 1e-12 {
+            let t0 = -o_along / d_along;
+            let t1 = (self.height - o_along) / d_along;
+            (t0.min(t1), t0.max(t1))
+        } else if (0.0..=self.height).contains(&o_along) {
+            (f64::NEG_INFINITY, f64::INFINITY)
+        } else {
+            return None;
+        };
+
+        let (t_in, t_out) = (side_in.max(caps_in), side_out.min(caps_out));
+        if t_in <= t_out {
+            Some((t_in, t_out))
+        } else {
+            None
+        }
+    }
+}
+
+impl Intersectable for Cylinder {
+    // A ray starting inside the cylinder hits the side or cap it leaves through.
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        let (t_in, t_out) = self.intersect_interval(ray)?;
+        nearest_of_interval(t_in, t_out, t_min)
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _direction: &Vector3) -> Vector3 {
+        let axis = self.axis.normalize();
+        let offset = *hit_point - self.base;
+        let along = offset.dot_prod(&axis);
+        if along <= 1e-6 {
+            -axis
+        } else if along >= self.height - 1e-6 {
+            axis
+        } else {
+            (offset - axis * along).normalize()
+        }
+    }
+
+    // Angle around the axis maps to u and height up the side to v.
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let axis = self.axis.normalize();
+        let (x_axis, y_axis) = plane_axes(&axis);
+        let offset = *hit_point - self.base;
+        let angle = offset.dot_prod(&y_axis).atan2(offset.dot_prod(&x_axis));
+        TextureCoords {
+            u: ((1.0 + angle / std::f64::consts::PI) * 0.5) as f32,
+            v: (offset.dot_prod(&axis) / self.height) as f32,
+        }
     }
+}
+
+impl Intersectable for Cone {
+    // Intersects the infinite double cone around the axis, keeps hits on the
+    // near half within the height, and also tries the base.
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        let axis = self.axis.normalize();
+        let offset = ray.origin - self.apex;
+        let d_along = ray.direction.dot_prod(&axis);
+        let o_along = offset.dot_prod(&axis);
+        let cos2 = self.half_angle.to_radians().cos().powi(2);
+
+        let mut nearest: Option<f64> = None;
+        let mut consider = |t: f64| {
+            if t >= t_min && nearest.is_none_or(|n| t < n) {
+                nearest = Some(t);
+            }
+        };
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
-        -self.normal
+        // Points on the cone make the angle to the axis the half-angle:
+        // ((p - apex) . axis)^2 = cos^2 * |p - apex|^2.
+        let a = d_along * d_along - cos2 * ray.direction.norm();
+        let b = 2.0 * (d_along * o_along - cos2 * ray.direction.dot_prod(&offset));
+        let c = o_along * o_along - cos2 * offset.norm();
+        let roots = if a.abs() > 1e-12 {
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant >= 0.0 {
+                let root = discriminant.sqrt();
+                vec![(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)]
+            } else {
+                Vec::new()
+            }
+        } else if b.abs() > 1e-12 {
+            // The ray runs parallel to the side and crosses it just once.
+            vec![-c / b]
+        } else {
+            Vec::new()
+        };
+        for t in roots {
+            let along = o_along + d_along * t;
+            if (0.0..=self.height).contains(&along) {
+                consider(t);
+            }
+        }
+
+        if d_along.abs() > 1e-12 {
+            let t = (self.height - o_along) / d_along;
+            let base_radius = self.height * self.half_angle.to_radians().tan();
+            let from_axis = offset + ray.direction * t - axis * self.height;
+            if from_axis.norm() <= base_radius * base_radius {
+                consider(t);
+            }
+        }
+        nearest
+    }
+
+    // On the side the normal leans back from the axis by the half-angle.
+    fn surface_normal(&self, hit_point: &Point, _direction: &Vector3) -> Vector3 {
+        let axis = self.axis.normalize();
+        let offset = *hit_point - self.apex;
+        let along = offset.dot_prod(&axis);
+        let perp = offset - axis * along;
+        if along >= self.height - 1e-6 {
+            return axis;
+        }
+        if perp.norm() < 1e-12 {
+            return -axis;
+        }
+        let angle = self.half_angle.to_radians();
+        (perp.normalize() * angle.cos() - axis * angle.sin()).normalize()
+    }
+
+    // Angle around the axis maps to u and distance from the apex to v.
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let axis = self.axis.normalize();
+        let (x_axis, y_axis) = plane_axes(&axis);
+        let offset = *hit_point - self.apex;
+        let angle = offset.dot_prod(&y_axis).atan2(offset.dot_prod(&x_axis));
+        TextureCoords {
+            u: ((1.0 + angle / std::f64::consts::PI) * 0.5) as f32,
+            v: (offset.dot_prod(&axis) / self.height) as f32,
+        }
+    }
+}
+
+impl Intersectable for Torus {
+    // Substituting the ray into the torus's implicit equation
+    // (|p|^2 + R^2 - r^2)^2 = 4R^2 (|p|^2 - (p . axis)^2), with p relative to
+    // the centre, gives a quartic in the distance along the ray. Its roots are
+    // searched for only where the ray is within the torus's bounding sphere.
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        let axis = self.axis.normalize();
+        let offset = ray.origin - self.center;
+        let (dd, od, oo) = (ray.direction.norm(), ray.direction.dot_prod(&offset), offset.norm());
+
+        let bounding_radius = self.major_radius + self.minor_radius;
+        let discriminant = od * od - dd * (oo - bounding_radius * bounding_radius);
+        if discriminant < 0.0 {
+            return None;
+        }
+        let (enter, exit) = ((-od - discriminant.sqrt()) / dd, (-od + discriminant.sqrt()) / dd);
+        if exit < t_min {
+            return None;
+        }
+
+        let (da, oa) = (ray.direction.dot_prod(&axis), offset.dot_prod(&axis));
+        let major2 = self.major_radius * self.major_radius;
+        let (a, b, c) = (dd, 2.0 * od, oo + major2 - self.minor_radius * self.minor_radius);
+        let coefficients = [
+            a * a,
+            2.0 * a * b,
+            b * b + 2.0 * a * c - 4.0 * major2 * (dd - da * da),
+            2.0 * b * c - 8.0 * major2 * (od - oa * da),
+            c * c - 4.0 * major2 * (oo - oa * oa),
+        ];
+        polynomial_roots(&coefficients, enter.max(t_min), exit).first().copied()
+    }
+
+    // The gradient of the implicit function, which points straight out of
+    // the tube.
+    fn surface_normal(&self, hit_point: &Point, _direction: &Vector3) -> Vector3 {
+        let axis = self.axis.normalize();
+        let p = *hit_point - self.center;
+        let major2 = self.major_radius * self.major_radius;
+        let sum = p.norm() + major2 - self.minor_radius * self.minor_radius;
+        (p * (4.0 * sum) - (p - axis * p.dot_prod(&axis)) * (8.0 * major2)).normalize()
+    }
+
+    // u goes around the axis and v around the tube, starting from its
+    // outside edge.
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let axis = self.axis.normalize();
+        let (x_axis, y_axis) = plane_axes(&axis);
+        let p = *hit_point - self.center;
+        let around = p.dot_prod(&y_axis).atan2(p.dot_prod(&x_axis));
+        let radial = (p - axis * p.dot_prod(&axis)).length() - self.major_radius;
+        let tube = p.dot_prod(&axis).atan2(radial);
+        TextureCoords {
+            u: ((1.0 + around / std::f64::consts::PI) * 0.5) as f32,
+            v: ((1.0 + tube / std::f64::consts::PI) * 0.5) as f32,
+        }
+    }
+}
+
+// A point where a ray crosses the surface of a solid, and the element whose
+// surface it is. `flip` marks surfaces whose normal points into the solid,
+// as where a subtracted shape has been carved away.
+#[derive(Clone, Copy)]
+struct Boundary<'a> {
+    t: f64,
+    element: &'a Element,
+    flip: bool,
+}
+
+// A span of a ray inside a solid, from where it enters to where it leaves.
+#[derive(Clone, Copy)]
+struct Span<'a> {
+    enter: Boundary<'a>,
+    exit: Boundary<'a>,
+}
+
+impl Element {
+    // The spans of the ray's line lying inside the element, in order along
+    // the ray. Only spheres, boxes, cylinders and CSG shapes are solids;
+    // other elements have no inside.
+    fn spans(&self, ray: &Ray) -> Vec<Span<'_>> {
+        let interval = match *self {
+            Element::Sphere(ref s) => s.intersect_interval(ray),
+            Element::Box(ref b) => b.intersect_interval(ray),
+            Element::Cylinder(ref c) => c.intersect_interval(ray),
+            Element::Csg(ref c) => return c.spans(ray),
+            _ => None,
+        };
+        let boundary = |t| Boundary {
+            t,
+            element: self,
+            flip: false,
+        };
+        interval.into_iter()
+            .map(|(t0, t1)| Span {
+                enter: boundary(t0),
+                exit: boundary(t1),
+            })
+            .collect()
+    }
+}
+
+impl Csg {
+    // Sweeps along the ray through the boundaries of both children, tracking
+    // whether the ray is inside each, and keeps the boundaries where the
+    // combined shape's inside begins or ends.
+    fn spans(&self, ray: &Ray) -> Vec<Span<'_>> {
+        let mut events: Vec<(Boundary, bool)> = Vec::new();
+        for span in self.left.spans(ray) {
+            events.push((span.enter, true));
+            events.push((span.exit, true));
+        }
+        for span in self.right.spans(ray) {
+            events.push((span.enter, false));
+            events.push((span.exit, false));
+        }
+        events.sort_by(|a, b| a.0.t.partial_cmp(&b.0.t).unwrap());
+
+        let (mut in_left, mut in_right) = (false, false);
+        let mut enter: Option<Boundary> = None;
+        let mut spans = Vec::new();
+        for (boundary, from_left) in events {
+            if from_left {
+                in_left = !in_left;
+            } else {
+                in_right = !in_right;
+            }
+            let inside = match self.op {
+                CsgOp::Union => in_left || in_right,
+                CsgOp::Intersection => in_left && in_right,
+                CsgOp::Difference => in_left && !in_right,
+            };
+            // The subtracted shape's surface faces into what is left.
+            let boundary = Boundary {
+                flip: boundary.flip != (self.op == CsgOp::Difference && !from_left),
+                ..boundary
+            };
+            match enter {
+                None if inside => enter = Some(boundary),
+                Some(start) if !inside => {
+                    spans.push(Span {
+                        enter: start,
+                        exit: boundary,
+                    });
+                    enter = None;
+                }
+                _ => {}
+            }
+        }
+        spans
+    }
+
+    fn boundaries(&self, ray: &Ray) -> impl Iterator<Item = Boundary<'_>> {
+        self.spans(ray).into_iter().flat_map(|span| [span.enter, span.exit])
+    }
+}
+
+impl Intersectable for Csg {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        self.boundaries(ray).map(|b| b.t).find(|&t| t >= t_min)
+    }
+
+    // The normal of whichever child's surface the point lies on, found by
+    // tracing back along the ray to the boundary nearest the point.
+    fn surface_normal(&self, hit_point: &Point, direction: &Vector3) -> Vector3 {
+        let ray = Ray {
+            origin: *hit_point - *direction,
+            direction: *direction,
+        };
+        let length = direction.length();
+        let nearest = self.boundaries(&ray)
+            .min_by(|a, b| (a.t - length).abs().partial_cmp(&(b.t - length).abs()).unwrap());
+        match nearest {
+            Some(b) => {
+                let normal = b.element.surface_normal(hit_point, direction);
+                if b.flip { -normal } else { normal }
+            }
+            None => -direction.normalize(),
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        self.left.texture_coords(hit_point)
+    }
+}
+
+impl Transformed {
+    // The ray in the element's own coordinates, with a unit direction, and the
+    // factor converting distances along it back to distances along `ray`.
+    fn object_ray(&self, ray: &Ray) -> (Ray, f64) {
+        let direction = self.inverse().transform_vector(&ray.direction);
+        let length = direction.length();
+        let object_ray = Ray {
+            origin: self.inverse().transform_point(&ray.origin),
+            direction: direction / length,
+        };
+        (object_ray, length)
+    }
+}
+
+impl Intersectable for Transformed {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        let (object_ray, length) = self.object_ray(ray);
+        self.element.intersect(&object_ray, t_min * length).map(|t| t / length)
+    }
+
+    // Normals transform by the inverse transpose, which keeps them
+    // perpendicular to surfaces under non-uniform scaling.
+    fn surface_normal(&self, hit_point: &Point, direction: &Vector3) -> Vector3 {
+        let object_point = self.inverse().transform_point(hit_point);
+        let object_direction = self.inverse().transform_vector(direction);
+        let normal = self.element.surface_normal(&object_point, &object_direction);
+        self.inverse().transpose().transform_vector(&normal).normalize()
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        self.element.texture_coords(&self.inverse().transform_point(hit_point))
+    }
+}
+
+impl Element {
+    // The element actually hit at `distance` along the ray: for a group, the
+    // child hit there; otherwise this one.
+    pub fn hit_at(&self, ray: &Ray, distance: f64) -> &Element {
+        match *self {
+            Element::Group(ref g) => g.child_at(ray, distance).unwrap_or(self),
+            _ => self,
+        }
     }
-}
\ No newline at end of file
+}
+
+impl Group {
+    // The child whose surface lies nearest `distance` along the ray, looking
+    // into nested groups.
+    fn child_at(&self, ray: &Ray, distance: f64) -> Option<&Element> {
+        let t_min = distance - 1e-9 * distance.abs().max(1.0);
+        self.elements.iter()
+            .filter_map(|e| e.intersect(ray, t_min).map(|t| (t, e)))
+            .min_by(|a, b| (a.0 - distance).abs().partial_cmp(&(b.0 - distance).abs()).unwrap())
+            .map(|(_, e)| e.hit_at(ray, distance))
+    }
+}
+
+impl Intersectable for Group {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        self.elements.iter()
+            .filter_map(|e| e.intersect(ray, t_min))
+            .min_by(|a, b| a.partial_cmp(b).unwrap())
+    }
+
+    // The normal of the child found by tracing back along the ray.
+    fn surface_normal(&self, hit_point: &Point, direction: &Vector3) -> Vector3 {
+        let ray = Ray {
+            origin: *hit_point - *direction,
+            direction: *direction,
+        };
+        match self.child_at(&ray, direction.length()) {
+            Some(child) => child.surface_normal(hit_point, direction),
+            None => -direction.normalize(),
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        self.elements.first().map_or(TextureCoords { u: 0.0, v: 0.0 }, |e| e.texture_coords(hit_point))
+    }
+}
+
+impl Intersectable for Triangle {
+    // Möller–Trumbore. Unlike planes, only one side is hit: the triangle is
+    // visible when its vertices wind counter-clockwise as seen by the ray.
+    fn intersect(&self, ray: &Ray, t_min: f64) -> Option<f64> {
+        let edge1 = self.b - self.a;
+        let edge2 = self.c - self.a;
+        let pvec = ray.direction.cross_prod(&edge2);
+        let det = edge1.dot_prod(&pvec);
+        if det < 1e-6 {
+            return None;
+        }
+
+        let tvec = ray.origin - self.a;
+        let u = tvec.dot_prod(&pvec) / det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let qvec = tvec.cross_prod(&edge1);
+        let v = ray.direction.dot_prod(&qvec) / det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge2.dot_prod(&qvec) / det;
+        if distance >= t_min {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    // With vertex normals, their blend by the hit point's barycentric weights
+    // (Phong shading).
+    fn surface_normal(&self, hit_point: &Point, _direction: &Vector3) -> Vector3 {
+        match self.normals {
+            Some([a, b, c]) => {
+                let (u, v) = self.barycentric(hit_point);
+                (a * (1.0 - u - v) + b * u + c * v).normalize()
+            }
+            None => (self.b - self.a).cross_prod(&(self.c - self.a)).normalize(),
+        }
+    }
+
+    // Barycentric weights of b and c, so the vertices map to (0, 0), (1, 0) and (0, 1).
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let (u, v) = self.barycentric(hit_point);
+        TextureCoords {
+            u: u as f32,
+            v: v as f32,
+        }
+    }
+}
+
+impl Triangle {
+    // The weights of b and c at a point on the triangle; a's is what remains.
+    fn barycentric(&self, point: &Point) -> (f64, f64) {
+        let edge1 = self.b - self.a;
+        let edge2 = self.c - self.a;
+        let hit_vec = *point - self.a;
+        let d11 = edge1.dot_prod(&edge1);
+        let d12 = edge1.dot_prod(&edge2);
+        let d22 = edge2.dot_prod(&edge2);
+        let dh1 = hit_vec.dot_prod(&edge1);
+        let dh2 = hit_vec.dot_prod(&edge2);
+        let denom = d11 * d22 - d12 * d12;
+        ((d22 * dh1 - d12 * dh2) / denom, (d11 * dh2 - d12 * dh1) / denom)
+    }
+}
+
+
+#[test]
+fn test_prime_ray_square_center_points_down_negative_z() {
+    let scene = Scene {
+        width: 101,
+        height: 101,
+        ..Scene::default()
+    };
+
+    let ray = Ray::create_prime_ray(50, 50, &scene);
+    assert!(ray.direction.x.abs() < 1e-12);
+    assert!(ray.direction.y.abs() < 1e-12);
+    assert!((ray.direction.z + 1.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_prime_ray_portrait_matches_rotated_landscape() {
+    let landscape = Scene {
+        width: 800,
+        height: 600,
+        ..Scene::default()
+    };
+    let portrait = Scene {
+        width: 600,
+        height: 800,
+        ..Scene::default()
+    };
+
+    // The top-left corner of a portrait image should see as far up as the
+    // landscape image sees to the left.
+    let l = Ray::create_prime_ray(0, 299, &landscape);
+    let p = Ray::create_prime_ray(299, 0, &portrait);
+    assert!((l.direction.x + p.direction.y).abs() < 1e-12);
+    assert!((l.direction.y + p.direction.x).abs() < 1e-12);
+    assert!((l.direction.z - p.direction.z).abs() < 1e-12);
+}
+
+#[test]
+fn test_default_camera_matches_legacy_rays() {
+    let scene = Scene {
+        width: 64,
+        height: 48,
+        ..Scene::default()
+    };
+
+    let fov_adjustment = (90.0f64.to_radians() / 2.0).tan();
+    let aspect_ratio = 64.0 / 48.0;
+    for &(x, y) in &[(0, 0), (63, 47), (10, 30), (32, 24)] {
+        let sensor_x = (((x as f64 + 0.5) / 64.0) * 2.0 - 1.0) * aspect_ratio * fov_adjustment;
+        let sensor_y = (1.0 - ((y as f64 + 0.5) / 48.0) * 2.0) * fov_adjustment;
+        let legacy = Vector3::new(sensor_x, sensor_y, -1.0).normalize();
+
+        let ray = Ray::create_prime_ray(x, y, &scene);
+        assert_eq!((ray.origin.x, ray.origin.y, ray.origin.z), (0.0, 0.0, 0.0));
+        assert!((ray.direction - legacy).length() < 1e-12);
+    }
+}
+
+#[test]
+fn test_camera_looks_at_target() {
+    let scene = Scene {
+        width: 101,
+        height: 101,
+        camera: Camera {
+            position: Point::new(10.0, 0.0, -5.0),
+            look_at: Point::new(0.0, 0.0, -5.0),
+            ..Camera::default()
+        },
+        ..Scene::default()
+    };
+
+    let ray = Ray::create_prime_ray(50, 50, &scene);
+    assert!((ray.direction - Vector3::new(-1.0, 0.0, 0.0)).length() < 1e-12);
+
+    // Pixels to the right of centre look towards -Z when facing -X.
+    let ray = Ray::create_prime_ray(100, 50, &scene);
+    assert!(ray.direction.z < 0.0);
+}
+
+#[test]
+fn test_zero_aperture_matches_pinhole() {
+    let pinhole = Scene::default();
+    let lens = Scene {
+        camera: Camera {
+            aperture: 0.0,
+            focus_distance: 3.0,
+            ..Camera::default()
+        },
+        ..Scene::default()
+    };
+
+    for &(x, y) in &[(0.0, 0.0), (400.0, 300.0), (123.25, 567.5)] {
+        let expected = Ray::create_prime_ray_at(x, y, &pinhole);
+        let ray = Ray::create_lens_ray(x, y, (0.7, -0.3), &lens);
+        assert_eq!((ray.origin.x, ray.origin.y, ray.origin.z),
+                   (expected.origin.x, expected.origin.y, expected.origin.z));
+        assert_eq!((ray.direction.x, ray.direction.y, ray.direction.z),
+                   (expected.direction.x, expected.direction.y, expected.direction.z));
+    }
+}
+
+#[test]
+fn test_lens_rays_converge_at_focus_distance() {
+    let scene = Scene {
+        camera: Camera {
+            aperture: 0.5,
+            focus_distance: 4.0,
+            ..Camera::default()
+        },
+        ..Scene::default()
+    };
+
+    let centre = Ray::create_lens_ray(200.0, 150.0, (0.0, 0.0), &scene);
+    let edge = Ray::create_lens_ray(200.0, 150.0, (1.0, 0.0), &scene);
+    let focus_at = |ray: &Ray| ray.origin + ray.direction * (-4.0 / ray.direction.z);
+    assert!(focus_at(&centre).approx_eq(&focus_at(&edge), 1e-9));
+    assert!(!edge.origin.approx_eq(&centre.origin, 1e-3));
+}
+
+#[test]
+fn test_orthographic_rays_are_parallel() {
+    let scene = Scene {
+        width: 64,
+        height: 48,
+        camera: Camera {
+            projection: Projection::Orthographic { scale: 2.0 },
+            ..Camera::default()
+        },
+        ..Scene::default()
+    };
+
+    let a = Ray::create_prime_ray(3, 7, &scene);
+    let b = Ray::create_prime_ray(50, 40, &scene);
+    assert_eq!((a.direction.x, a.direction.y, a.direction.z), (0.0, 0.0, -1.0));
+    assert_eq!((b.direction.x, b.direction.y, b.direction.z), (0.0, 0.0, -1.0));
+    assert!(a.origin.x < b.origin.x);
+    assert!(a.origin.y > b.origin.y);
+}
+
+#[test]
+fn test_plane_normal_faces_ray() {
+    use crate::scene::{Colour, Material};
+
+    // A floor declared with its normal pointing up, seen from above and below.
+    let floor = Plane {
+        origin: Point::new(0.0, -2.0, 0.0),
+        normal: Vector3::new(0.0, 1.0, 0.0),
+        material: Material::diffuse(Colour::black(), 0.18),
+    };
+
+    let from_above = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, -1.0, -1.0).normalize(),
+    };
+    let distance = floor.intersect(&from_above, 0.0).unwrap();
+    let hit_point = from_above.origin + from_above.direction * distance;
+    let normal = floor.surface_normal(&hit_point, &from_above.direction);
+    assert_eq!((normal.x, normal.y, normal.z), (0.0, 1.0, 0.0));
+
+    let from_below = Ray {
+        origin: Point::new(0.0, -4.0, 0.0),
+        direction: Vector3::new(0.0, 1.0, -1.0).normalize(),
+    };
+    let distance = floor.intersect(&from_below, 0.0).unwrap();
+    let hit_point = from_below.origin + from_below.direction * distance;
+    let normal = floor.surface_normal(&hit_point, &from_below.direction);
+    assert_eq!((normal.x, normal.y, normal.z), (0.0, -1.0, 0.0));
+}
+
+#[cfg(test)]
+fn test_disk() -> Disk {
+    use crate::scene::{Colour, Material};
+
+    Disk {
+        center: Point::new(0.0, 0.0, -5.0),
+        normal: Vector3::new(0.0, 0.0, 1.0),
+        radius: 1.0,
+        material: Material::diffuse(Colour::black(), 0.18),
+    }
+}
+
+#[test]
+fn test_disk_hit_inside_radius() {
+    let ray = Ray {
+        origin: Point::new(0.5, 0.5, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(test_disk().intersect(&ray, 0.0), Some(5.0));
+}
+
+#[test]
+fn test_disk_miss_outside_radius() {
+    let ray = Ray {
+        origin: Point::new(0.8, 0.8, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(test_disk().intersect(&ray, 0.0), None);
+}
+
+#[test]
+fn test_disk_miss_parallel() {
+    let ray = Ray {
+        origin: Point::new(-2.0, 0.0, -5.0),
+        direction: Vector3::new(1.0, 0.0, 0.0),
+    };
+    assert_eq!(test_disk().intersect(&ray, 0.0), None);
+}
+
+#[cfg(test)]
+fn test_quad() -> Quad {
+    use crate::scene::{Colour, Material};
+
+    // A 2 x 1 rectangle in the plane z = -5 with its lower left corner at the origin.
+    Quad {
+        corner: Point::new(0.0, 0.0, -5.0),
+        edge_u: Vector3::new(2.0, 0.0, 0.0),
+        edge_v: Vector3::new(0.0, 1.0, 0.0),
+        material: Material::diffuse(Colour::black(), 0.18),
+    }
+}
+
+#[test]
+fn test_quad_hit_inside() {
+    let ray = Ray {
+        origin: Point::new(1.5, 0.25, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(test_quad().intersect(&ray, 0.0), Some(5.0));
+    let coords = test_quad().texture_coords(&Point::new(1.5, 0.25, -5.0));
+    assert_eq!((coords.u, coords.v), (0.75, 0.25));
+
+    // Seen from behind, the normal still faces the ray.
+    let behind = Vector3::new(0.0, 0.0, 1.0);
+    let normal = test_quad().surface_normal(&Point::new(1.5, 0.25, -5.0), &behind);
+    assert_eq!((normal.x, normal.y, normal.z), (0.0, 0.0, -1.0));
+}
+
+#[test]
+fn test_quad_miss_outside_each_edge() {
+    let quad = test_quad();
+    for (x, y) in [(-0.01, 0.5), (2.01, 0.5), (1.0, -0.01), (1.0, 1.01)] {
+        let ray = Ray {
+            origin: Point::new(x, y, 0.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        assert_eq!(quad.intersect(&ray, 0.0), None, "hit at ({}, {})", x, y);
+    }
+}
+
+#[cfg(test)]
+fn test_box() -> AxisAlignedBox {
+    use crate::scene::{Colour, Material};
+
+    AxisAlignedBox {
+        min: Point::new(-1.0, -1.0, -6.0),
+        max: Point::new(1.0, 1.0, -4.0),
+        material: Material::diffuse(Colour::black(), 0.18),
+    }
+}
+
+#[test]
+fn test_box_hit_face_head_on() {
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    let aabb = test_box();
+    assert_eq!(aabb.intersect(&ray, 0.0), Some(4.0));
+    let normal = aabb.surface_normal(&Point::new(0.0, 0.0, -4.0), &ray.direction);
+    assert_eq!((normal.x, normal.y, normal.z), (0.0, 0.0, 1.0));
+}
+
+#[test]
+fn test_box_hit_grazing_edge() {
+    let ray = Ray {
+        origin: Point::new(1.0, 1.0, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(test_box().intersect(&ray, 0.0), Some(4.0));
+
+    let beside = Ray {
+        origin: Point::new(1.0 + 1e-9, 1.0, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(test_box().intersect(&beside, 0.0), None);
+}
+
+#[test]
+fn test_box_hit_from_inside() {
+    let ray = Ray {
+        origin: Point::new(0.0, 0.0, -5.0),
+        direction: Vector3::new(1.0, 0.0, 0.0),
+    };
+    let aabb = test_box();
+    assert_eq!(aabb.intersect(&ray, 0.0), Some(1.0));
+    let normal = aabb.surface_normal(&Point::new(1.0, 0.0, -5.0), &ray.direction);
+    assert_eq!((normal.x, normal.y, normal.z), (1.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_sphere_hit_from_inside_is_exit_distance() {
+    use crate::scene::{Colour, Material};
+
+    let sphere = Sphere {
+        center: Point::new(0.0, 0.0, -5.0),
+        radius: 2.0,
+        material: Material::diffuse(Colour::black(), 0.18),
+    };
+    let ray = Ray {
+        origin: Point::new(0.0, 0.0, -4.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(sphere.intersect(&ray, 0.0), Some(3.0));
+}
+
+#[cfg(test)]
+fn test_cylinder() -> Cylinder {
+    use crate::scene::{Colour, Material};
+
+    Cylinder {
+        base: Point::new(0.0, 0.0, -5.0),
+        axis: Vector3::new(0.0, 1.0, 0.0),
+        radius: 1.0,
+        height: 2.0,
+        material: Material::diffuse(Colour::black(), 0.18),
+    }
+}
+
+#[test]
+fn test_cylinder_hit_side() {
+    let ray = Ray {
+        origin: Point::new(0.0, 1.0, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    let cylinder = test_cylinder();
+    assert_eq!(cylinder.intersect(&ray, 0.0), Some(4.0));
+    let normal = cylinder.surface_normal(&Point::new(0.0, 1.0, -4.0), &ray.direction);
+    assert!(normal.approx_eq(&Vector3::new(0.0, 0.0, 1.0), 1e-9));
+}
+
+#[test]
+fn test_cylinder_hit_cap() {
+    let ray = Ray {
+        origin: Point::new(0.5, 5.0, -5.0),
+        direction: Vector3::new(0.0, -1.0, 0.0),
+    };
+    let cylinder = test_cylinder();
+    assert_eq!(cylinder.intersect(&ray, 0.0), Some(3.0));
+    let normal = cylinder.surface_normal(&Point::new(0.5, 2.0, -5.0), &ray.direction);
+    assert!(normal.approx_eq(&Vector3::new(0.0, 1.0, 0.0), 1e-9));
+}
+
+#[test]
+fn test_cylinder_miss_above_top() {
+    let ray = Ray {
+        origin: Point::new(0.0, 2.5, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(test_cylinder().intersect(&ray, 0.0), None);
+}
+
+#[cfg(test)]
+fn test_torus() -> Torus {
+    use crate::scene::{Colour, Material};
+
+    // Facing the camera, with a hole of radius 1.5 in the middle.
+    Torus {
+        center: Point::new(0.0, 0.0, -5.0),
+        axis: Vector3::new(0.0, 0.0, 1.0),
+        major_radius: 2.0,
+        minor_radius: 0.5,
+        material: Material::diffuse(Colour::black(), 0.18),
+    }
+}
+
+#[test]
+fn test_torus_miss_through_hole() {
+    let torus = test_torus();
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(torus.intersect(&ray, 0.0), None);
+    // Also clear of the outside edge.
+    let beside = Ray {
+        origin: Point::new(2.6, 0.0, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(torus.intersect(&beside, 0.0), None);
+}
+
+#[test]
+fn test_torus_hit_ring() {
+    let torus = test_torus();
+    let head_on = Ray {
+        origin: Point::new(2.0, 0.0, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    let distance = torus.intersect(&head_on, 0.0).unwrap();
+    assert!((distance - 4.5).abs() < 1e-9);
+    let normal = torus.surface_normal(&Point::new(2.0, 0.0, -4.5), &head_on.direction);
+    assert!(normal.approx_eq(&Vector3::new(0.0, 0.0, 1.0), 1e-9));
+
+    // Edge on, through the ring's plane: the outside of the tube, then its
+    // inside, across the hole, and into the far side of the ring.
+    let across = Ray {
+        origin: Point::new(-10.0, 0.0, -5.0),
+        direction: Vector3::new(1.0, 0.0, 0.0),
+    };
+    let distance = torus.intersect(&across, 0.0).unwrap();
+    assert!((distance - 7.5).abs() < 1e-9);
+    let normal = torus.surface_normal(&Point::new(-2.5, 0.0, -5.0), &across.direction);
+    assert!(normal.approx_eq(&Vector3::new(-1.0, 0.0, 0.0), 1e-9));
+    assert!((torus.intersect(&across, 8.0).unwrap() - 8.5).abs() < 1e-9);
+    assert!((torus.intersect(&across, 9.0).unwrap() - 11.5).abs() < 1e-9);
+}
+
+#[cfg(test)]
+fn test_cone() -> Cone {
+    use crate::scene::{Colour, Material};
+
+    // Opening downwards from y = 2 to a base of radius 2 at y = 0.
+    Cone {
+        apex: Point::new(0.0, 2.0, -5.0),
+        axis: Vector3::new(0.0, -1.0, 0.0),
+        half_angle: 45.0,
+        height: 2.0,
+        material: Material::diffuse(Colour::black(), 0.18),
+    }
+}
+
+#[test]
+fn test_cone_hit_side() {
+    // Halfway up, the cone is one unit in radius.
+    let ray = Ray {
+        origin: Point::new(0.0, 1.0, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    let cone = test_cone();
+    assert!((cone.intersect(&ray, 0.0).unwrap() - 4.0).abs() < 1e-9);
+    let normal = cone.surface_normal(&Point::new(0.0, 1.0, -4.0), &ray.direction);
+    let half = 0.5f64.sqrt();
+    assert!(normal.approx_eq(&Vector3::new(0.0, half, half), 1e-9));
+}
+
+#[test]
+fn test_cone_miss_past_apex() {
+    // Level with the apex but off to one side, and above it.
+    let cone = test_cone();
+    let beside = Ray {
+        origin: Point::new(0.0, 2.0, 0.0),
+        direction: Vector3::new(0.1, 0.0, -1.0).normalize(),
+    };
+    assert_eq!(cone.intersect(&beside, 0.0), None);
+    let above = Ray {
+        origin: Point::new(0.0, 2.5, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(cone.intersect(&above, 0.0), None);
+}
+
+#[test]
+fn test_cone_hit_along_axis() {
+    // Up the axis from below, the ray meets the middle of the base.
+    let ray = Ray {
+        origin: Point::new(0.0, -3.0, -5.0),
+        direction: Vector3::new(0.0, 1.0, 0.0),
+    };
+    let cone = test_cone();
+    assert_eq!(cone.intersect(&ray, 0.0), Some(3.0));
+    let normal = cone.surface_normal(&Point::new(0.0, 0.0, -5.0), &ray.direction);
+    assert!(normal.approx_eq(&Vector3::new(0.0, -1.0, 0.0), 1e-9));
+
+    // From inside, the ray leaves through the apex.
+    let inside = Ray {
+        origin: Point::new(0.0, 1.0, -5.0),
+        direction: Vector3::new(0.0, 1.0, 0.0),
+    };
+    assert!((cone.intersect(&inside, 0.0).unwrap() - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_sphere_texture_coords_on_equator() {
+    use crate::scene::{Colour, Material};
+
+    let sphere = Sphere {
+        center: Point::new(1.0, 2.0, 3.0),
+        radius: 2.0,
+        material: Material::diffuse(Colour::black(), 0.18),
+    };
+
+    let coords = sphere.texture_coords(&Point::new(3.0, 2.0, 3.0));
+    assert!((coords.u - 0.5).abs() < 1e-6);
+    assert!((coords.v - 0.5).abs() < 1e-6);
+    assert!((0.0..=1.0).contains(&coords.u));
+}
+
+#[cfg(test)]
+fn test_triangle() -> Triangle {
+    use crate::scene::{Colour, Material};
+
+    Triangle {
+        a: Point::new(-1.0, -1.0, -5.0),
+        b: Point::new(1.0, -1.0, -5.0),
+        c: Point::new(0.0, 1.0, -5.0),
+        material: Material::diffuse(Colour::black(), 0.18),
+        normals: None,
+    }
+}
+
+#[test]
+fn test_triangle_vertex_normals_blend() {
+    let flat = test_triangle();
+    let normals = [Vector3::new(-1.0, 0.0, 1.0).normalize(),
+                   Vector3::new(1.0, 0.0, 1.0).normalize(),
+                   Vector3::new(0.0, 1.0, 1.0).normalize()];
+    let smooth = Triangle {
+        normals: Some(normals),
+        ..test_triangle()
+    };
+    let direction = Vector3::new(0.0, 0.0, -1.0);
+
+    // At the centroid each vertex counts equally.
+    let centroid = Point::new(0.0, -1.0 / 3.0, -5.0);
+    let average = ((normals[0] + normals[1] + normals[2]) / 3.0).normalize();
+    assert!(smooth.surface_normal(&centroid, &direction).approx_eq(&average, 1e-12));
+    // At a vertex, its own normal; without normals, the flat face.
+    assert!(smooth.surface_normal(&smooth.b, &direction).approx_eq(&normals[1], 1e-12));
+    assert!(flat.surface_normal(&centroid, &direction).approx_eq(&Vector3::new(0.0, 0.0, 1.0), 1e-12));
+}
+
+#[test]
+fn test_triangle_hit_interior() {
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(test_triangle().intersect(&ray, 0.0), Some(5.0));
+
+    let normal = test_triangle().surface_normal(&Point::new(0.0, 0.0, -5.0), &ray.direction);
+    assert_eq!((normal.x, normal.y, normal.z), (0.0, 0.0, 1.0));
+}
+
+#[test]
+fn test_triangle_miss_outside_edge() {
+    let ray = Ray {
+        origin: Point::new(0.0, -1.001, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(test_triangle().intersect(&ray, 0.0), None);
+}
+
+#[test]
+fn test_triangle_hit_on_vertex() {
+    let ray = Ray {
+        origin: Point::new(1.0, -1.0, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(test_triangle().intersect(&ray, 0.0), Some(5.0));
+}
+
+#[test]
+fn test_triangle_back_face_culled() {
+    let ray = Ray {
+        origin: Point::new(0.0, 0.0, -10.0),
+        direction: Vector3::new(0.0, 0.0, 1.0),
+    };
+    assert_eq!(test_triangle().intersect(&ray, 0.0), None);
+}
+
+#[test]
+fn test_tangents_follow_texture_coords() {
+    use crate::scene::{Colour, Material};
+
+    let sphere = Element::Sphere(Sphere {
+        center: Point::zero(),
+        radius: 1.0,
+        material: Material::diffuse(Colour::black(), 0.18),
+    });
+    let point = Point::new(1.0, 0.0, 0.0);
+    let normal = sphere.surface_normal(&point, &Vector3::new(-1.0, 0.0, 0.0));
+    let tangent = sphere.tangent(&point, &normal);
+    assert!(tangent.dot_prod(&normal).abs() < 1e-12);
+
+    // A small step along the tangent increases u.
+    let u = sphere.texture_coords(&point).u;
+    let stepped = Point::new(1.0, 0.0, 0.0) + tangent * 1e-3;
+    assert!(sphere.texture_coords(&stepped).u > u);
+
+    // At the poles the direction of u is undefined, but a tangent is still given.
+    let pole = Point::new(0.0, 1.0, 0.0);
+    let normal = sphere.surface_normal(&pole, &Vector3::new(0.0, -1.0, 0.0));
+    assert!(sphere.tangent(&pole, &normal).dot_prod(&normal).abs() < 1e-12);
+
+    let plane = Element::Plane(Plane {
+        origin: Point::zero(),
+        normal: Vector3::new(0.0, 1.0, 0.0),
+        material: Material::diffuse(Colour::black(), 0.18),
+    });
+    let point = Point::new(0.5, 0.0, 0.5);
+    let tangent = plane.tangent(&point, &Vector3::new(0.0, 1.0, 0.0));
+    let u = plane.texture_coords(&point).u;
+    assert!(plane.texture_coords(&(point + tangent)).u > u);
+}
+
+#[cfg(test)]
+fn test_csg(op: CsgOp, left: Sphere, right: Sphere) -> Csg {
+    Csg {
+        op,
+        left: Box::new(Element::Sphere(left)),
+        right: Box::new(Element::Sphere(right)),
+    }
+}
+
+#[cfg(test)]
+fn test_sphere_at(z: f64, radius: f64) -> Sphere {
+    use crate::scene::{Colour, Material};
+
+    Sphere {
+        center: Point::new(0.0, 0.0, z),
+        radius,
+        material: Material::diffuse(Colour::black(), 0.18),
+    }
+}
+
+#[test]
+fn test_csg_difference_hollows_sphere() {
+    // A shell: the sphere of radius 2 with a radius 1 cavity at its centre.
+    let shell = test_csg(CsgOp::Difference, test_sphere_at(-10.0, 2.0), test_sphere_at(-10.0, 1.0));
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(shell.intersect(&ray, 0.0), Some(8.0));
+    // Past the outer wall the ray reaches the cavity, then the far wall.
+    assert_eq!(shell.intersect(&ray, 8.5), Some(9.0));
+    assert_eq!(shell.intersect(&ray, 10.0), Some(11.0));
+    assert_eq!(shell.intersect(&ray, 11.5), Some(12.0));
+    assert_eq!(shell.intersect(&ray, 12.5), None);
+
+    // Normals point out of the shell: towards the ray at the outer wall, and
+    // into the cavity at the inner one.
+    let normal = shell.surface_normal(&Point::new(0.0, 0.0, -8.0), &ray.direction);
+    assert!(normal.approx_eq(&Vector3::new(0.0, 0.0, 1.0), 1e-12));
+    let normal = shell.surface_normal(&Point::new(0.0, 0.0, -9.0), &ray.direction);
+    assert!(normal.approx_eq(&Vector3::new(0.0, 0.0, -1.0), 1e-12));
+
+    // From inside the cavity the inner wall is the first surface hit.
+    let inside = Ray {
+        origin: Point::new(0.0, 0.0, -10.0),
+        direction: Vector3::new(0.0, 1.0, 0.0),
+    };
+    assert_eq!(shell.intersect(&inside, 0.0), Some(1.0));
+    let normal = shell.surface_normal(&Point::new(0.0, 1.0, -10.0), &inside.direction);
+    assert!(normal.approx_eq(&Vector3::new(0.0, -1.0, 0.0), 1e-12));
+}
+
+#[test]
+fn test_csg_union_and_intersection() {
+    // Two overlapping spheres spanning z in [-12, -8] and [-11, -7] along the axis.
+    let union = test_csg(CsgOp::Union, test_sphere_at(-10.0, 2.0), test_sphere_at(-9.0, 2.0));
+    let both = test_csg(CsgOp::Intersection, test_sphere_at(-10.0, 2.0), test_sphere_at(-9.0, 2.0));
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(union.intersect(&ray, 0.0), Some(7.0));
+    assert_eq!(union.intersect(&ray, 8.0), Some(12.0));
+    assert_eq!(both.intersect(&ray, 0.0), Some(8.0));
+    assert_eq!(both.intersect(&ray, 9.0), Some(11.0));
+
+    // Disjoint spheres have no intersection.
+    let apart = test_csg(CsgOp::Intersection, test_sphere_at(-10.0, 1.0), test_sphere_at(-20.0, 1.0));
+    assert_eq!(apart.intersect(&ray, 0.0), None);
+}
+
+#[test]
+fn test_translated_sphere_matches_moved_sphere() {
+    use crate::math::Matrix4;
+    use crate::scene::{Colour, Material};
+
+    let unit = Element::Sphere(Sphere {
+        center: Point::zero(),
+        radius: 1.0,
+        material: Material::diffuse(Colour::black(), 0.18),
+    });
+    let translated = Transformed::new(unit.clone(),
+                                      Matrix4::translate(&Vector3::new(5.0, 0.0, 0.0))).unwrap();
+    let moved = Sphere {
+        center: Point::new(5.0, 0.0, 0.0),
+        radius: 1.0,
+        material: Material::diffuse(Colour::black(), 0.18),
+    };
+
+    for direction in [Vector3::new(1.0, 0.0, 0.0), Vector3::new(5.0, 0.5, -0.3).normalize()] {
+        let ray = Ray {
+            origin: Point::zero(),
+            direction,
+        };
+        let expected = moved.intersect(&ray, 0.0).unwrap();
+        let distance = translated.intersect(&ray, 0.0).unwrap();
+        assert!((distance - expected).abs() < 1e-12);
+
+        let hit_point = ray.origin + direction * distance;
+        let normal = translated.surface_normal(&hit_point, &direction);
+        assert!(normal.approx_eq(&moved.surface_normal(&hit_point, &direction), 1e-12));
+    }
+
+    // Scaling the unit sphere by 2 doubles its radius, with distances measured in world space.
+    let scaled = Transformed::new(unit, Matrix4::translate(&Vector3::new(5.0, 0.0, 0.0)) *
+                                        Matrix4::scale(&Vector3::new(2.0, 2.0, 2.0))).unwrap();
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(1.0, 0.0, 0.0),
+    };
+    assert!((scaled.intersect(&ray, 0.0).unwrap() - 3.0).abs() < 1e-12);
+    assert!((scaled.intersect(&ray, 4.0).unwrap() - 7.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_translated_group_moves_its_children() {
+    use crate::math::Matrix4;
+    use crate::scene::{Colour, Material, Intersection};
+
+    let sphere_at = |x: f64, albedo: f32| Element::Sphere(Sphere {
+        center: Point::new(x, 0.0, -5.0),
+        radius: 0.5,
+        material: Material::diffuse(Colour::black(), albedo),
+    });
+    let group = Group::new(vec![sphere_at(-1.0, 0.2), sphere_at(1.0, 0.8)],
+                           Some(Matrix4::translate(&Vector3::new(0.0, 2.0, 0.0)))).unwrap();
+    let element = Element::Group(group);
+
+    let ray_at = |x: f64, y: f64| Ray {
+        origin: Point::new(x, y, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    for (x, albedo) in [(-1.0, 0.2), (1.0, 0.8)] {
+        // Both spheres have moved up out of the way of the original rays.
+        assert_eq!(element.intersect(&ray_at(x, 0.0), 0.0), None);
+
+        let ray = ray_at(x, 2.0);
+        let distance = element.intersect(&ray, 0.0).unwrap();
+        assert!((distance - 4.5).abs() < 1e-12);
+
+        // The hit belongs to the child, with its own normal and material.
+        let hit = Intersection::new(&ray, distance, &element).unwrap();
+        assert!(hit.surface_normal.approx_eq(&Vector3::new(0.0, 0.0, 1.0), 1e-12));
+        assert_eq!(hit.elements.albedo(), albedo);
+        assert!(element.contains(hit.elements));
+    }
+    assert_eq!(element.intersect(&ray_at(0.0, 2.0), 0.0), None);
+}
@@ -1,10 +1,25 @@
 use crate::point::Point;
 use crate::vector::Vector3;
 use crate::render::{Ray, Intersectable};
-use image::{DynamicImage, GenericImage, Pixel, Rgba};
-use std::ops::{Mul, Add};
+use crate::bvh::{Bvh, BoundingBox};
+use crate::grid::UniformGrid;
+use crate::math::Matrix4;
+use crate::sdf;
+use crate::stats::{self, Counter, StatCounters};
+use crate::texture::{Texture, EnvMap, NormalMap};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use rand::Rng;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::{Mul, Add, Sub};
+use std::sync::{Arc, LazyLock, OnceLock};
 
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "ColourDescription"))]
 pub struct Colour {
     pub red: f32,
     pub green: f32,
@@ -12,11 +27,153 @@ pub struct Colour {
 }
 
 impl Colour {
+    pub fn black() -> Colour {
+        Colour {
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+        }
+    }
+
+    pub fn from_one(v: f32) -> Colour {
+        Colour {
+            red: v,
+            green: v,
+            blue: v,
+        }
+    }
+
     pub fn clamp(&self) -> Colour {
         Colour {
-            red: self.red.min(1.0),
-            green: self.green.min(1.0),
-            blue: self.blue.min(1.0),
+            red: self.red.clamp(0.0, 1.0),
+            green: self.green.clamp(0.0, 1.0),
+            blue: self.blue.clamp(0.0, 1.0),
+        }
+    }
+
+    // Brings an over-bright colour into range by moving it towards a grey of
+    // the same luminance until its brightest channel is 1.0, so the hue holds
+    // while the colour washes out towards white. Too bright for that and it
+    // is white.
+    pub fn clamp_preserve_hue(&self) -> Colour {
+        let colour = Colour {
+            red: self.red.max(0.0),
+            green: self.green.max(0.0),
+            blue: self.blue.max(0.0),
+        };
+        let brightest = colour.red.max(colour.green).max(colour.blue);
+        if brightest <= 1.0 {
+            return colour;
+        }
+        let luminance = colour.luminance();
+        if luminance >= 1.0 {
+            return Colour::from_one(1.0);
+        }
+        let saturation = (1.0 - luminance) / (brightest - luminance);
+        colour.map(|c| (luminance + (c - luminance) * saturation).clamp(0.0, 1.0))
+    }
+
+    // Linear interpolation from this colour (t = 0) to `other` (t = 1), with
+    // t clamped to [0, 1].
+    pub fn lerp(&self, other: &Colour, t: f32) -> Colour {
+        let t = t.clamp(0.0, 1.0);
+        *self * (1.0 - t) + *other * t
+    }
+
+    // Perceived brightness, using the Rec. 709 weights.
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
+
+    // Parses "#RRGGBB", taking each channel as a fraction of 255.
+    pub fn from_hex(hex: &str) -> Result<Colour, ColourError> {
+        let digits = hex.strip_prefix('#')
+            .filter(|d| d.len() == 6 && d.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| ColourError::InvalidHex(hex.to_string()))?;
+        let channel = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).unwrap() as f32 / 255.0;
+        Ok(Colour {
+            red: channel(0),
+            green: channel(2),
+            blue: channel(4),
+        })
+    }
+
+    // As `from_hex`, for colours picked in sRGB (as most colour pickers give
+    // them) that need converting to linear values before lighting.
+    pub fn from_srgb_hex(hex: &str) -> Result<Colour, ColourError> {
+        Ok(Colour::from_hex(hex)?.srgb_to_linear())
+    }
+
+    // Decodes sRGB-encoded components to linear light using the piecewise
+    // sRGB transfer function.
+    pub fn srgb_to_linear(&self) -> Colour {
+        self.map(|c| if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        })
+    }
+
+    // The inverse of `srgb_to_linear`.
+    pub fn linear_to_srgb(&self) -> Colour {
+        self.map(|c| if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        })
+    }
+
+    fn map<F: Fn(f32) -> f32>(&self, f: F) -> Colour {
+        Colour {
+            red: f(self.red),
+            green: f(self.green),
+            blue: f(self.blue),
+        }
+    }
+
+    // "#RRGGBB", with each channel clamped to [0, 1] and rounded.
+    pub fn to_hex(&self) -> String {
+        let c = self.clamp();
+        let byte = |v: f32| (v * 255.0).round() as u8;
+        format!("#{:02x}{:02x}{:02x}", byte(c.red), byte(c.green), byte(c.blue))
+    }
+}
+
+#[derive(Debug)]
+pub enum ColourError {
+    InvalidHex(String),
+}
+
+impl fmt::Display for ColourError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ColourError::InvalidHex(ref hex) => write!(f, "{:?} is not a #RRGGBB colour", hex),
+        }
+    }
+}
+
+impl Error for ColourError {}
+
+// Scene files may give a colour by its components, as a linear hex string, or
+// as `{ "srgb": "#RRGGBB" }` for a hex colour to be decoded from sRGB.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColourDescription {
+    Hex(String),
+    Srgb { srgb: String },
+    Components { red: f32, green: f32, blue: f32 },
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ColourDescription> for Colour {
+    type Error = ColourError;
+
+    fn try_from(description: ColourDescription) -> Result<Colour, ColourError> {
+        match description {
+            ColourDescription::Hex(hex) => Colour::from_hex(&hex),
+            ColourDescription::Srgb { srgb } => Colour::from_srgb_hex(&srgb),
+            ColourDescription::Components { red, green, blue } => Ok(Colour { red, green, blue }),
         }
     }
 }
@@ -32,6 +189,17 @@ impl Add for Colour {
     }
 }
 
+impl Sub for Colour {
+    type Output = Colour;
+    fn sub(self, other: Colour) -> Colour {
+        Colour {
+            red: self.red - other.red,
+            blue: self.blue - other.blue,
+            green: self.green - other.green,
+        }
+    }
+}
+
 impl<'a> Mul for &'a Colour {
     type Output = Colour;
 
@@ -63,30 +231,237 @@ impl Mul<Colour> for f32 {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+fn default_refractive_index() -> f32 {
+    1.0
+}
+
+#[cfg(feature = "serde")]
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_shininess() -> f32 {
+    32.0
+}
+
+// Metals have no diffuse layer, and tint their highlights and reflections
+// with their colour. Dielectrics (plastic, paint, glass) reflect white light
+// over a diffuse base.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum MaterialKind {
+    Metal,
+    #[default]
+    Dielectric,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Material {
+    pub colour: Texture,
+    pub albedo: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub kind: MaterialKind,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reflectivity: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub transparency: f32,
+    #[cfg_attr(feature = "serde", serde(default = "default_refractive_index"))]
+    pub refractive_index: f32,
+    // Strength of Phong highlights; `shininess` narrows them as it grows.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub specular: f32,
+    #[cfg_attr(feature = "serde", serde(default = "default_shininess"))]
+    pub shininess: f32,
+    // Light given off by the surface itself, added regardless of lighting.
+    #[cfg_attr(feature = "serde", serde(default = "Colour::black"))]
+    pub emission: Colour,
+    // Adds surface detail by perturbing the normal used for lighting.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub normal_map: Option<NormalMap>,
+}
+
+impl Material {
+    pub fn diffuse(colour: Colour, albedo: f32) -> Material {
+        Material {
+            colour: Texture::Solid(colour),
+            albedo,
+            kind: MaterialKind::Dielectric,
+            reflectivity: 0.0,
+            transparency: 0.0,
+            refractive_index: default_refractive_index(),
+            specular: 0.0,
+            shininess: default_shininess(),
+            emission: Colour::black(),
+            normal_map: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
 pub struct Sphere {
     pub center: Point,
     pub radius: f64,
-    pub colour: Colour,
-    pub albedo: f32,
+    pub material: Material,
 }
 
+// Every kind of light also has a `name`, by which `Scene::light_by_name`
+// finds it, and can be switched off without removing it by clearing
+// `enabled`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
 pub struct DirectionalLight {
     pub direction: Vector3,
     pub colour: Colour,
     pub intensity: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "default_enabled"))]
+    pub enabled: bool,
+}
+
+// How a light weakens with distance. Each matches the physically correct
+// inverse-square falloff one unit from the light.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Attenuation {
+    #[default]
+    InverseSquare,
+    Linear,
+    None,
+}
+
+impl Attenuation {
+    pub fn apply(&self, intensity: f32, position: &Point, hit_point: &Point) -> f32 {
+        let unit = intensity / (4.0 * ::std::f32::consts::PI);
+        match *self {
+            Attenuation::InverseSquare => inverse_square(intensity, position, hit_point),
+            Attenuation::Linear => unit / position.distance(hit_point) as f32,
+            Attenuation::None => unit,
+        }
+    }
 }
 
+// A point light, or a glowing sphere when `radius` is non-zero. Sampling
+// points across a sphere's surface gives soft-edged shadows.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
 pub struct SphericalLight {
     pub position: Point,
     pub colour: Colour,
     pub intensity: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub radius: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub attenuation: Attenuation,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "default_enabled"))]
+    pub enabled: bool,
+}
+
+impl SphericalLight {
+    // A uniformly distributed random point on the light's surface.
+    pub fn sample_point<R: Rng>(&self, rng: &mut R) -> Point {
+        loop {
+            let v = Vector3::new(rng.gen_range(-1.0..=1.0),
+                                 rng.gen_range(-1.0..=1.0),
+                                 rng.gen_range(-1.0..=1.0));
+            let norm = v.norm();
+            if norm > 1e-6 && norm <= 1.0 {
+                return self.position + v.normalize() * self.radius as f64;
+            }
+        }
+    }
+}
+
+// A rectangular light with one corner at `corner` and sides along `edge_u`
+// and `edge_v`. It shines from the side that `edge_u` x `edge_v` faces, and
+// sampling points across it gives soft-edged shadows.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct AreaLight {
+    pub corner: Point,
+    pub edge_u: Vector3,
+    pub edge_v: Vector3,
+    pub colour: Colour,
+    pub intensity: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "default_enabled"))]
+    pub enabled: bool,
+}
+
+impl AreaLight {
+    pub fn center(&self) -> Point {
+        self.corner + (self.edge_u + self.edge_v) * 0.5
+    }
+
+    // A uniformly distributed random point on the rectangle.
+    pub fn sample_point<R: Rng>(&self, rng: &mut R) -> Point {
+        self.corner + self.edge_u * rng.gen::<f64>() + self.edge_v * rng.gen::<f64>()
+    }
+
+    // Inverse-square falloff from the centre, weakening towards the edge of
+    // the light's front side like a diffuse emitter.
+    fn intensity_at(&self, hit_point: &Point) -> f32 {
+        let normal = self.edge_u.cross_prod(&self.edge_v).normalize();
+        let facing = normal.dot_prod(&(*hit_point - self.center()).normalize()).max(0.0);
+        inverse_square(self.intensity, &self.center(), hit_point) * facing as f32
+    }
+}
+
+// A cone of light. `cone_angle` is the half-angle of the cone in degrees, and
+// intensity fades smoothly to zero over the outermost `falloff` degrees.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct SpotLight {
+    pub position: Point,
+    pub direction: Vector3,
+    pub colour: Colour,
+    pub intensity: f32,
+    pub cone_angle: f32,
+    pub falloff: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "default_enabled"))]
+    pub enabled: bool,
 }
 
+impl SpotLight {
+    fn cone_attenuation(&self, hit_point: &Point) -> f32 {
+        let to_point = (*hit_point - self.position).normalize();
+        let cos_angle = to_point.dot_prod(&self.direction.normalize()).clamp(-1.0, 1.0);
+        let angle = (cos_angle.acos() as f32).to_degrees();
+
+        let inner = self.cone_angle - self.falloff;
+        if angle >= self.cone_angle {
+            0.0
+        } else if angle <= inner {
+            1.0
+        } else {
+            let t = (self.cone_angle - angle) / self.falloff;
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "lowercase"))]
 pub enum Light {
     Directional(DirectionalLight),
     Spherical(SphericalLight),
+    Spot(SpotLight),
+    Area(AreaLight),
+}
+
+fn inverse_square(intensity: f32, position: &Point, hit_point: &Point) -> f32 {
+    let r2 = (*position - *hit_point).norm() as f32;
+    intensity / (4.0 * ::std::f32::consts::PI * r2)
 }
 
 impl Light {
@@ -94,95 +469,2071 @@ impl Light {
         match *self {
             Light::Directional(ref d) => d.colour,
             Light::Spherical(ref s) => s.colour,
+            Light::Spot(ref s) => s.colour,
+            Light::Area(ref a) => a.colour,
         }
     }
     pub fn direction_from(&self, hit_point: &Point) -> Vector3 {
         match *self {
-            Light::Directional(ref d) => -d.direction,
+            Light::Directional(ref d) => -d.direction.normalize(),
             Light::Spherical(ref s) => (s.position - *hit_point).normalize(),
+            Light::Spot(ref s) => (s.position - *hit_point).normalize(),
+            Light::Area(ref a) => (a.center() - *hit_point).normalize(),
+        }
+    }
+    pub fn name(&self) -> &str {
+        match *self {
+            Light::Directional(ref d) => &d.name,
+            Light::Spherical(ref s) => &s.name,
+            Light::Spot(ref s) => &s.name,
+            Light::Area(ref a) => &a.name,
+        }
+    }
+    pub fn enabled(&self) -> bool {
+        match *self {
+            Light::Directional(ref d) => d.enabled,
+            Light::Spherical(ref s) => s.enabled,
+            Light::Spot(ref s) => s.enabled,
+            Light::Area(ref a) => a.enabled,
+        }
+    }
+    pub fn set_enabled(&mut self, enabled: bool) {
+        match *self {
+            Light::Directional(ref mut d) => d.enabled = enabled,
+            Light::Spherical(ref mut s) => s.enabled = enabled,
+            Light::Spot(ref mut s) => s.enabled = enabled,
+            Light::Area(ref mut a) => a.enabled = enabled,
         }
     }
+    // Zero for disabled lights.
     pub fn intensity(&self, hit_point: &Point) -> f32 {
+        if !self.enabled() {
+            return 0.0;
+        }
         match *self {
             Light::Directional(ref d) => d.intensity,
-            Light::Spherical(ref s) => {
-                let r2 = (s.position - *hit_point).norm() as f32;
-                s.intensity / (4.0 * ::std::f32::consts::PI * r2)
+            Light::Spherical(ref s) => s.attenuation.apply(s.intensity, &s.position, hit_point),
+            Light::Spot(ref s) => {
+                inverse_square(s.intensity, &s.position, hit_point) * s.cone_attenuation(hit_point)
             }
+            Light::Area(ref a) => a.intensity_at(hit_point),
         }
     }
     pub fn distance(&self, hit_point: &Point) -> f64 {
         match *self {
-            Light::Directional(_) => ::std::f64::INFINITY,
-            Light::Spherical(ref s) => (s.position - *hit_point).length(),
+            Light::Directional(_) => f64::INFINITY,
+            Light::Spherical(ref s) => s.position.distance(hit_point),
+            Light::Spot(ref s) => s.position.distance(hit_point),
+            Light::Area(ref a) => a.center().distance(hit_point),
+        }
+    }
+}
+
+// How pixel colours are computed. `PathTrace` follows random diffuse bounces
+// to gather indirect light, at the cost of noise unless many samples are taken.
+// `RayMarch` finds surfaces by sphere tracing their signed distance fields
+// instead of intersecting them analytically; elements without a distance
+// field are not seen. `Depth` shows the distance to the first surface as grey,
+// from black at `depth_near` to white at `depth_far` and beyond. `Normals`
+// shows the surface normal, with each component mapped from [-1, 1] to [0, 1]
+// as red, green and blue. `ObjectId` identifies the element seen, as given
+// by `Scene::element_id`, with its low, middle and high bytes as red, green
+// and blue; where nothing is hit the id is 0. `Wireframe` draws the edges of
+// triangles in white over the background, for checking imported meshes.
+// `FocusPeaking` renders as `Raytrace` but marks in green the surfaces within
+// `focus_tolerance` of the camera's focus distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum RenderMode {
+    Raytrace,
+    PathTrace,
+    RayMarch,
+    Depth,
+    Normals,
+    ObjectId,
+    Wireframe,
+    FocusPeaking,
+}
+
+impl RenderMode {
+    // Modes whose pixels hold data about the surfaces seen rather than light.
+    // They take a single sample through each pixel's centre and are written
+    // out without exposure, tone mapping or gamma.
+    pub fn is_data(&self) -> bool {
+        matches!(*self, RenderMode::Depth | RenderMode::Normals | RenderMode::ObjectId)
+    }
+}
+
+// How the lights are gathered at each shading point. `All` adds up every
+// light. `Importance` picks one at random, more often the more light it
+// brings (its intensity after falloff), and scales it by the inverse of that
+// chance, so many samples average to the same result for far fewer shadow
+// rays.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum LightSampling {
+    #[default]
+    All,
+    Importance,
+}
+
+// The structure used to find which element a ray hits first. A uniform grid
+// suits many small, evenly spread elements; the BVH adapts better to scenes
+// where elements are clustered or vary in size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Acceleration {
+    Bvh,
+    Grid,
+}
+
+// A single part of the shading, which can be rendered alone for debugging.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Channel {
+    Diffuse,
+    Specular,
+    Reflection,
+    Refraction,
+    Emission,
+}
+
+// What rays that hit nothing see. A gradient blends from `bottom` for rays
+// pointing straight down to `top` for rays pointing straight up, and an
+// environment map is looked up by direction.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "lowercase"))]
+pub enum Background {
+    Solid(Colour),
+    Gradient { top: Colour, bottom: Colour },
+    Environment(EnvMap),
+}
+
+impl Background {
+    pub fn colour(&self, direction: &Vector3) -> Colour {
+        match *self {
+            Background::Solid(colour) => colour,
+            Background::Gradient { top, bottom } => {
+                bottom.lerp(&top, ((direction.normalize().y + 1.0) / 2.0) as f32)
+            }
+            Background::Environment(ref env) => env.sample(direction),
+        }
+    }
+
+    // Whether reflected and refracted rays see this background. Only
+    // environment maps can opt out; other rays that miss see black instead.
+    pub fn in_reflections(&self) -> bool {
+        match *self {
+            Background::Environment(ref env) => env.in_reflections,
+            _ => true,
+        }
+    }
+}
+
+// Exponential fog. Surfaces fade towards `colour` with distance from the
+// viewer, and rays that escape the scene see the fog colour.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Fog {
+    pub colour: Colour,
+    pub density: f32,
+}
+
+impl Fog {
+    pub fn apply(&self, colour: &Colour, distance: f64) -> Colour {
+        colour.lerp(&self.colour, 1.0 - (-self.density * distance as f32).exp())
+    }
+}
+
+// How linear colours brighter than 1.0 are brought into displayable range.
+// `Clamp` clips each channel, which can shift the hue of bright colours;
+// `PreserveHue` desaturates them instead.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ToneMap {
+    Clamp,
+    Reinhard,
+    PreserveHue,
+}
+
+impl ToneMap {
+    pub fn apply(&self, colour: &Colour) -> Colour {
+        match *self {
+            ToneMap::Clamp => colour.clamp(),
+            ToneMap::Reinhard => Colour {
+                red: colour.red / (1.0 + colour.red),
+                green: colour.green / (1.0 + colour.green),
+                blue: colour.blue / (1.0 + colour.blue),
+            },
+            ToneMap::PreserveHue => colour.clamp_preserve_hue(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "lowercase"))]
+pub enum Projection {
+    // fov is the angle in degrees spanned by the shorter image dimension.
+    Perspective { fov: f64 },
+    // scale is the world-space half-extent of the shorter image dimension.
+    Orthographic { scale: f64 },
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Camera {
+    pub position: Point,
+    pub look_at: Point,
+    pub up: Vector3,
+    pub projection: Projection,
+    // Lens diameter for depth of field; zero gives a pinhole camera with
+    // everything in focus. Only perspective cameras use it.
+    pub aperture: f64,
+    // Distance along the view direction of the plane that is in focus.
+    pub focus_distance: f64,
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera {
+            position: Point::zero(),
+            look_at: Point::new(0.0, 0.0, -1.0),
+            up: Vector3::new(0.0, 1.0, 0.0),
+            projection: Projection::Perspective { fov: 90.0 },
+            aperture: 0.0,
+            focus_distance: 1.0,
+        }
+    }
+}
+
+impl Camera {
+    // Orthonormal (right, up, forward) view basis. `up` only needs to be
+    // roughly upwards; it is re-orthogonalised against the view direction.
+    pub fn basis(&self) -> (Vector3, Vector3, Vector3) {
+        let forward = (self.look_at - self.position).normalize();
+        let right = forward.cross_prod(&self.up).normalize();
+        let up = right.cross_prod(&forward);
+        (right, up, forward)
+    }
+
+    // The scene's camera moved back along `direction`, looking at the centre
+    // of the scene, until the sphere around its bounding box fits in view.
+    // Cameras are left where they are when there is nothing bounded to see.
+    pub fn frame_scene(scene: &Scene, direction: &Vector3) -> Camera {
+        let bounds = scene.bounding_box();
+        if bounds.min.x > bounds.max.x {
+            return scene.camera;
+        }
+        let centre = bounds.centroid();
+        let radius = (bounds.max - bounds.min).length() / 2.0;
+        let direction = direction.normalize();
+
+        let mut camera = scene.camera;
+        let distance = match camera.projection {
+            // Fitting the sphere to the shorter side's field of view fits it
+            // to the longer side as well.
+            Projection::Perspective { fov } => radius / (fov.to_radians() / 2.0).sin(),
+            Projection::Orthographic { .. } => {
+                camera.projection = Projection::Orthographic { scale: radius };
+                2.0 * radius
+            }
+        };
+        camera.position = centre - direction * distance;
+        camera.look_at = centre;
+        if direction.cross_prod(&camera.up).length() < 1e-9 {
+            camera.up = direction.cross_prod(&Vector3::new(1.0, 0.0, 0.0)).normalize();
+            if camera.up.length() < 1e-9 {
+                camera.up = Vector3::new(0.0, 0.0, -1.0);
+            }
         }
+        camera
     }
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Scene {
     pub width: u32,
     pub height: u32,
-    pub fov: f64,
+    pub camera: Camera,
+    // Further cameras, by name, that `render_from` can use in place of `camera`.
+    pub cameras: Vec<(String, Camera)>,
     pub elements: Vec<Element>,
+    #[cfg_attr(feature = "serde", serde(rename = "lights"))]
     pub light: Vec<Light>,
     pub shadow_bias: f64,
+    // Shadow rays ignore hits closer than this, so that the surface being
+    // shaded can't shadow itself, even at grazing angles or zero bias.
+    pub shadow_epsilon: f64,
+    // The most mirror reflections (and, when path tracing, diffuse bounces)
+    // followed from a camera ray, and separately the most passes through
+    // transparent surfaces.
+    pub max_reflection_depth: u32,
+    pub max_refraction_depth: u32,
+    pub samples_per_pixel: u32,
+    // Adaptive sampling: when `max_samples` exceeds `samples_per_pixel`, pixels
+    // whose first samples vary by more than `variance_threshold` are sampled
+    // further, up to `max_samples` rays.
+    pub max_samples: u32,
+    pub variance_threshold: f32,
+    // Width and height in pixels of the square tiles rendered in parallel.
+    pub tile_size: u32,
+    // Shadow rays cast towards each light with a size (spherical lights with
+    // a non-zero radius and area lights), and towards every other light when
+    // `shadow_jitter` is set. Their average gives soft shadow edges.
+    pub shadow_samples: u32,
+    // Angular spread, in radians, of the shadow rays cast towards lights
+    // without a size when `shadow_samples` is above one. Zero keeps their
+    // shadows hard.
+    pub shadow_jitter: f64,
+    // When false no shadow rays are cast and every light reaches every
+    // surface facing it, for quicker previews.
+    pub shadows_enabled: bool,
+    pub light_sampling: LightSampling,
+    pub gamma: f32,
+    // Encode output with the piecewise sRGB curve rather than a plain `gamma`
+    // power.
+    pub srgb_output: bool,
+    // Write each pixel as its luminance, after tone mapping.
+    pub grayscale: bool,
+    // Brightness adjustment in stops: colours are scaled by 2^exposure before
+    // tone mapping.
+    pub exposure: f32,
+    // Light reaching every surface regardless of the scene's lights or shadows.
+    pub ambient: Colour,
+    pub tone_map: ToneMap,
+    pub fog: Option<Fog>,
+    pub background: Background,
+    pub render_mode: RenderMode,
+    // Distances shown as black and white in `RenderMode::Depth`.
+    pub depth_near: f64,
+    pub depth_far: f64,
+    // How close to an edge a point must be to be drawn in
+    // `RenderMode::Wireframe`, as a barycentric coordinate.
+    pub wireframe_width: f64,
+    // How far from the plane of focus a surface may be to be marked in
+    // `RenderMode::FocusPeaking`.
+    pub focus_tolerance: f64,
+    // Path tracing depth after which paths are ended at random, more often the
+    // less light they can still carry back (Russian roulette).
+    pub rr_min_depth: u32,
+    // Smooths sampling noise after rendering, without blurring across edges.
+    pub denoise: bool,
+    // Seeds the random numbers used for sampling so renders are repeatable.
+    pub rng_seed: Option<u64>,
+    // When set, only this part of the shading is rendered.
+    pub debug_channel: Option<Channel>,
+    // Which of `bvh` and `grid` `build_acceleration` creates.
+    pub acceleration: Acceleration,
+    // Built by `build_bvh`. Once elements are added or removed it no longer
+    // matches them, and traces test every element until it is rebuilt.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub bvh: Option<Bvh>,
+    // Built by `build_grid`, and like `bvh` ignored once it no longer matches
+    // the elements.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub grid: Option<UniformGrid>,
+    // Set in place of `bvh` and `grid` when elements are edited through
+    // `element_mut`, `add_element` or `remove_element`, so that the first
+    // trace afterwards rebuilds whichever `acceleration` selects.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) rebuilt: Option<OnceLock<(Option<UniformGrid>, Option<Bvh>)>>,
+    // When set, rays and intersection tests are counted here.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub stats: Option<Arc<StatCounters>>,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Default for Scene {
+    fn default() -> Scene {
+        Scene {
+            width: 800,
+            height: 600,
+            camera: Camera::default(),
+            cameras: Vec::new(),
+            elements: Vec::new(),
+            light: Vec::new(),
+            shadow_bias: 0.0001,
+            shadow_epsilon: 1e-6,
+            max_reflection_depth: 4,
+            max_refraction_depth: 4,
+            samples_per_pixel: 1,
+            max_samples: 0,
+            variance_threshold: 0.001,
+            tile_size: 16,
+            shadow_samples: 1,
+            shadow_jitter: 0.0,
+            shadows_enabled: true,
+            light_sampling: LightSampling::All,
+            gamma: 2.2,
+            srgb_output: false,
+            grayscale: false,
+            exposure: 0.0,
+            ambient: Colour::black(),
+            tone_map: ToneMap::Clamp,
+            fog: None,
+            background: Background::Solid(Colour::black()),
+            render_mode: RenderMode::Raytrace,
+            depth_near: 0.0,
+            depth_far: 100.0,
+            wireframe_width: 0.03,
+            focus_tolerance: 0.1,
+            rr_min_depth: 3,
+            denoise: false,
+            rng_seed: None,
+            debug_channel: None,
+            acceleration: Acceleration::Bvh,
+            bvh: None,
+            grid: None,
+            rebuilt: None,
+            stats: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
 pub struct Plane {
     pub origin: Point,
     pub normal: Vector3,
-    pub colour: Colour,
-    pub albedo: f32,
+    pub material: Material,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Triangle {
+    pub a: Point,
+    pub b: Point,
+    pub c: Point,
+    pub material: Material,
+    // Normals at a, b and c, blended across the triangle so that a mesh of
+    // flat triangles shades as a smooth surface. Without them the triangle's
+    // own flat normal is used.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub normals: Option<[Vector3; 3]>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Disk {
+    pub center: Point,
+    pub normal: Vector3,
+    pub radius: f64,
+    pub material: Material,
+}
+
+// A parallelogram with one corner at `corner` and sides along `edge_u` and
+// `edge_v`; a rectangle when the edges are perpendicular.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Quad {
+    pub corner: Point,
+    pub edge_u: Vector3,
+    pub edge_v: Vector3,
+    pub material: Material,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct AxisAlignedBox {
+    pub min: Point,
+    pub max: Point,
+    pub material: Material,
+}
+
+// A solid, capped cylinder standing on `base` and extending `height` along `axis`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Cylinder {
+    pub base: Point,
+    pub axis: Vector3,
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+}
+
+// A solid cone with its point at `apex`, widening along `axis` at
+// `half_angle` degrees from it and closed by a flat base `height` away.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Cone {
+    pub apex: Point,
+    pub axis: Vector3,
+    pub half_angle: f64,
+    pub height: f64,
+    pub material: Material,
+}
+
+// A ring: the points `minor_radius` from a circle of `major_radius` around
+// `center`, in the plane perpendicular to `axis`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Torus {
+    pub center: Point,
+    pub axis: Vector3,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Material,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+// A boolean combination of two solids: everything inside either of them, the
+// part inside both, or the part of `left` outside `right`. Only spheres,
+// boxes, cylinders and other CSG shapes are solid. The surface takes its
+// material from `left`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Csg {
+    pub op: CsgOp,
+    pub left: Box<Element>,
+    pub right: Box<Element>,
+}
+
+// Another element moved, scaled or rotated by `transform`, which maps its
+// own coordinates into the scene's. One shape can be placed many times by
+// wrapping copies of it in different transforms.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "TransformedDescription"))]
+pub struct Transformed {
+    pub element: Box<Element>,
+    transform: Matrix4,
+    inverse: Matrix4,
+}
+
+impl Transformed {
+    // Returns None if the transform can't be inverted, as for a scale by zero.
+    pub fn new(element: Element, transform: Matrix4) -> Option<Transformed> {
+        Some(Transformed {
+            element: Box::new(element),
+            transform,
+            inverse: transform.inverse()?,
+        })
+    }
+
+    pub fn transform(&self) -> &Matrix4 {
+        &self.transform
+    }
+
+    pub fn inverse(&self) -> &Matrix4 {
+        &self.inverse
+    }
+}
+
+// Elements placed and moved together. A ray hits whichever child is nearest,
+// and the hit is reported as that child's, so each keeps its own material.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "GroupDescription"))]
+pub struct Group {
+    pub elements: Vec<Element>,
+}
+
+impl Group {
+    // Applies `transform`, if given, to every child, as `Transformed` does to
+    // a single element. Returns None if the transform can't be inverted.
+    pub fn new(elements: Vec<Element>, transform: Option<Matrix4>) -> Option<Group> {
+        let elements = match transform {
+            Some(transform) => {
+                let inverse = transform.inverse()?;
+                elements.into_iter().map(|e| place(e, &transform, &inverse)).collect()
+            }
+            None => elements,
+        };
+        Some(Group { elements })
+    }
+}
+
+// Wraps an element in a transform, pushing it down into nested groups so that
+// hits are still reported against the individual children.
+fn place(element: Element, transform: &Matrix4, inverse: &Matrix4) -> Element {
+    match element {
+        Element::Group(group) => Element::Group(Group {
+            elements: group.elements.into_iter().map(|e| place(e, transform, inverse)).collect(),
+        }),
+        element => Element::Transformed(Transformed {
+            element: Box::new(element),
+            transform: *transform,
+            inverse: *inverse,
+        }),
+    }
+}
+
+// In scene files a transform is a list of steps, applied in order.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TransformStep {
+    Translate(Vector3),
+    Scale(Vector3),
+    Rotate { axis: Vector3, degrees: f64 },
 }
 
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct TransformedDescription {
+    transform: Vec<TransformStep>,
+    element: Box<Element>,
+}
+
+#[cfg(feature = "serde")]
+fn transform_from_steps(steps: &[TransformStep]) -> Matrix4 {
+    steps.iter()
+        .fold(Matrix4::identity(), |matrix, step| {
+            let step = match *step {
+                TransformStep::Translate(ref offset) => Matrix4::translate(offset),
+                TransformStep::Scale(ref factors) => Matrix4::scale(factors),
+                TransformStep::Rotate { ref axis, degrees } => Matrix4::rotate(axis, degrees),
+            };
+            step * matrix
+        })
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<TransformedDescription> for Transformed {
+    type Error = String;
+
+    fn try_from(description: TransformedDescription) -> Result<Transformed, String> {
+        Transformed::new(*description.element, transform_from_steps(&description.transform))
+            .ok_or_else(|| "transform can't be inverted".to_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct GroupDescription {
+    #[serde(default)]
+    transform: Option<Vec<TransformStep>>,
+    elements: Vec<Element>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<GroupDescription> for Group {
+    type Error = String;
+
+    fn try_from(description: GroupDescription) -> Result<Group, String> {
+        let transform = description.transform.as_deref().map(transform_from_steps);
+        Group::new(description.elements, transform)
+            .ok_or_else(|| "transform can't be inverted".to_string())
+    }
+}
+
+static EMPTY_GROUP_MATERIAL: LazyLock<Material> =
+    LazyLock::new(|| Material::diffuse(Colour::black(), 0.0));
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "lowercase"))]
 pub enum Element {
     Sphere(Sphere),
     Plane(Plane),
+    Triangle(Triangle),
+    Disk(Disk),
+    Quad(Quad),
+    Box(AxisAlignedBox),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Torus(Torus),
+    Csg(Csg),
+    Transformed(Transformed),
+    Group(Group),
 }
 
 impl Element {
-    pub fn colour(&self) -> &Colour {
+    pub fn material(&self) -> &Material {
         match *self {
-            Element::Sphere(ref s) => &s.colour,
-            Element::Plane(ref p) => &p.colour,
+            Element::Sphere(ref s) => &s.material,
+            Element::Plane(ref p) => &p.material,
+            Element::Triangle(ref t) => &t.material,
+            Element::Disk(ref d) => &d.material,
+            Element::Quad(ref q) => &q.material,
+            Element::Box(ref b) => &b.material,
+            Element::Cylinder(ref c) => &c.material,
+            Element::Cone(ref c) => &c.material,
+            Element::Torus(ref t) => &t.material,
+            Element::Csg(ref c) => c.left.material(),
+            Element::Transformed(ref t) => t.element.material(),
+            // Hits are reported against the children, so this only stands
+            // in where one material is needed for the whole group.
+            Element::Group(ref g) => g.elements.first().map_or(&EMPTY_GROUP_MATERIAL, |e| e.material()),
         }
     }
 
-    pub fn albedo(&self) -> f32 {
-        match *self {
-            Element::Sphere(ref s) => s.albedo,
-            Element::Plane(ref p) => p.albedo,
+    // Whether `other` is this element or one inside it.
+    pub fn contains(&self, other: &Element) -> bool {
+        std::ptr::eq(self, other) || match *self {
+            Element::Group(ref g) => g.elements.iter().any(|e| e.contains(other)),
+            Element::Transformed(ref t) => t.element.contains(other),
+            _ => false,
         }
     }
+
+    pub fn colour(&self, hit_point: &Point) -> Colour {
+        self.material().colour.colour_at(hit_point, &self.texture_coords(hit_point))
+    }
+
+    pub fn albedo(&self) -> f32 {
+        self.material().albedo
+    }
 }
 
 
 pub struct Intersection<'a> {
     pub distance: f64,
     pub elements: &'a Element,
+    pub hit_point: Point,
+    // Facing the ray for two-sided surfaces, as from `Intersectable::surface_normal`.
+    pub surface_normal: Vector3,
 }
 
 impl<'a> Intersection<'a> {
-    pub fn new<'b>(distance: f64, element: &'b Element) -> Intersection<'b> {
+    // Degenerate geometry can produce infinite or NaN distances; those hits
+    // are dropped rather than allowed to upset the search for the nearest one.
+    pub fn new<'b>(ray: &Ray, distance: f64, element: &'b Element) -> Option<Intersection<'b>> {
         if !distance.is_finite() {
-            panic!("Intersection must have a finite distance.");
+            return None;
         }
-        
-        Intersection {
-            distance: distance,
+
+        let hit_point = ray.origin + ray.direction * distance;
+        let element = element.hit_at(ray, distance);
+        Some(Intersection {
+            distance,
             elements: element,
-        }
+            hit_point,
+            surface_normal: element.surface_normal(&hit_point, &ray.direction),
+        })
     }
 }
 
 
-impl Scene {
-    pub fn trace(&self, ray: &Ray) -> Option<Intersection> {
-        self.elements
-            .iter()
-            .filter_map(|e| e.intersect(ray).map(|d| Intersection::new(d, e)))
-            .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap())
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    #[cfg(feature = "serde")]
+    Parse(serde_json::Error),
+    ColourOutOfRange { owner: String, colour: Colour },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SceneError::Io(ref e) => write!(f, "could not read scene file: {}", e),
+            #[cfg(feature = "serde")]
+            SceneError::Parse(ref e) => write!(f, "invalid scene description: {}", e),
+            SceneError::ColourOutOfRange { ref owner, ref colour } => write!(
+                f,
+                "{} has a colour component out of range: ({}, {}, {})",
+                owner, colour.red, colour.green, colour.blue
+            ),
+        }
     }
 }
 
+impl Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+    fn from(e: io::Error) -> SceneError {
+        SceneError::Io(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for SceneError {
+    fn from(e: serde_json::Error) -> SceneError {
+        SceneError::Parse(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+fn check_colour(owner: String, colour: &Colour, max: f32) -> Result<(), SceneError> {
+    let in_range = |c: f32| (0.0..=max).contains(&c);
+    if in_range(colour.red) && in_range(colour.green) && in_range(colour.blue) {
+        Ok(())
+    } else {
+        Err(SceneError::ColourOutOfRange { owner, colour: *colour })
+    }
+}
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ObjError::Io(ref e) => write!(f, "could not read OBJ file: {}", e),
+            ObjError::Parse { line, ref message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for ObjError {}
+
+impl From<io::Error> for ObjError {
+    fn from(e: io::Error) -> ObjError {
+        ObjError::Io(e)
+    }
+}
+
+// Loads the triangles of a Wavefront OBJ model, all sharing `material`.
+pub fn load_obj(path: &str, material: &Material) -> Result<Vec<Element>, ObjError> {
+    let contents = fs::read_to_string(path)?;
+    parse_obj(&contents, material)
+}
+
+// Only vertex positions and faces are read. Faces may list plain vertex
+// indices or `v/vt/vn` triples (texture and normal indices are ignored), and
+// polygons are split into a fan of triangles around their first vertex.
+pub fn parse_obj(source: &str, material: &Material) -> Result<Vec<Element>, ObjError> {
+    let mut vertices: Vec<Point> = Vec::new();
+    let mut normals: Vec<Vector3> = Vec::new();
+    let mut elements = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let error = |message: String| ObjError::Parse { line: i + 1, message };
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next();
+        match keyword {
+            Some("v") | Some("vn") => {
+                let coords = tokens.take(3)
+                    .map(|t| {
+                        t.parse::<f64>()
+                            .map_err(|e| error(format!("bad coordinate {:?}: {}", t, e)))
+                    })
+                    .collect::<Result<Vec<f64>, ObjError>>()?;
+                if coords.len() < 3 {
+                    return Err(error("vertex needs three coordinates".to_string()));
+                }
+                if keyword == Some("v") {
+                    vertices.push(Point::new(coords[0], coords[1], coords[2]));
+                } else {
+                    normals.push(Vector3::new(coords[0], coords[1], coords[2]).normalize());
+                }
+            }
+            Some("f") => {
+                // Indices are 1-based; negative ones count back from the latest entry.
+                let resolve = |index: &str, count: usize, kind: &str| {
+                    let index = index.parse::<i64>()
+                        .map_err(|e| error(format!("bad {} index {:?}: {}", kind, index, e)))?;
+                    let resolved = if index < 0 {
+                        count as i64 + index
+                    } else {
+                        index - 1
+                    };
+                    if resolved < 0 || resolved as usize >= count {
+                        return Err(error(format!("{} index {} out of range", kind, index)));
+                    }
+                    Ok(resolved as usize)
+                };
+                // Each corner is v, v/vt, v//vn or v/vt/vn.
+                let face = tokens
+                    .map(|t| {
+                        let mut parts = t.split('/');
+                        let vertex = resolve(parts.next().unwrap_or(""), vertices.len(), "vertex")?;
+                        let normal = match parts.nth(1) {
+                            Some(n) if !n.is_empty() => Some(normals[resolve(n, normals.len(), "normal")?]),
+                            _ => None,
+                        };
+                        Ok((vertices[vertex], normal))
+                    })
+                    .collect::<Result<Vec<(Point, Option<Vector3>)>, ObjError>>()?;
+                if face.len() < 3 {
+                    return Err(error("face needs at least three vertices".to_string()));
+                }
+                for k in 1..face.len() - 1 {
+                    let corners = [face[0], face[k], face[k + 1]];
+                    let normals = match (corners[0].1, corners[1].1, corners[2].1) {
+                        (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                        _ => None,
+                    };
+                    elements.push(Element::Triangle(Triangle {
+                        a: corners[0].0,
+                        b: corners[1].0,
+                        c: corners[2].0,
+                        material: material.clone(),
+                        normals,
+                    }));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(elements)
+}
+
+// Chained construction of a `Scene`. Anything not set keeps the value from
+// `Scene::default()`, and `build` prepares the scene for rendering.
+pub struct SceneBuilder {
+    scene: Scene,
+}
+
+impl SceneBuilder {
+    pub fn new() -> SceneBuilder {
+        SceneBuilder {
+            scene: Scene::default(),
+        }
+    }
+
+    pub fn width(mut self, width: u32) -> SceneBuilder {
+        self.scene.width = width;
+        self
+    }
+
+    pub fn height(mut self, height: u32) -> SceneBuilder {
+        self.scene.height = height;
+        self
+    }
+
+    pub fn camera(mut self, camera: Camera) -> SceneBuilder {
+        self.scene.camera = camera;
+        self
+    }
+
+    pub fn add_camera(mut self, name: &str, camera: Camera) -> SceneBuilder {
+        self.scene.cameras.push((name.to_string(), camera));
+        self
+    }
+
+    // Switches to a perspective projection with the given field of view in degrees.
+    pub fn fov(mut self, fov: f64) -> SceneBuilder {
+        self.scene.camera.projection = Projection::Perspective { fov };
+        self
+    }
+
+    pub fn add_element(mut self, element: Element) -> SceneBuilder {
+        self.scene.elements.push(element);
+        self
+    }
+
+    pub fn add_light(mut self, light: Light) -> SceneBuilder {
+        self.scene.light.push(light);
+        self
+    }
+
+    pub fn shadow_bias(mut self, shadow_bias: f64) -> SceneBuilder {
+        self.scene.shadow_bias = shadow_bias;
+        self
+    }
+
+    pub fn shadow_epsilon(mut self, shadow_epsilon: f64) -> SceneBuilder {
+        self.scene.shadow_epsilon = shadow_epsilon;
+        self
+    }
+
+    pub fn shadow_jitter(mut self, shadow_jitter: f64) -> SceneBuilder {
+        self.scene.shadow_jitter = shadow_jitter;
+        self
+    }
+
+    pub fn shadows_enabled(mut self, shadows_enabled: bool) -> SceneBuilder {
+        self.scene.shadows_enabled = shadows_enabled;
+        self
+    }
+
+    pub fn light_sampling(mut self, light_sampling: LightSampling) -> SceneBuilder {
+        self.scene.light_sampling = light_sampling;
+        self
+    }
+
+    // Sets both the reflection and the refraction limit.
+    pub fn max_recursion_depth(mut self, depth: u32) -> SceneBuilder {
+        self.scene.max_reflection_depth = depth;
+        self.scene.max_refraction_depth = depth;
+        self
+    }
+
+    pub fn max_reflection_depth(mut self, depth: u32) -> SceneBuilder {
+        self.scene.max_reflection_depth = depth;
+        self
+    }
+
+    pub fn max_refraction_depth(mut self, depth: u32) -> SceneBuilder {
+        self.scene.max_refraction_depth = depth;
+        self
+    }
+
+    pub fn samples_per_pixel(mut self, samples: u32) -> SceneBuilder {
+        self.scene.samples_per_pixel = samples;
+        self
+    }
+
+    pub fn adaptive_sampling(mut self, max_samples: u32, variance_threshold: f32) -> SceneBuilder {
+        self.scene.max_samples = max_samples;
+        self.scene.variance_threshold = variance_threshold;
+        self
+    }
+
+    pub fn render_mode(mut self, render_mode: RenderMode) -> SceneBuilder {
+        self.scene.render_mode = render_mode;
+        self
+    }
+
+    pub fn depth_range(mut self, near: f64, far: f64) -> SceneBuilder {
+        self.scene.depth_near = near;
+        self.scene.depth_far = far;
+        self
+    }
+
+    pub fn wireframe_width(mut self, width: f64) -> SceneBuilder {
+        self.scene.wireframe_width = width;
+        self
+    }
+
+    pub fn focus_tolerance(mut self, tolerance: f64) -> SceneBuilder {
+        self.scene.focus_tolerance = tolerance;
+        self
+    }
+
+    pub fn rr_min_depth(mut self, rr_min_depth: u32) -> SceneBuilder {
+        self.scene.rr_min_depth = rr_min_depth;
+        self
+    }
+
+    pub fn denoise(mut self, denoise: bool) -> SceneBuilder {
+        self.scene.denoise = denoise;
+        self
+    }
+
+    pub fn rng_seed(mut self, seed: u64) -> SceneBuilder {
+        self.scene.rng_seed = Some(seed);
+        self
+    }
+
+    pub fn tile_size(mut self, tile_size: u32) -> SceneBuilder {
+        self.scene.tile_size = tile_size;
+        self
+    }
+
+    pub fn gamma(mut self, gamma: f32) -> SceneBuilder {
+        self.scene.gamma = gamma;
+        self
+    }
+
+    pub fn srgb_output(mut self, srgb_output: bool) -> SceneBuilder {
+        self.scene.srgb_output = srgb_output;
+        self
+    }
+
+    pub fn grayscale(mut self, grayscale: bool) -> SceneBuilder {
+        self.scene.grayscale = grayscale;
+        self
+    }
+
+    pub fn exposure(mut self, exposure: f32) -> SceneBuilder {
+        self.scene.exposure = exposure;
+        self
+    }
+
+    pub fn ambient(mut self, ambient: Colour) -> SceneBuilder {
+        self.scene.ambient = ambient;
+        self
+    }
+
+    pub fn background(mut self, background: Background) -> SceneBuilder {
+        self.scene.background = background;
+        self
+    }
+
+    pub fn fog(mut self, fog: Fog) -> SceneBuilder {
+        self.scene.fog = Some(fog);
+        self
+    }
+
+    pub fn acceleration(mut self, acceleration: Acceleration) -> SceneBuilder {
+        self.scene.acceleration = acceleration;
+        self
+    }
+
+    pub fn build(mut self) -> Scene {
+        self.scene.build_acceleration();
+        self.scene
+    }
+}
+
+impl Default for SceneBuilder {
+    fn default() -> SceneBuilder {
+        SceneBuilder::new()
+    }
+}
+
+impl Scene {
+    #[cfg(feature = "serde")]
+    pub fn from_json(path: &str) -> Result<Scene, SceneError> {
+        let contents = fs::read_to_string(path)?;
+        Scene::from_json_str(&contents)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json_str(json: &str) -> Result<Scene, SceneError> {
+        let mut value: serde_json::Value = serde_json::from_str(json)?;
+        // Older scene files give one `max_recursion_depth` for both reflection
+        // and refraction, which sets whichever of the two newer limits the
+        // file leaves out.
+        let old_depth = value.as_object_mut().and_then(|fields| fields.remove("max_recursion_depth"));
+        let recursion_depth = match old_depth {
+            Some(depth) => Option::<u32>::deserialize(depth)?,
+            None => None,
+        };
+        let reflection_given = value.get("max_reflection_depth").is_some();
+        let refraction_given = value.get("max_refraction_depth").is_some();
+        let mut scene = Scene::deserialize(value)?;
+        if let Some(depth) = recursion_depth {
+            if !reflection_given {
+                scene.max_reflection_depth = depth;
+            }
+            if !refraction_given {
+                scene.max_refraction_depth = depth;
+            }
+        }
+        scene.validate()?;
+        scene.build_acceleration();
+        Ok(scene)
+    }
+
+    // Surface colours are reflectances and must lie in [0, 1]; lights and
+    // emission may be brighter than 1 but never negative.
+    #[cfg(feature = "serde")]
+    fn validate(&self) -> Result<(), SceneError> {
+        for (i, element) in self.elements.iter().enumerate() {
+            if let Texture::Solid(ref colour) = element.material().colour {
+                check_colour(format!("element {}", i), colour, 1.0)?;
+            }
+            check_colour(format!("element {} emission", i), &element.material().emission, f32::INFINITY)?;
+        }
+        for (i, light) in self.light.iter().enumerate() {
+            check_colour(format!("light {}", i), &light.colour(), f32::INFINITY)?;
+        }
+        Ok(())
+    }
+
+    // Builds whichever structure `acceleration` selects, dropping the other.
+    pub fn build_acceleration(&mut self) {
+        (self.grid, self.bvh) = self.new_acceleration();
+        self.rebuilt = None;
+    }
+
+    pub fn build_bvh(&mut self) {
+        self.bvh = Some(Bvh::new(&self.elements));
+        self.rebuilt = None;
+    }
+
+    pub fn build_grid(&mut self) {
+        self.grid = Some(UniformGrid::new(&self.elements));
+        self.rebuilt = None;
+    }
+
+    fn new_acceleration(&self) -> (Option<UniformGrid>, Option<Bvh>) {
+        match self.acceleration {
+            Acceleration::Bvh => (None, Some(Bvh::new(&self.elements))),
+            Acceleration::Grid => (Some(UniformGrid::new(&self.elements)), None),
+        }
+    }
+
+    // The grid and BVH to trace with, first rebuilding them if elements have
+    // been edited since they were built. Either is left out when it doesn't
+    // match the elements.
+    fn acceleration_structures(&self) -> (Option<&UniformGrid>, Option<&Bvh>) {
+        let (grid, bvh) = match self.rebuilt {
+            Some(ref rebuilt) => {
+                let (grid, bvh) = rebuilt.get_or_init(|| self.new_acceleration());
+                (grid.as_ref(), bvh.as_ref())
+            }
+            None => (self.grid.as_ref(), self.bvh.as_ref()),
+        };
+        let count = self.elements.len();
+        (grid.filter(|grid| grid.element_count() == count),
+         bvh.filter(|bvh| bvh.element_count() == count))
+    }
+
+    // Index-based access to the elements, for editors. After any edit, an
+    // acceleration structure already built is rebuilt by the next trace, so
+    // a run of edits costs one rebuild. Elements after a removed one move
+    // down an index.
+    pub fn element_count(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn element(&self, i: usize) -> Option<&Element> {
+        self.elements.get(i)
+    }
+
+    pub fn element_mut(&mut self, i: usize) -> Option<&mut Element> {
+        if i < self.elements.len() {
+            self.elements_edited();
+        }
+        self.elements.get_mut(i)
+    }
+
+    pub fn add_element(&mut self, element: Element) -> usize {
+        self.elements.push(element);
+        self.elements_edited();
+        self.elements.len() - 1
+    }
+
+    pub fn remove_element(&mut self, i: usize) -> Option<Element> {
+        if i >= self.elements.len() {
+            return None;
+        }
+        let element = self.elements.remove(i);
+        self.elements_edited();
+        Some(element)
+    }
+
+    fn elements_edited(&mut self) {
+        if self.bvh.is_some() || self.grid.is_some() || self.rebuilt.is_some() {
+            self.bvh = None;
+            self.grid = None;
+            self.rebuilt = Some(OnceLock::new());
+        }
+    }
+
+    // Lights left unnamed have the empty name, so it never picks one out.
+    pub fn light_by_name(&self, name: &str) -> Option<&Light> {
+        self.light.iter().find(|light| !name.is_empty() && light.name() == name)
+    }
+
+    // Switches the named light on or off, returning false if there is no
+    // light of that name.
+    pub fn set_light_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.light.iter_mut().find(|light| !name.is_empty() && light.name() == name) {
+            Some(light) => {
+                light.set_enabled(enabled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn camera_named(&self, name: &str) -> Option<&Camera> {
+        self.cameras.iter().find(|(n, _)| n == name).map(|(_, camera)| camera)
+    }
+
+    // The box around every bounded element. Planes go on for ever, so are
+    // left out; with nothing else in the scene the box is empty.
+    pub fn bounding_box(&self) -> BoundingBox {
+        self.elements.iter()
+            .filter_map(|e| e.bounding_box())
+            .fold(BoundingBox::empty(), |acc, b| acc.union(&b))
+    }
+
+    // Identifies one of the scene's elements by its position in `elements`,
+    // counting from 1 so that 0 can stand for no element. Elements inside a
+    // group or transform share its id.
+    pub fn element_id(&self, element: &Element) -> u32 {
+        self.elements.iter()
+            .position(|e| e.contains(element))
+            .map_or(0, |i| i as u32 + 1)
+    }
+
+    pub fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.trace_from(ray, 0.0)
+    }
+
+    // Like trace, ignoring hits closer than `t_min` along the ray.
+    pub fn trace_from(&self, ray: &Ray, t_min: f64) -> Option<Intersection<'_>> {
+        if self.render_mode == RenderMode::RayMarch {
+            return sdf::march_from(ray, self, t_min);
+        }
+        let mut tests = 0;
+        let intersection = match self.acceleration_structures() {
+            (Some(grid), _) => grid.trace(&self.elements, ray, t_min, &mut tests),
+            (None, Some(bvh)) => bvh.trace(&self.elements, ray, t_min, &mut tests),
+            (None, None) => {
+                tests = self.elements.len() as u64;
+                self.trace_linear(ray, t_min)
+            }
+        };
+        self.count(Counter::IntersectionTests, tests);
+        intersection
+    }
+
+    // Whether anything lies along the ray closer than `max_distance`, for
+    // shadow rays that only need to know if the light is blocked. Hits closer
+    // than `shadow_epsilon` are ignored, and the search stops at the first
+    // blocker found rather than looking for the nearest.
+    pub fn trace_shadow(&self, ray: &Ray, max_distance: f64) -> bool {
+        let t_min = self.shadow_epsilon;
+        if self.render_mode == RenderMode::RayMarch {
+            return sdf::march_from(ray, self, t_min).is_some_and(|i| i.distance < max_distance);
+        }
+        let mut tests = 0;
+        let occluded = match self.acceleration_structures() {
+            (Some(grid), _) => grid.occluded(&self.elements, ray, t_min, max_distance, &mut tests),
+            (None, Some(bvh)) => bvh.occluded(&self.elements, ray, t_min, max_distance, &mut tests),
+            (None, None) => self.elements.iter().any(|e| {
+                tests += 1;
+                e.intersect(ray, t_min).is_some_and(|d| d < max_distance)
+            }),
+        };
+        self.count(Counter::IntersectionTests, tests);
+        occluded
+    }
+
+    // Adds to one of the render statistics, if they're being gathered.
+    pub fn count(&self, counter: Counter, n: u64) {
+        if let Some(ref stats) = self.stats {
+            stats.add(counter, n);
+        }
+        stats::count(counter, n);
+    }
+
+    fn trace_linear(&self, ray: &Ray, t_min: f64) -> Option<Intersection<'_>> {
+        self.elements
+            .iter()
+            .filter_map(|e| e.intersect(ray, t_min).filter(|d| d.is_finite()).map(|d| (d, e)))
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+            .and_then(|(d, e)| Intersection::new(ray, d, e))
+    }
+}
+
+#[test]
+fn test_colour_lerp() {
+    let a = Colour {
+        red: 0.2,
+        green: 0.4,
+        blue: 1.0,
+    };
+    let b = Colour {
+        red: 0.6,
+        green: 0.0,
+        blue: 0.5,
+    };
+
+    let start = a.lerp(&b, 0.0);
+    assert_eq!((start.red, start.green, start.blue), (0.2, 0.4, 1.0));
+    let end = a.lerp(&b, 1.0);
+    assert_eq!((end.red, end.green, end.blue), (0.6, 0.0, 0.5));
+    let middle = a.lerp(&b, 0.5);
+    assert!((middle.red - 0.4).abs() < 1e-6);
+    assert!((middle.green - 0.2).abs() < 1e-6);
+    assert!((middle.blue - 0.75).abs() < 1e-6);
+    let beyond = a.lerp(&b, 3.0);
+    assert_eq!((beyond.red, beyond.green, beyond.blue), (0.6, 0.0, 0.5));
+}
+
+#[test]
+fn test_clamp_preserve_hue() {
+    let rgb = |c: Colour| (c.red, c.green, c.blue);
+    let red = Colour {
+        red: 4.0,
+        green: 0.0,
+        blue: 0.0,
+    };
+    assert_eq!(rgb(red.clamp()), (1.0, 0.0, 0.0));
+
+    // Still reddest, but washed out towards white with its luminance kept.
+    let kept = ToneMap::PreserveHue.apply(&red);
+    assert_eq!(rgb(kept), rgb(red.clamp_preserve_hue()));
+    assert_eq!(kept.red, 1.0);
+    assert!(kept.green > 0.0 && kept.green < 1.0);
+    assert_eq!(kept.green, kept.blue);
+    assert!((kept.luminance() - red.luminance()).abs() < 1e-6);
+
+    // Colours already in range are left alone, and very bright ones are white.
+    assert_eq!(rgb(Colour::from_one(0.5).clamp_preserve_hue()), (0.5, 0.5, 0.5));
+    assert_eq!(rgb(Colour::from_one(3.0).clamp_preserve_hue()), (1.0, 1.0, 1.0));
+}
+
+#[test]
+fn test_colour_hex() {
+    let red = Colour::from_hex("#ff0000").unwrap();
+    assert_eq!((red.red, red.green, red.blue), (1.0, 0.0, 0.0));
+
+    let colour = Colour::from_hex("#3A7bC0").unwrap();
+    assert_eq!(colour.to_hex(), "#3a7bc0");
+    assert_eq!(Colour::from_hex(&colour.to_hex()).unwrap().to_hex(), "#3a7bc0");
+
+    // sRGB mid grey is about a fifth in linear terms.
+    let grey = Colour::from_srgb_hex("#808080").unwrap();
+    assert!((grey.red - 0.2158).abs() < 1e-3);
+
+    for bad in ["ff0000", "#ff00", "#ff00zz", "#ff00000"] {
+        assert!(Colour::from_hex(bad).is_err(), "{} should not parse", bad);
+    }
+}
+
+#[test]
+fn test_srgb_transfer_function() {
+    let grey = |v: f32| Colour { red: v, green: v, blue: v };
+
+    // Both pieces of the curve meet at the 0.04045 threshold.
+    let below = grey(0.04045).srgb_to_linear().red;
+    assert!((below - 0.04045 / 12.92).abs() < 1e-7);
+    let above = grey(0.04046).srgb_to_linear().red;
+    assert!(above > below && above - below < 1e-5);
+    assert!((grey(0.0031308).linear_to_srgb().red - 0.04045).abs() < 1e-5);
+
+    assert_eq!(grey(0.0).srgb_to_linear().red, 0.0);
+    assert!((grey(1.0).srgb_to_linear().red - 1.0).abs() < 1e-6);
+    for v in [0.01, 0.2, 0.5, 0.9] {
+        assert!((grey(v).srgb_to_linear().linear_to_srgb().red - v).abs() < 1e-5);
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_colour_hex_in_json() {
+    let colour: Colour = serde_json::from_str("\"#00ff00\"").unwrap();
+    assert_eq!((colour.red, colour.green, colour.blue), (0.0, 1.0, 0.0));
+    let colour: Colour = serde_json::from_str(r#"{"red": 0.5, "green": 0.25, "blue": 1.0}"#).unwrap();
+    assert_eq!((colour.red, colour.green, colour.blue), (0.5, 0.25, 1.0));
+    let colour: Colour = serde_json::from_str(r##"{"srgb": "#808080"}"##).unwrap();
+    assert!((colour.red - 0.2158).abs() < 1e-3);
+    assert!(serde_json::from_str::<Colour>("\"#00ff\"").is_err());
+}
+
+#[test]
+fn test_colour_sub() {
+    let c = Colour {
+        red: 1.0,
+        green: 0.5,
+        blue: 0.25,
+    } - Colour {
+        red: 0.5,
+        green: 0.5,
+        blue: 0.5,
+    };
+    assert_eq!((c.red, c.green, c.blue), (0.5, 0.0, -0.25));
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_scene_from_json() {
+    let scene = Scene::from_json_str(r#"{
+        "width": 320,
+        "height": 240,
+        "elements": [
+            {
+                "type": "sphere",
+                "center": { "x": 0.0, "y": 0.0, "z": -5.0 },
+                "radius": 1.0,
+                "material": {
+                    "colour": { "red": 0.2, "green": 0.4, "blue": 0.6 },
+                    "albedo": 0.18
+                }
+            }
+        ],
+        "lights": [
+            {
+                "type": "directional",
+                "direction": { "x": 0.0, "y": -1.0, "z": 0.0 },
+                "colour": { "red": 1.0, "green": 1.0, "blue": 1.0 },
+                "intensity": 5.0
+            }
+        ]
+    }"#).unwrap();
+
+    assert_eq!((scene.width, scene.height), (320, 240));
+    assert!(matches!(scene.camera.projection, Projection::Perspective { fov } if fov == 90.0));
+    assert_eq!(scene.elements.len(), 1);
+    assert_eq!(scene.light.len(), 1);
+    assert_eq!(scene.elements[0].material().reflectivity, 0.0);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_scene_from_json_missing_field() {
+    let result = Scene::from_json_str(r#"{
+        "elements": [
+            {
+                "type": "sphere",
+                "center": { "x": 0.0, "y": 0.0, "z": -5.0 },
+                "material": {
+                    "colour": { "red": 0.2, "green": 0.4, "blue": 0.6 },
+                    "albedo": 0.18
+                }
+            }
+        ]
+    }"#);
+
+    match result {
+        Err(SceneError::Parse(e)) => assert!(e.to_string().contains("radius")),
+        _ => panic!("expected a parse error"),
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_scene_from_json_colour_out_of_range() {
+    let result = Scene::from_json_str(r#"{
+        "elements": [
+            {
+                "type": "plane",
+                "origin": { "x": 0.0, "y": -2.0, "z": 0.0 },
+                "normal": { "x": 0.0, "y": -1.0, "z": 0.0 },
+                "material": {
+                    "colour": { "red": 1.5, "green": 0.4, "blue": 0.6 },
+                    "albedo": 0.18
+                }
+            }
+        ]
+    }"#);
+
+    assert!(matches!(result, Err(SceneError::ColourOutOfRange { .. })));
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_scene_from_json_old_recursion_depth() {
+    let scene = Scene::from_json_str(r#"{ "max_recursion_depth": 7 }"#).unwrap();
+    assert_eq!((scene.max_reflection_depth, scene.max_refraction_depth), (7, 7));
+
+    // The newer keys take precedence where both are given.
+    let scene = Scene::from_json_str(r#"{
+        "max_recursion_depth": 7,
+        "max_refraction_depth": 2
+    }"#).unwrap();
+    assert_eq!((scene.max_reflection_depth, scene.max_refraction_depth), (7, 2));
+}
+
+#[test]
+fn test_builder_recursion_depth_sets_both_limits() {
+    let scene = SceneBuilder::new().max_recursion_depth(9).build();
+    assert_eq!((scene.max_reflection_depth, scene.max_refraction_depth), (9, 9));
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_scene_from_json_transformed_element() {
+    let scene = Scene::from_json_str(r#"{
+        "elements": [
+            {
+                "type": "transformed",
+                "transform": [
+                    { "scale": { "x": 2.0, "y": 2.0, "z": 2.0 } },
+                    { "translate": { "x": 0.0, "y": 0.0, "z": -5.0 } }
+                ],
+                "element": {
+                    "type": "sphere",
+                    "center": { "x": 0.0, "y": 0.0, "z": 0.0 },
+                    "radius": 1.0,
+                    "material": {
+                        "colour": { "red": 0.2, "green": 0.4, "blue": 0.6 },
+                        "albedo": 0.18
+                    }
+                }
+            }
+        ]
+    }"#).unwrap();
+
+    // Scaled first and then moved, so the front of the sphere is at z = -3.
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert!((scene.trace(&ray).unwrap().distance - 3.0).abs() < 1e-12);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_scene_from_json_group() {
+    let sphere = |x: f64| format!(r#"{{
+        "type": "sphere",
+        "center": {{ "x": {}, "y": 0.0, "z": -5.0 }},
+        "radius": 0.5,
+        "material": {{ "colour": {{ "red": 0.2, "green": 0.4, "blue": 0.6 }}, "albedo": 0.18 }}
+    }}"#, x);
+    let scene = Scene::from_json_str(&format!(r#"{{
+        "elements": [
+            {{
+                "type": "group",
+                "transform": [{{ "translate": {{ "x": 0.0, "y": 2.0, "z": 0.0 }} }}],
+                "elements": [{}, {}]
+            }}
+        ]
+    }}"#, sphere(-1.0), sphere(1.0))).unwrap();
+
+    for x in [-1.0, 1.0] {
+        let ray = Ray {
+            origin: Point::new(x, 2.0, 0.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let hit = scene.trace(&ray).unwrap();
+        assert!((hit.distance - 4.5).abs() < 1e-12);
+        assert_eq!(scene.element_id(hit.elements), 1);
+    }
+}
+
+#[cfg(test)]
+fn test_spot_light() -> Light {
+    Light::Spot(SpotLight {
+        position: Point::new(0.0, 10.0, 0.0),
+        direction: Vector3::new(0.0, -1.0, 0.0),
+        colour: Colour {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        },
+        intensity: 1000.0,
+        cone_angle: 30.0,
+        falloff: 10.0,
+        name: String::new(),
+        enabled: true,
+    })
+}
+
+#[test]
+fn test_spot_light_lights_its_axis() {
+    let light = test_spot_light();
+    let on_axis = Point::new(0.0, 0.0, 0.0);
+
+    let expected = 1000.0 / (4.0 * std::f32::consts::PI * 100.0);
+    assert!((light.intensity(&on_axis) - expected).abs() < 1e-6);
+}
+
+#[test]
+fn test_spot_light_is_dark_outside_cone() {
+    let light = test_spot_light();
+    // 45 degrees off the axis.
+    let outside = Point::new(10.0, 0.0, 0.0);
+    assert_eq!(light.intensity(&outside), 0.0);
+}
+
+#[test]
+fn test_spot_light_fades_at_edge() {
+    let light = test_spot_light();
+    let full = light.intensity(&Point::new(0.0, 0.0, 0.0));
+    // 25 degrees off the axis, inside the falloff band.
+    let edge = Point::new(10.0 * 25.0f64.to_radians().tan(), 0.0, 0.0);
+    let intensity = light.intensity(&edge);
+    assert!(intensity > 0.0);
+    assert!(intensity < full);
+}
+
+#[test]
+fn test_light_attenuation() {
+    let light = |attenuation: Attenuation| Light::Spherical(SphericalLight {
+        position: Point::zero(),
+        colour: Colour::from_one(1.0),
+        intensity: 1000.0,
+        radius: 0.0,
+        attenuation,
+        name: String::new(),
+        enabled: true,
+    });
+    let at = |distance: f64| Point::new(0.0, distance, 0.0);
+    let unit = 1000.0 / (4.0 * std::f32::consts::PI);
+
+    // Inverse-square matches the falloff of a plain point light.
+    let squared = light(Attenuation::InverseSquare);
+    assert!((squared.intensity(&at(10.0)) - unit / 100.0).abs() < 1e-6);
+    assert_eq!(squared.intensity(&at(3.0)), inverse_square(1000.0, &Point::zero(), &at(3.0)));
+
+    let linear = light(Attenuation::Linear);
+    assert!((linear.intensity(&at(10.0)) - unit / 10.0).abs() < 1e-6);
+
+    let none = light(Attenuation::None);
+    assert_eq!(none.intensity(&at(1.0)), unit);
+    assert_eq!(none.intensity(&at(1000.0)), unit);
+}
+
+#[test]
+fn test_bvh_trace_matches_brute_force() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    let mut rng = StdRng::seed_from_u64(15);
+    let mut elements = Vec::new();
+    for _ in 0..200 {
+        elements.push(Element::Sphere(Sphere {
+            center: Point::new(rng.gen_range(-10.0..10.0),
+                               rng.gen_range(-10.0..10.0),
+                               rng.gen_range(-30.0..-5.0)),
+            radius: rng.gen_range(0.1..1.5),
+            material: Material::diffuse(Colour::black(), 0.18),
+        }));
+    }
+    elements.push(Element::Plane(Plane {
+        origin: Point::new(0.0, -8.0, 0.0),
+        normal: Vector3::new(0.0, -1.0, 0.0),
+        material: Material::diffuse(Colour::black(), 0.18),
+    }));
+    let mut scene = Scene {
+        elements,
+        ..Scene::default()
+    };
+    scene.build_bvh();
+
+    for _ in 0..2000 {
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(rng.gen_range(-0.6..0.6), rng.gen_range(-0.6..0.6), -1.0)
+                .normalize(),
+        };
+        let expected = scene.trace_linear(&ray, 0.0);
+        let actual = scene.trace(&ray);
+        match (expected, actual) {
+            (None, None) => {}
+            (Some(e), Some(a)) => {
+                assert_eq!(e.distance, a.distance);
+                assert!(std::ptr::eq(e.elements, a.elements));
+            }
+            _ => panic!("BVH and brute force disagree on whether the ray hits"),
+        }
+    }
+}
+
+#[test]
+fn test_stale_bvh_is_ignored() {
+    let sphere = |z: f64| Element::Sphere(Sphere {
+        center: Point::new(0.0, 0.0, z),
+        radius: 0.5,
+        material: Material::diffuse(Colour::black(), 0.18),
+    });
+    let mut scene = Scene {
+        elements: vec![sphere(-5.0), sphere(-10.0)],
+        ..Scene::default()
+    };
+    scene.build_bvh();
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+
+    // Removing an element leaves the tree indexing past the end.
+    scene.elements.remove(0);
+    assert!((scene.trace(&ray).unwrap().distance - 9.5).abs() < 1e-9);
+    assert!(scene.trace_shadow(&ray, 20.0));
+
+    // An element pushed after the tree was built is still hit.
+    scene.elements.push(sphere(-2.0));
+    assert!((scene.trace(&ray).unwrap().distance - 1.5).abs() < 1e-9);
+}
+
+#[test]
+fn test_stale_grid_is_ignored() {
+    let sphere = |z: f64| Element::Sphere(Sphere {
+        center: Point::new(0.0, 0.0, z),
+        radius: 0.5,
+        material: Material::diffuse(Colour::black(), 0.18),
+    });
+    let mut scene = Scene {
+        elements: vec![sphere(-5.0), sphere(-10.0)],
+        ..Scene::default()
+    };
+    scene.build_grid();
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+
+    scene.elements.remove(0);
+    assert!((scene.trace(&ray).unwrap().distance - 9.5).abs() < 1e-9);
+    assert!(scene.trace_shadow(&ray, 20.0));
+
+    scene.elements.push(sphere(-2.0));
+    assert!((scene.trace(&ray).unwrap().distance - 1.5).abs() < 1e-9);
+}
+
+#[test]
+fn test_grid_trace_matches_brute_force() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    let mut elements = Vec::new();
+    for x in 0..10 {
+        for y in 0..10 {
+            for z in 0..10 {
+                elements.push(Element::Sphere(Sphere {
+                    center: Point::new(x as f64 * 2.0 - 9.0, y as f64 * 2.0 - 9.0, z as f64 * -2.0 - 10.0),
+                    radius: 0.6,
+                    material: Material::diffuse(Colour::black(), 0.18),
+                }));
+            }
+        }
+    }
+    elements.push(Element::Plane(Plane {
+        origin: Point::new(0.0, -12.0, 0.0),
+        normal: Vector3::new(0.0, -1.0, 0.0),
+        material: Material::diffuse(Colour::black(), 0.18),
+    }));
+    let mut scene = Scene {
+        elements,
+        acceleration: Acceleration::Grid,
+        ..Scene::default()
+    };
+    scene.build_acceleration();
+    assert!(scene.grid.is_some() && scene.bvh.is_none());
+
+    // Rays from outside the grid and from within it, in every direction.
+    let mut rng = StdRng::seed_from_u64(21);
+    for i in 0..3000 {
+        let origin = if i % 2 == 0 {
+            Point::zero()
+        } else {
+            Point::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0), rng.gen_range(-30.0..-9.0))
+        };
+        let ray = Ray {
+            origin,
+            direction: Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0))
+                .normalize(),
+        };
+        let expected = scene.trace_linear(&ray, 0.0);
+        let actual = scene.trace(&ray);
+        match (expected, actual) {
+            (None, None) => {}
+            (Some(e), Some(a)) => {
+                assert_eq!(e.distance, a.distance);
+                assert!(std::ptr::eq(e.elements, a.elements));
+            }
+            _ => panic!("grid and brute force disagree on whether the ray hits"),
+        }
+    }
+}
+
+#[test]
+fn test_trace_shadow_finds_any_blocker() {
+    let sphere = |z: f64| Element::Sphere(Sphere {
+        center: Point::new(0.0, 0.0, z),
+        radius: 1.0,
+        material: Material::diffuse(Colour::black(), 0.18),
+    });
+    let towards = |x: f64| Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(x, 0.0, -1.0).normalize(),
+    };
+
+    for acceleration in [None, Some(Acceleration::Bvh), Some(Acceleration::Grid)] {
+        let mut scene = Scene {
+            elements: vec![sphere(-5.0), sphere(-10.0)],
+            ..Scene::default()
+        };
+        if let Some(acceleration) = acceleration {
+            scene.acceleration = acceleration;
+            scene.build_acceleration();
+        }
+
+        // Either sphere alone is enough to block the ray, whichever is found.
+        assert!(scene.trace_shadow(&towards(0.0), 20.0));
+        assert!(scene.trace_shadow(&towards(0.0), 5.0));
+        // Nothing lies in the way of a light in front of both spheres, or off
+        // to one side.
+        assert!(!scene.trace_shadow(&towards(0.0), 3.5));
+        assert!(!scene.trace_shadow(&towards(1.0), 20.0));
+    }
+}
+
+#[test]
+fn test_scene_bounding_box() {
+    let sphere = |x: f64| Element::Sphere(Sphere {
+        center: Point::new(x, 0.0, 0.0),
+        radius: 1.0,
+        material: Material::diffuse(Colour::black(), 0.18),
+    });
+    let scene = Scene {
+        elements: vec![
+            sphere(-5.0),
+            sphere(5.0),
+            Element::Plane(Plane {
+                origin: Point::new(0.0, -1.0, 0.0),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                material: Material::diffuse(Colour::black(), 0.18),
+            }),
+        ],
+        ..Scene::default()
+    };
+    let bounds = scene.bounding_box();
+    assert!(bounds.min.approx_eq(&Point::new(-6.0, -1.0, -1.0), 1e-12));
+    assert!(bounds.max.approx_eq(&Point::new(6.0, 1.0, 1.0), 1e-12));
+}
+
+#[test]
+fn test_frame_scene_keeps_everything_in_view() {
+    let sphere = |x: f64, y: f64, z: f64, radius: f64| Element::Sphere(Sphere {
+        center: Point::new(x, y, z),
+        radius,
+        material: Material::diffuse(Colour::black(), 0.18),
+    });
+    let mut scene = Scene {
+        width: 64,
+        height: 48,
+        elements: vec![sphere(-5.0, 0.0, 0.0, 1.0), sphere(5.0, 2.0, -3.0, 2.0), sphere(0.0, 8.0, 1.0, 0.5)],
+        camera: Camera {
+            projection: Projection::Perspective { fov: 60.0 },
+            ..Camera::default()
+        },
+        ..Scene::default()
+    };
+    let bounds = scene.bounding_box();
+
+    for direction in [Vector3::new(0.0, 0.0, -1.0), Vector3::new(1.0, -0.5, -1.0),
+                      Vector3::new(0.0, -1.0, 0.0)] {
+        scene.camera = Camera::frame_scene(&scene, &direction);
+        for i in 0..8 {
+            let corner = Point::new(if i & 1 == 0 { bounds.min.x } else { bounds.max.x },
+                                    if i & 2 == 0 { bounds.min.y } else { bounds.max.y },
+                                    if i & 4 == 0 { bounds.min.z } else { bounds.max.z });
+            // Project the corner onto the image and find its pixel.
+            let (right, up, forward) = scene.camera.basis();
+            let offset = corner - scene.camera.position;
+            let depth = offset.dot_prod(&forward);
+            assert!(depth > 0.0);
+            let half_side = (30.0f64).to_radians().tan() * depth;
+            let shorter = 48.0;
+            let x = 32.0 + offset.dot_prod(&right) / half_side * shorter / 2.0;
+            let y = 24.0 - offset.dot_prod(&up) / half_side * shorter / 2.0;
+            assert!((0.0..=64.0).contains(&x) && (0.0..=48.0).contains(&y), "{} {}", x, y);
+        }
+    }
+
+    // With nothing bounded to frame, the camera stays put.
+    let empty = Scene::default();
+    let camera = Camera::frame_scene(&empty, &Vector3::new(0.0, 0.0, -1.0));
+    assert!(camera.position.approx_eq(&empty.camera.position, 0.0));
+}
+
+#[test]
+fn test_edit_elements_by_index() {
+    let sphere = |x: f64| Element::Sphere(Sphere {
+        center: Point::new(x, 0.0, -5.0),
+        radius: 0.5,
+        material: Material::diffuse(Colour::black(), 0.18),
+    });
+    let centre_x = |scene: &Scene, i: usize| match scene.element(i) {
+        Some(Element::Sphere(s)) => s.center.x,
+        _ => panic!("expected a sphere"),
+    };
+    let mut scene = SceneBuilder::new().build();
+    assert_eq!(scene.element_count(), 0);
+    for (i, x) in [-2.0, 0.0, 2.0].into_iter().enumerate() {
+        assert_eq!(scene.add_element(sphere(x)), i);
+    }
+
+    // Removing the middle sphere shifts the last one down, unchanged.
+    assert!(matches!(scene.remove_element(1), Some(Element::Sphere(ref s)) if s.center.x == 0.0));
+    assert_eq!(scene.element_count(), 2);
+    assert_eq!((centre_x(&scene, 0), centre_x(&scene, 1)), (-2.0, 2.0));
+    assert!(scene.remove_element(2).is_none() && scene.element(2).is_none());
+
+    // Adding it back puts it at the end, and the rebuilt BVH sees it.
+    assert_eq!(scene.add_element(sphere(0.0)), 2);
+    assert_eq!(centre_x(&scene, 2), 0.0);
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert!(scene.trace(&ray).is_some());
+    assert!(scene.acceleration_structures().1.is_some());
+
+    // Moving a sphere through element_mut takes effect straight away, and
+    // traces still go through a BVH rather than testing every element.
+    if let Some(Element::Sphere(s)) = scene.element_mut(2) {
+        s.center.x = 5.0;
+    }
+    assert_eq!((centre_x(&scene, 0), centre_x(&scene, 1), centre_x(&scene, 2)), (-2.0, 2.0, 5.0));
+    assert!(scene.trace(&ray).is_none());
+    let (grid, bvh) = scene.acceleration_structures();
+    assert!(grid.is_none() && bvh.is_some_and(|bvh| bvh.element_count() == 3));
+    let moved = Ray {
+        origin: Point::new(5.0, 0.0, 0.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert!(scene.trace(&moved).is_some_and(|hit| (hit.distance - 4.5).abs() < 1e-9));
+}
+
+#[test]
+fn test_parse_obj_triangulates_quad() {
+    let obj = "# a unit square\n\
+               v 0 0 0\n\
+               v 1 0 0\n\
+               v 1 1 0\n\
+               v 0 1 0\n\
+               vt 0 0\n\
+               vn 0 0 1\n\
+               f 1/1/1 2/1/1 3/1/1 4/1/1\n";
+    let elements = parse_obj(obj, &Material::diffuse(Colour::black(), 0.18)).unwrap();
+    assert_eq!(elements.len(), 2);
+    match elements[1] {
+        Element::Triangle(ref t) => {
+            assert_eq!((t.a.x, t.a.y), (0.0, 0.0));
+            assert_eq!((t.b.x, t.b.y), (1.0, 1.0));
+            assert_eq!((t.c.x, t.c.y), (0.0, 1.0));
+            let normals = t.normals.unwrap();
+            assert!(normals.iter().all(|n| n.approx_eq(&Vector3::new(0.0, 0.0, 1.0), 1e-12)));
+        }
+        _ => panic!("expected a triangle"),
+    }
+}
+
+#[test]
+fn test_parse_obj_rejects_missing_vertex() {
+    let obj = "v 0 0 0\nv 1 0 0\nf 1 2 3\n";
+    match parse_obj(obj, &Material::diffuse(Colour::black(), 0.18)) {
+        Err(ObjError::Parse { line: 3, .. }) => {}
+        other => panic!("expected a parse error on line 3, got {:?}", other.map(|e| e.len())),
+    }
+}
+
+#[test]
+fn test_trace_skips_non_finite_distances() {
+    let far_plane = Element::Plane(Plane {
+        origin: Point::new(0.0, 1e305, 0.0),
+        normal: Vector3::new(0.0, 1.0, 0.0),
+        material: Material::diffuse(Colour::black(), 0.18),
+    });
+    // Barely tilted towards the plane, so the hit distance overflows.
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(1.0, 2e-6, 0.0).normalize(),
+    };
+    assert_eq!(far_plane.intersect(&ray, 0.0), Some(f64::INFINITY));
+
+    let mut scene = Scene {
+        elements: vec![far_plane],
+        ..Scene::default()
+    };
+    assert!(scene.trace(&ray).is_none());
+    scene.build_bvh();
+    assert!(scene.trace(&ray).is_none());
+}
+
+#[test]
+fn test_trace_reports_hit_point_and_normal() {
+    let mut scene = Scene {
+        elements: vec![
+            Element::Sphere(Sphere {
+                center: Point::new(0.5, 0.0, -5.0),
+                radius: 1.0,
+                material: Material::diffuse(Colour::black(), 0.18),
+            }),
+            Element::Plane(Plane {
+                origin: Point::new(0.0, -1.0, 0.0),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                material: Material::diffuse(Colour::black(), 0.18),
+            }),
+        ],
+        ..Scene::default()
+    };
+    let ray = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.1, -0.05, -1.0).normalize(),
+    };
+
+    for _ in 0..2 {
+        let hit = scene.trace(&ray).unwrap();
+        let expected = ray.origin + ray.direction * hit.distance;
+        assert!(hit.hit_point.approx_eq(&expected, 0.0));
+        let normal = hit.elements.surface_normal(&expected, &ray.direction);
+        assert!(hit.surface_normal.approx_eq(&normal, 0.0));
+        // The same hit is found with and without the BVH.
+        scene.build_bvh();
+    }
+}
@@ -0,0 +1,130 @@
+use crate::point::Point;
+use crate::vector::Vector3;
+use crate::render::Ray;
+use crate::scene::{Scene, Element, Intersection, Sphere, Plane};
+
+const MAX_STEPS: u32 = 256;
+const MAX_DISTANCE: f64 = 1e4;
+// A point this close to a surface counts as on it. It must be smaller than
+// the scene's `shadow_bias`, or rays leaving a surface would hit it again.
+const SURFACE_EPSILON: f64 = 1e-6;
+// Step used for the finite differences that estimate surface normals.
+const GRADIENT_STEP: f64 = 1e-6;
+
+// A signed distance field: the distance from a point to the nearest point on
+// a surface, negative inside solid shapes.
+pub trait Sdf {
+    fn distance(&self, p: &Point) -> f64;
+}
+
+impl Sdf for Sphere {
+    fn distance(&self, p: &Point) -> f64 {
+        p.distance(&self.center) - self.radius
+    }
+}
+
+// Positive on the side the normal faces.
+impl Sdf for Plane {
+    fn distance(&self, p: &Point) -> f64 {
+        (*p - self.origin).dot_prod(&self.normal.normalize())
+    }
+}
+
+impl Element {
+    // Only spheres and planes have distance fields so far.
+    pub fn sdf(&self) -> Option<&dyn Sdf> {
+        match *self {
+            Element::Sphere(ref s) => Some(s),
+            Element::Plane(ref p) => Some(p),
+            _ => None,
+        }
+    }
+}
+
+// The direction in which the distance field grows fastest, by central differences.
+pub fn sdf_normal(sdf: &dyn Sdf, p: &Point) -> Vector3 {
+    let axes = [
+        Vector3::new(GRADIENT_STEP, 0.0, 0.0),
+        Vector3::new(0.0, GRADIENT_STEP, 0.0),
+        Vector3::new(0.0, 0.0, GRADIENT_STEP),
+    ];
+    let d: Vec<f64> = axes.iter()
+        .map(|&h| sdf.distance(&(*p + h)) - sdf.distance(&(*p - h)))
+        .collect();
+    Vector3::new(d[0], d[1], d[2]).normalize()
+}
+
+pub fn march<'a>(ray: &Ray, scene: &'a Scene) -> Option<Intersection<'a>> {
+    march_from(ray, scene, 0.0)
+}
+
+// Sphere tracing: steps along the ray by the distance to the nearest surface,
+// which can't overshoot any of them, until that distance is below
+// SURFACE_EPSILON. Distances are taken unsigned so that rays inside a sphere
+// march out to its far side. Gives up after MAX_STEPS or beyond MAX_DISTANCE.
+pub fn march_from<'a>(ray: &Ray, scene: &'a Scene, t_min: f64) -> Option<Intersection<'a>> {
+    let mut t = t_min;
+    for _ in 0..MAX_STEPS {
+        let p = ray.origin + ray.direction * t;
+        let nearest = scene.elements.iter()
+            .filter_map(|e| e.sdf().map(|sdf| (e, sdf.distance(&p).abs())))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        let (element, distance) = nearest?;
+
+        if distance < SURFACE_EPSILON {
+            let mut normal = sdf_normal(element.sdf().unwrap(), &p);
+            // Planes are two-sided, so their normal is flipped to face the ray.
+            if let Element::Plane(_) = *element {
+                if normal.dot_prod(&ray.direction) > 0.0 {
+                    normal = -normal;
+                }
+            }
+            return Some(Intersection {
+                distance: t,
+                elements: element,
+                hit_point: p,
+                surface_normal: normal,
+            });
+        }
+        t += distance;
+        if t > MAX_DISTANCE {
+            break;
+        }
+    }
+    None
+}
+
+#[test]
+fn test_march_matches_analytic_sphere() {
+    use crate::render::Intersectable;
+    use crate::scene::{Colour, Material};
+
+    let sphere = Sphere {
+        center: Point::new(0.3, -0.2, -5.0),
+        radius: 1.0,
+        material: Material::diffuse(Colour::black(), 0.18),
+    };
+    let scene = Scene {
+        elements: vec![Element::Sphere(sphere.clone())],
+        ..Scene::default()
+    };
+
+    for direction in [Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.2, 0.1, -1.0).normalize()] {
+        let ray = Ray {
+            origin: Point::zero(),
+            direction,
+        };
+        let expected = sphere.intersect(&ray, 0.0).unwrap();
+        let hit = march(&ray, &scene).unwrap();
+        assert!((hit.distance - expected).abs() < 1e-5, "{} vs {}", hit.distance, expected);
+
+        let normal = sphere.surface_normal(&hit.hit_point, &direction);
+        assert!(hit.surface_normal.approx_eq(&normal, 1e-4));
+    }
+
+    let miss = Ray {
+        origin: Point::zero(),
+        direction: Vector3::new(0.0, 1.0, 0.0),
+    };
+    assert!(march(&miss, &scene).is_none());
+}
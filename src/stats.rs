@@ -0,0 +1,85 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Work done during a render.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RenderStats {
+    pub primary_rays: u64,
+    pub shadow_rays: u64,
+    // Rays continuing from a surface: reflections, refractions and, when path
+    // tracing, diffuse bounces.
+    pub reflection_rays: u64,
+    // Ray-element intersection tests, whichever structure chose the elements.
+    pub intersection_tests: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Counter {
+    PrimaryRays,
+    ShadowRays,
+    ReflectionRays,
+    IntersectionTests,
+}
+
+// Running totals for a `RenderStats`, safe to add to from many threads.
+#[derive(Debug, Default)]
+pub struct StatCounters {
+    primary_rays: AtomicU64,
+    shadow_rays: AtomicU64,
+    reflection_rays: AtomicU64,
+    intersection_tests: AtomicU64,
+}
+
+impl StatCounters {
+    pub fn add(&self, counter: Counter, n: u64) {
+        let total = match counter {
+            Counter::PrimaryRays => &self.primary_rays,
+            Counter::ShadowRays => &self.shadow_rays,
+            Counter::ReflectionRays => &self.reflection_rays,
+            Counter::IntersectionTests => &self.intersection_tests,
+        };
+        total.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn totals(&self) -> RenderStats {
+        RenderStats {
+            primary_rays: self.primary_rays.load(Ordering::Relaxed),
+            shadow_rays: self.shadow_rays.load(Ordering::Relaxed),
+            reflection_rays: self.reflection_rays.load(Ordering::Relaxed),
+            intersection_tests: self.intersection_tests.load(Ordering::Relaxed),
+        }
+    }
+}
+
+thread_local! {
+    // The counters that work done on this thread is being gathered into.
+    static GATHERING: RefCell<Option<Arc<StatCounters>>> = const { RefCell::new(None) };
+}
+
+// Puts back the counters gathered into before `gather` when dropped, even if
+// its closure panics.
+struct Restore(Option<Arc<StatCounters>>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        GATHERING.with(|gathering| *gathering.borrow_mut() = self.0.take());
+    }
+}
+
+// Runs `f`, adding whatever it counts on this thread to `counters` as well,
+// so a render can be measured without attaching counters to its scene.
+pub fn gather<R>(counters: Option<&Arc<StatCounters>>, f: impl FnOnce() -> R) -> R {
+    let Some(counters) = counters else { return f() };
+    let _restore = Restore(GATHERING.with(|gathering| gathering.replace(Some(counters.clone()))));
+    f()
+}
+
+// Adds to the counters being gathered into on this thread, if any.
+pub fn count(counter: Counter, n: u64) {
+    GATHERING.with(|gathering| {
+        if let Some(ref counters) = *gathering.borrow() {
+            counters.add(counter, n);
+        }
+    });
+}
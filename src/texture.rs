@@ -0,0 +1,381 @@
+use crate::point::Point;
+use crate::scene::Colour;
+use crate::vector::Vector3;
+use image::{ImageError, Rgb32FImage, RgbaImage};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+// Surface coordinates in texture space. Both run over [0, 1) across a single
+// copy of a texture; values outside that range tile it.
+#[derive(Debug, Clone, Copy)]
+pub struct TextureCoords {
+    pub u: f32,
+    pub v: f32,
+}
+
+// How an image texture is sampled between the centres of its texels.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum TextureFilter {
+    // The texel the point falls in, which looks blocky when magnified.
+    #[default]
+    Nearest,
+    // A blend of the four texels whose centres surround the point.
+    Bilinear,
+}
+
+// When `srgb` is set the image's pixels are taken to be sRGB encoded, as most
+// images are, and are decoded to linear values when sampled.
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    pub image: RgbaImage,
+    pub srgb: bool,
+    pub filter: TextureFilter,
+}
+
+impl ImageTexture {
+    pub fn load(path: &str) -> Result<ImageTexture, ImageError> {
+        Ok(ImageTexture {
+            image: image::open(path)?.to_rgba8(),
+            srgb: false,
+            filter: TextureFilter::Nearest,
+        })
+    }
+
+    // Looks up the colour at `coords`, with v = 0 at the top of the image.
+    pub fn sample(&self, coords: &TextureCoords) -> Colour {
+        let (width, height) = self.image.dimensions();
+        let (u, v) = (coords.u.rem_euclid(1.0) * width as f32, coords.v.rem_euclid(1.0) * height as f32);
+        match self.filter {
+            TextureFilter::Nearest => self.texel((u as u32).min(width - 1), (v as u32).min(height - 1)),
+            TextureFilter::Bilinear => {
+                // Measure from texel centres, wrapping around the edges.
+                let (x, y) = (u - 0.5, v - 0.5);
+                let (fx, fy) = (x - x.floor(), y - y.floor());
+                let wrap = |i: f32, size: u32| (i as i64).rem_euclid(size as i64) as u32;
+                let (x0, x1) = (wrap(x.floor(), width), wrap(x.floor() + 1.0, width));
+                let (y0, y1) = (wrap(y.floor(), height), wrap(y.floor() + 1.0, height));
+                let top = self.texel(x0, y0).lerp(&self.texel(x1, y0), fx);
+                let bottom = self.texel(x0, y1).lerp(&self.texel(x1, y1), fx);
+                top.lerp(&bottom, fy)
+            }
+        }
+    }
+
+    fn texel(&self, x: u32, y: u32) -> Colour {
+        let pixel = self.image.get_pixel(x, y);
+        let colour = Colour {
+            red: pixel.0[0] as f32 / 255.0,
+            green: pixel.0[1] as f32 / 255.0,
+            blue: pixel.0[2] as f32 / 255.0,
+        };
+        if self.srgb {
+            colour.srgb_to_linear()
+        } else {
+            colour
+        }
+    }
+}
+
+// Alternating squares laid out in world space across the X/Z plane, so it
+// suits floors and other horizontal planes. `scale` is the number of squares
+// per unit distance.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct CheckerTexture {
+    pub colour_a: Colour,
+    pub colour_b: Colour,
+    pub scale: f64,
+}
+
+impl CheckerTexture {
+    pub fn sample(&self, hit_point: &Point) -> Colour {
+        let cell = (hit_point.x * self.scale).floor() + (hit_point.z * self.scale).floor();
+        if cell.rem_euclid(2.0) == 0.0 {
+            self.colour_a
+        } else {
+            self.colour_b
+        }
+    }
+}
+
+// An equirectangular (latitude/longitude) environment image, kept as floats
+// so HDR images retain their full range. `in_reflections` controls whether
+// secondary rays see it as well as rays from the camera.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "EnvMapDescription"))]
+pub struct EnvMap {
+    pub image: Rgb32FImage,
+    pub in_reflections: bool,
+}
+
+impl EnvMap {
+    pub fn load(path: &str) -> Result<EnvMap, ImageError> {
+        Ok(EnvMap {
+            image: image::open(path)?.to_rgb32f(),
+            in_reflections: true,
+        })
+    }
+
+    // Longitude maps to u, with -Z at the centre of the image, and latitude
+    // from straight up to straight down maps to v.
+    pub fn direction_to_coords(direction: &Vector3) -> TextureCoords {
+        let d = direction.normalize();
+        TextureCoords {
+            u: (0.5 + d.x.atan2(-d.z) / (2.0 * std::f64::consts::PI)) as f32,
+            v: (d.y.clamp(-1.0, 1.0).acos() / std::f64::consts::PI) as f32,
+        }
+    }
+
+    pub fn sample(&self, direction: &Vector3) -> Colour {
+        let coords = EnvMap::direction_to_coords(direction);
+        let (width, height) = self.image.dimensions();
+        let x = (coords.u.rem_euclid(1.0) * width as f32) as u32;
+        let y = (coords.v * height as f32) as u32;
+        let pixel = self.image.get_pixel(x.min(width - 1), y.min(height - 1));
+        Colour {
+            red: pixel.0[0],
+            green: pixel.0[1],
+            blue: pixel.0[2],
+        }
+    }
+}
+
+// A tangent-space normal map. Each texel's red, green and blue encode the
+// surface normal's components along the tangent, bitangent and geometric
+// normal, mapped from [-1, 1] to [0, 1]; a flat map is (0.5, 0.5, 1.0).
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "NormalMapDescription"))]
+pub struct NormalMap {
+    pub image: Rgb32FImage,
+}
+
+impl NormalMap {
+    pub fn load(path: &str) -> Result<NormalMap, ImageError> {
+        Ok(NormalMap {
+            image: image::open(path)?.to_rgb32f(),
+        })
+    }
+
+    // Nearest-texel lookup of the tangent-space normal, tiling like ImageTexture.
+    pub fn sample(&self, coords: &TextureCoords) -> Vector3 {
+        let (width, height) = self.image.dimensions();
+        let x = (coords.u.rem_euclid(1.0) * width as f32) as u32;
+        let y = (coords.v.rem_euclid(1.0) * height as f32) as u32;
+        let pixel = self.image.get_pixel(x.min(width - 1), y.min(height - 1));
+        Vector3::new(pixel.0[0] as f64 * 2.0 - 1.0,
+                     pixel.0[1] as f64 * 2.0 - 1.0,
+                     pixel.0[2] as f64 * 2.0 - 1.0)
+    }
+
+    // Tilts `normal` by the map's normal at `coords`, where `tangent` is the
+    // direction in which u increases across the surface.
+    pub fn perturb(&self, normal: &Vector3, tangent: &Vector3, coords: &TextureCoords) -> Vector3 {
+        let mapped = self.sample(coords);
+        let bitangent = normal.cross_prod(tangent);
+        (*tangent * mapped.x + bitangent * mapped.y + *normal * mapped.z).normalize()
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "TextureDescription"))]
+pub enum Texture {
+    Solid(Colour),
+    Image(ImageTexture),
+    Checker(CheckerTexture),
+}
+
+impl Texture {
+    pub fn colour_at(&self, hit_point: &Point, coords: &TextureCoords) -> Colour {
+        match *self {
+            Texture::Solid(colour) => colour,
+            Texture::Image(ref image) => image.sample(coords),
+            Texture::Checker(ref checker) => checker.sample(hit_point),
+        }
+    }
+}
+
+// In scene files a texture is a plain colour, `{ "image": "path" }` (with
+// optional `"srgb": true` and `"filter": "bilinear"`), or a checker
+// description.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureDescription {
+    Solid(Colour),
+    Image {
+        image: String,
+        #[serde(default)]
+        srgb: bool,
+        #[serde(default)]
+        filter: TextureFilter,
+    },
+    Checker(CheckerTexture),
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct EnvMapDescription {
+    image: String,
+    #[serde(default = "default_in_reflections")]
+    in_reflections: bool,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct NormalMapDescription {
+    image: String,
+}
+
+#[cfg(feature = "serde")]
+fn default_in_reflections() -> bool {
+    true
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<EnvMapDescription> for EnvMap {
+    type Error = String;
+
+    fn try_from(description: EnvMapDescription) -> Result<EnvMap, String> {
+        let image = &description.image;
+        EnvMap::load(image)
+            .map(|env| EnvMap {
+                in_reflections: description.in_reflections,
+                ..env
+            })
+            .map_err(|e| format!("could not load environment map {}: {}", image, e))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<NormalMapDescription> for NormalMap {
+    type Error = String;
+
+    fn try_from(description: NormalMapDescription) -> Result<NormalMap, String> {
+        let image = &description.image;
+        NormalMap::load(image)
+            .map_err(|e| format!("could not load normal map {}: {}", image, e))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<TextureDescription> for Texture {
+    type Error = String;
+
+    fn try_from(description: TextureDescription) -> Result<Texture, String> {
+        match description {
+            TextureDescription::Solid(colour) => Ok(Texture::Solid(colour)),
+            TextureDescription::Checker(checker) => Ok(Texture::Checker(checker)),
+            TextureDescription::Image { image, srgb, filter } => ImageTexture::load(&image)
+                .map(|texture| Texture::Image(ImageTexture { srgb, filter, ..texture }))
+                .map_err(|e| format!("could not load texture {}: {}", image, e)),
+        }
+    }
+}
+
+#[test]
+fn test_image_texture_tiles() {
+    use image::Rgba;
+
+    let mut image = RgbaImage::new(2, 1);
+    image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+    image.put_pixel(1, 0, Rgba([0, 0, 255, 255]));
+    let texture = Texture::Image(ImageTexture {
+        image,
+        srgb: false,
+        filter: TextureFilter::Nearest,
+    });
+
+    let hit_point = Point::zero();
+    let left = texture.colour_at(&hit_point, &TextureCoords { u: 0.25, v: 0.5 });
+    assert_eq!((left.red, left.blue), (1.0, 0.0));
+    let right = texture.colour_at(&hit_point, &TextureCoords { u: 0.75, v: 0.5 });
+    assert_eq!((right.red, right.blue), (0.0, 1.0));
+    let wrapped = texture.colour_at(&hit_point, &TextureCoords { u: -0.25, v: 1.5 });
+    assert_eq!((wrapped.red, wrapped.blue), (0.0, 1.0));
+}
+
+#[test]
+fn test_bilinear_filter_blends_neighbouring_texels() {
+    use image::Rgba;
+
+    let mut image = RgbaImage::new(2, 2);
+    image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+    image.put_pixel(1, 0, Rgba([0, 0, 255, 255]));
+    image.put_pixel(0, 1, Rgba([255, 0, 0, 255]));
+    image.put_pixel(1, 1, Rgba([0, 0, 255, 255]));
+    let texture = ImageTexture {
+        image,
+        srgb: false,
+        filter: TextureFilter::Bilinear,
+    };
+
+    // Texel centres give the texel's own colour.
+    let red = texture.sample(&TextureCoords { u: 0.25, v: 0.25 });
+    assert_eq!((red.red, red.green, red.blue), (1.0, 0.0, 0.0));
+    let blue = texture.sample(&TextureCoords { u: 0.75, v: 0.75 });
+    assert_eq!((blue.red, blue.green, blue.blue), (0.0, 0.0, 1.0));
+
+    // Halfway between the centres is an even mix, including across the edge
+    // where the texture wraps.
+    let mid = texture.sample(&TextureCoords { u: 0.5, v: 0.25 });
+    assert_eq!((mid.red, mid.blue), (0.5, 0.5));
+    let wrapped = texture.sample(&TextureCoords { u: 0.0, v: 0.25 });
+    assert_eq!((wrapped.red, wrapped.blue), (0.5, 0.5));
+}
+
+#[test]
+fn test_checker_texture_alternates_along_x() {
+    let white = Colour {
+        red: 1.0,
+        green: 1.0,
+        blue: 1.0,
+    };
+    let checker = CheckerTexture {
+        colour_a: white,
+        colour_b: Colour::black(),
+        scale: 2.0,
+    };
+
+    // Squares are half a unit wide at this scale.
+    let colours: Vec<f32> = (0..4)
+        .map(|i| checker.sample(&Point::new(-0.75 + 0.5 * i as f64, -2.0, 0.25)).red)
+        .collect();
+    assert_eq!(colours, vec![1.0, 0.0, 1.0, 0.0]);
+}
+
+#[test]
+fn test_env_map_opposite_directions_half_apart() {
+    let forward = EnvMap::direction_to_coords(&Vector3::new(0.0, 0.0, -1.0));
+    let backward = EnvMap::direction_to_coords(&Vector3::new(0.0, 0.0, 1.0));
+    assert_eq!(forward.u, 0.5);
+    assert_eq!((backward.u - forward.u).abs(), 0.5);
+    assert_eq!(forward.v, 0.5);
+
+    let up = EnvMap::direction_to_coords(&Vector3::new(0.0, 1.0, 0.0));
+    assert_eq!(up.v, 0.0);
+}
+
+#[test]
+fn test_flat_normal_map_keeps_normal() {
+    use image::Rgb;
+
+    let map = NormalMap {
+        image: Rgb32FImage::from_pixel(4, 4, Rgb([0.5, 0.5, 1.0])),
+    };
+    let normal = Vector3::new(0.0, 0.6, 0.8);
+    let tangent = Vector3::new(1.0, 0.0, 0.0);
+    let coords = TextureCoords { u: 0.3, v: 0.7 };
+    assert!(map.perturb(&normal, &tangent, &coords).approx_eq(&normal, 1e-12));
+
+    // A map leaning towards +u tilts the normal along the tangent.
+    let tilted = NormalMap {
+        image: Rgb32FImage::from_pixel(4, 4, Rgb([1.0, 0.5, 1.0])),
+    };
+    let n = tilted.perturb(&normal, &tangent, &coords);
+    assert!(n.approx_eq(&(tangent + normal).normalize(), 1e-12));
+}
@@ -1,6 +1,10 @@
-use std::ops::{Add, Sub, Mul, Neg};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use rand::Rng;
+use std::ops::{Add, Sub, Mul, Div, Neg};
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Vector3 {
     pub x: f64,
     pub y: f64,
@@ -8,9 +12,9 @@ pub struct Vector3 {
 }
 
 impl Vector3 {
-    pub fn zero() {
-        Vector3::from_one(0.0);
-        }
+    pub fn zero() -> Vector3 {
+        Vector3::from_one(0.0)
+    }
     
 
     pub fn from_one(v: f64) -> Vector3 {
@@ -23,9 +27,9 @@ impl Vector3 {
 
     pub fn new(x: f64, y: f64, z: f64) -> Vector3 {
         Vector3 {
-            x: x,
-            y: y,
-            z: z,
+            x,
+            y,
+            z,
         }
     }
 
@@ -50,6 +54,119 @@ impl Vector3 {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 
+    pub fn reflect(&self, normal: &Vector3) -> Vector3 {
+        *self - (*normal * (2.0 * self.dot_prod(normal)))
+    }
+
+    // Refracts this (normalized) direction through a surface whose normal faces
+    // the incoming ray, where eta is the ratio of refractive indices n1 / n2.
+    // Returns None on total internal reflection.
+    pub fn refract(&self, normal: &Vector3, eta: f64) -> Option<Vector3> {
+        let cos_i = -self.dot_prod(normal);
+        let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+        if k < 0.0 {
+            None
+        } else {
+            Some(*self * eta + *normal * (eta * cos_i - k.sqrt()))
+        }
+    }
+
+    // Component by index: 0 = x, 1 = y, 2 = z. Panics for any other index.
+    pub fn component(&self, i: usize) -> f64 {
+        match i {
+            0 => self.x,
+            1 => self.y,
+            2 => self.z,
+            _ => panic!("Vector3 component index out of range: {}", i),
+        }
+    }
+
+    pub fn min_component(&self) -> f64 {
+        self.x.min(self.y).min(self.z)
+    }
+
+    pub fn max_component(&self) -> f64 {
+        self.x.max(self.y).max(self.z)
+    }
+
+    // A random unit vector in the hemisphere around `normal`, distributed in
+    // proportion to the cosine of its angle from the normal.
+    pub fn random_in_hemisphere<R: Rng>(normal: &Vector3, rng: &mut R) -> Vector3 {
+        let (tangent, bitangent) = normal.tangents();
+
+        // Pick a point on the unit disk and project it up onto the hemisphere.
+        let r2: f64 = rng.gen();
+        let phi = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
+        let r = r2.sqrt();
+        tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + *normal * (1.0 - r2).sqrt()
+    }
+
+    // A uniformly distributed random point on the unit disk perpendicular to
+    // `normal`.
+    pub fn random_in_disk<R: Rng>(normal: &Vector3, rng: &mut R) -> Vector3 {
+        let (tangent, bitangent) = normal.tangents();
+        let r = rng.gen::<f64>().sqrt();
+        let phi = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
+        tangent * (r * phi.cos()) + bitangent * (r * phi.sin())
+    }
+
+    // Two unit vectors perpendicular to this (unit) vector and to each other.
+    fn tangents(&self) -> (Vector3, Vector3) {
+        let helper = if self.x.abs() > 0.9 {
+            Vector3::new(0.0, 1.0, 0.0)
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let tangent = helper.cross_prod(self).normalize();
+        (tangent, self.cross_prod(&tangent))
+    }
+
+    // Linear interpolation from this vector (t = 0) to `other` (t = 1).
+    pub fn lerp(&self, other: &Vector3, t: f64) -> Vector3 {
+        *self * (1.0 - t) + *other * t
+    }
+
+    // Spherical interpolation between two unit vectors, turning at a constant
+    // rate through the angle between them. Nearly parallel vectors fall back
+    // to a normalized lerp, where the two agree.
+    pub fn slerp(&self, other: &Vector3, t: f64) -> Vector3 {
+        let cos_angle = self.dot_prod(other).clamp(-1.0, 1.0);
+        if cos_angle > 0.9995 {
+            return self.lerp(other, t).normalize();
+        }
+        let angle = cos_angle.acos();
+        let sin_angle = angle.sin();
+        *self * (((1.0 - t) * angle).sin() / sin_angle) + *other * ((t * angle).sin() / sin_angle)
+    }
+
+    // Angle in radians between the two vectors, from 0 to π. Taken as 0 if
+    // either vector has zero length.
+    pub fn angle_between(&self, other: &Vector3) -> f64 {
+        let lengths = self.length() * other.length();
+        if lengths == 0.0 {
+            return 0.0;
+        }
+        // Rounding can take the cosine just outside [-1, 1].
+        (self.dot_prod(other) / lengths).clamp(-1.0, 1.0).acos()
+    }
+
+    // The component of this vector along `other`. Projecting onto a zero
+    // vector gives zero.
+    pub fn project_onto(&self, other: &Vector3) -> Vector3 {
+        let norm = other.norm();
+        if norm == 0.0 {
+            return Vector3::zero();
+        }
+        *other * (self.dot_prod(other) / norm)
+    }
+
+    // True when every component is within `eps` of the other vector's.
+    pub fn approx_eq(&self, other: &Vector3, eps: f64) -> bool {
+        (self.x - other.x).abs() <= eps &&
+        (self.y - other.y).abs() <= eps &&
+        (self.z - other.z).abs() <= eps
+    }
+
     pub fn cross_prod(&self, other: &Vector3) -> Vector3 {
         Vector3 {
             x: self.y * other.z - self.z * other.y,
@@ -59,6 +176,30 @@ impl Vector3 {
     }
 }
 
+impl From<[f64; 3]> for Vector3 {
+    fn from(a: [f64; 3]) -> Vector3 {
+        Vector3::new(a[0], a[1], a[2])
+    }
+}
+
+impl From<Vector3> for [f64; 3] {
+    fn from(v: Vector3) -> [f64; 3] {
+        [v.x, v.y, v.z]
+    }
+}
+
+impl From<(f64, f64, f64)> for Vector3 {
+    fn from((x, y, z): (f64, f64, f64)) -> Vector3 {
+        Vector3::new(x, y, z)
+    }
+}
+
+impl From<Vector3> for (f64, f64, f64) {
+    fn from(v: Vector3) -> (f64, f64, f64) {
+        (v.x, v.y, v.z)
+    }
+}
+
 impl Add for Vector3 {
     type Output = Vector3;
 
@@ -107,6 +248,17 @@ impl Mul<f64> for Vector3 {
     }
 }
 
+impl Div<f64> for Vector3 {
+    type Output = Vector3;
+
+    fn div(self, other: f64) -> Vector3 {
+        Vector3 {
+            x: self.x / other,
+            y: self.y / other,
+            z: self.z / other,
+        }
+    }
+}
 
 impl Neg for Vector3 {
     type Output = Vector3;
@@ -119,3 +271,145 @@ impl Neg for Vector3 {
         }
     }
 }
+
+
+#[test]
+fn test_zero_vector() {
+    assert_eq!(Vector3::zero().length(), 0.0);
+
+    let v = Vector3::new(1.5, -2.0, 3.25);
+    let sum = v + Vector3::zero();
+    assert_eq!((sum.x, sum.y, sum.z), (v.x, v.y, v.z));
+}
+
+#[test]
+fn test_divide_by_scalar() {
+    let v = Vector3::new(3.0, -6.0, 1.5) / 3.0;
+    assert_eq!((v.x, v.y, v.z), (1.0, -2.0, 0.5));
+}
+
+#[test]
+fn test_components() {
+    let v = Vector3::new(-4.0, 2.5, -0.5);
+    assert_eq!(v.max_component(), 2.5);
+    assert_eq!(v.min_component(), -4.0);
+    assert_eq!((v.component(0), v.component(1), v.component(2)), (-4.0, 2.5, -0.5));
+}
+
+#[test]
+fn test_vector_approx_eq() {
+    let v = Vector3::new(1.0, -2.0, 3.0);
+    let nudged = v + Vector3::new(1e-12, 0.0, -1e-12);
+    assert!(v.approx_eq(&nudged, 1e-9));
+    assert!(!v.approx_eq(&nudged, 1e-15));
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serde_round_trip() {
+    let v = Vector3::new(0.25, -1.5, 3.0);
+    let json = serde_json::to_string(&v).unwrap();
+    let back: Vector3 = serde_json::from_str(&json).unwrap();
+    assert_eq!((back.x, back.y, back.z), (v.x, v.y, v.z));
+}
+
+#[test]
+fn test_random_in_hemisphere() {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    let mut rng = StdRng::seed_from_u64(3);
+    let normal = Vector3::new(1.0, 2.0, -0.5).normalize();
+    let mut mean_cos = 0.0;
+    for _ in 0..1000 {
+        let v = Vector3::random_in_hemisphere(&normal, &mut rng);
+        assert!((v.length() - 1.0).abs() < 1e-9);
+        assert!(v.dot_prod(&normal) >= 0.0);
+        mean_cos += v.dot_prod(&normal) / 1000.0;
+    }
+    // Cosine weighting gives a mean cosine of 2/3.
+    assert!((mean_cos - 2.0 / 3.0).abs() < 0.05);
+}
+
+#[test]
+fn test_lerp_and_slerp() {
+    let a = Vector3::new(1.0, 0.0, 0.0);
+    let b = Vector3::new(0.0, 1.0, 0.0);
+
+    let mid = a.lerp(&b, 0.5);
+    assert_eq!((mid.x, mid.y, mid.z), (0.5, 0.5, 0.0));
+
+    // Halfway between orthogonal directions is at 45 degrees to each.
+    let half = 0.5f64.sqrt();
+    assert!(a.slerp(&b, 0.5).approx_eq(&Vector3::new(half, half, 0.0), 1e-12));
+    assert!(a.slerp(&b, 0.0).approx_eq(&a, 1e-12));
+    assert!(a.slerp(&b, 1.0).approx_eq(&b, 1e-12));
+
+    // Nearly parallel vectors still give a unit vector between them.
+    let c = Vector3::new(1.0, 1e-9, 0.0).normalize();
+    let v = a.slerp(&c, 0.5);
+    assert!((v.length() - 1.0).abs() < 1e-12 && v.y > 0.0 && v.y < c.y);
+}
+
+#[test]
+fn test_angle_between_and_project_onto() {
+    use std::f64::consts::PI;
+
+    let x = Vector3::new(2.0, 0.0, 0.0);
+    let y = Vector3::new(0.0, 3.0, 0.0);
+    assert!((x.angle_between(&y) - PI / 2.0).abs() < 1e-12);
+    assert_eq!(x.angle_between(&(x * 5.0)), 0.0);
+    assert!((x.angle_between(&-x) - PI).abs() < 1e-12);
+    assert_eq!(x.angle_between(&Vector3::zero()), 0.0);
+
+    let v = Vector3::new(3.0, -4.0, 5.0);
+    let p = v.project_onto(&y);
+    assert_eq!((p.x, p.y, p.z), (0.0, -4.0, 0.0));
+    assert!((v - p).dot_prod(&y).abs() < 1e-12);
+    let p = v.project_onto(&Vector3::zero());
+    assert_eq!((p.x, p.y, p.z), (0.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_reflect() {
+    let normal = Vector3::new(0.0, 1.0, 0.0);
+
+    let r = Vector3::new(1.0, -1.0, 0.0).reflect(&normal);
+    assert_eq!((r.x, r.y, r.z), (1.0, 1.0, 0.0));
+
+    let r = Vector3::new(0.0, -1.0, 0.0).reflect(&normal);
+    assert_eq!((r.x, r.y, r.z), (0.0, 1.0, 0.0));
+
+    let r = Vector3::new(1.0, 0.0, 0.0).reflect(&normal);
+    assert_eq!((r.x, r.y, r.z), (1.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_refract() {
+    let normal = Vector3::new(0.0, 1.0, 0.0);
+
+    // Matching indices leave the direction unchanged.
+    let incident = Vector3::new(1.0, -1.0, 0.0).normalize();
+    let r = incident.refract(&normal, 1.0).unwrap();
+    assert!((r.x - incident.x).abs() < 1e-12 && (r.y - incident.y).abs() < 1e-12);
+
+    // Entering glass at 45 degrees bends towards the normal: sin(t) = sin(45) / 1.5.
+    let r = incident.refract(&normal, 1.0 / 1.5).unwrap();
+    assert!((r.x - (0.5f64.sqrt() / 1.5)).abs() < 1e-12);
+    assert!((r.length() - 1.0).abs() < 1e-12);
+
+    // Leaving glass at 45 degrees is past the critical angle.
+    assert!(incident.refract(&normal, 1.5).is_none());
+}
+
+#[test]
+fn test_vector_array_and_tuple_conversion() {
+    let v = Vector3::from([1.0, -2.0, 3.5]);
+    assert_eq!((v.x, v.y, v.z), (1.0, -2.0, 3.5));
+    assert_eq!(<[f64; 3]>::from(v), [1.0, -2.0, 3.5]);
+
+    let v: Vector3 = (0.25, 8.0, -4.0).into();
+    assert_eq!((v.x, v.y, v.z), (0.25, 8.0, -4.0));
+    let t: (f64, f64, f64) = v.into();
+    assert_eq!(t, (0.25, 8.0, -4.0));
+}